@@ -0,0 +1,108 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use data_ferret::db::Database;
+use data_ferret::server::{DynamoServer, RespServer, RestServer, Server, TableHandles};
+use data_ferret::utils::Logger;
+
+const USAGE: &str = "Usage: ferret-server --db <path> [--addr <host:port>] [--http <host:port>] [--dynamo <host:port>] [--resp <host:port>]";
+
+fn main() {
+    let mut db_path = None;
+    let mut addr = "127.0.0.1:7878".to_string();
+    let mut http_addr = None;
+    let mut dynamo_addr = None;
+    let mut resp_addr = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => db_path = args.next().map(PathBuf::from),
+            "--addr" => addr = args.next().unwrap_or_else(|| exit_with_usage()),
+            "--http" => http_addr = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "--dynamo" => dynamo_addr = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "--resp" => resp_addr = Some(args.next().unwrap_or_else(|| exit_with_usage())),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => exit_with_usage(),
+        }
+    }
+    let db_path = db_path.unwrap_or_else(|| exit_with_usage());
+
+    let database = Database::open(db_path.clone()).unwrap_or_else(|e| {
+        Logger::log_error(&format!("Failed to open {}: {}", db_path.display(), e));
+        process::exit(1);
+    });
+    if let Some(pid) = database.stale_lock() {
+        Logger::log_info(&format!("Process {} exited without closing {}; took over its lock", pid, db_path.display()));
+    }
+    let database = Arc::new(Mutex::new(database));
+    let server = Server::bind(&addr, Arc::clone(&database)).unwrap_or_else(|e| exit_bind_failed(&addr, e));
+    let mut shutdown_handles = vec![server.shutdown_handle()];
+
+    // The REST and DynamoDB front-ends share one handle per table.
+    let tables = TableHandles::new();
+    let mut workers = Vec::new();
+    if let Some(http_addr) = http_addr {
+        let rest = RestServer::bind(&http_addr, Arc::clone(&database), Arc::clone(&tables)).unwrap_or_else(|e| exit_bind_failed(&http_addr, e));
+        shutdown_handles.push(rest.shutdown_handle());
+        Logger::log_info(&format!("Serving HTTP on {}", rest.local_addr().expect("Failed to get local address")));
+        workers.push(thread::spawn(move || rest.run()));
+    }
+    if let Some(dynamo_addr) = dynamo_addr {
+        let dynamo = DynamoServer::bind(&dynamo_addr, Arc::clone(&database), Arc::clone(&tables)).unwrap_or_else(|e| exit_bind_failed(&dynamo_addr, e));
+        shutdown_handles.push(dynamo.shutdown_handle());
+        Logger::log_info(&format!("Serving the DynamoDB API on {}", dynamo.local_addr().expect("Failed to get local address")));
+        workers.push(thread::spawn(move || dynamo.run()));
+    }
+    if let Some(resp_addr) = resp_addr {
+        let resp = RespServer::bind(&resp_addr, Arc::clone(&database)).unwrap_or_else(|e| exit_bind_failed(&resp_addr, e));
+        shutdown_handles.push(resp.shutdown_handle());
+        Logger::log_info(&format!("Serving RESP on {}", resp.local_addr().expect("Failed to get local address")));
+        workers.push(thread::spawn(move || resp.run()));
+    }
+
+    let terminate = Arc::new(AtomicBool::new(false));
+    for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&terminate)).expect("Failed to register signal handler");
+    }
+    thread::spawn(move || {
+        while !terminate.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        Logger::log_info("Shutting down");
+        for handle in shutdown_handles {
+            handle.shutdown();
+        }
+    });
+
+    Logger::log_info(&format!("Listening on {}", server.local_addr().expect("Failed to get local address")));
+    if let Err(e) = server.run() {
+        Logger::log_error(&format!("Server failed: {}", e));
+        process::exit(1);
+    }
+    for worker in workers {
+        if let Ok(Err(e)) = worker.join() {
+            Logger::log_error(&format!("Server failed: {}", e));
+            process::exit(1);
+        }
+    }
+}
+
+fn exit_bind_failed(addr: &str, error: io::Error) -> ! {
+    Logger::log_error(&format!("Failed to bind {}: {}", addr, error));
+    process::exit(1);
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
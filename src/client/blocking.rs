@@ -0,0 +1,211 @@
+use crate::db::{Data, Filter};
+use crate::server::{error_kind, Request, Response};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub addr: String,
+    /// Maximum number of idle connections kept for reuse.
+    pub pool_size: usize,
+    pub connect_timeout: Duration,
+    /// Applies to sending a request and to waiting for its response.
+    pub request_timeout: Duration,
+    /// How many times a request is retried on a fresh connection after a
+    /// network failure. Errors reported by the server are never retried.
+    pub max_retries: u32,
+    /// Delay before the first retry; doubled for every further attempt.
+    pub retry_backoff: Duration,
+}
+
+impl ClientConfig {
+    pub fn new(addr: &str) -> Self {
+        ClientConfig {
+            addr: addr.to_string(),
+            pool_size: 8,
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(30),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+/// A blocking client for `ferret-server` with the same operations as `Database`.
+///
+/// Connections are pooled, so a `Client` can be shared between threads behind an
+/// `Arc`. Every operation is a keyed put or delete, which makes retrying after a
+/// dropped connection safe; the one visible difference is that a retried
+/// `delete` may report `NotFound` if the first attempt had already succeeded.
+#[derive(Debug)]
+pub struct Client {
+    config: ClientConfig,
+    idle: Mutex<Vec<Connection>>,
+}
+
+#[derive(Debug)]
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        Client::with_config(ClientConfig::new(addr))
+    }
+
+    /// Creates a client and checks that the server is reachable.
+    pub fn with_config(config: ClientConfig) -> io::Result<Self> {
+        let client = Client { config, idle: Mutex::new(Vec::new()) };
+        let connection = client.open()?;
+        client.release(connection);
+        Ok(client)
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    /// Fetches an item, or `None` if it doesn't exist. Unlike `Database::get`, a
+    /// missing item isn't an error.
+    pub fn get(&self, partition_key: String, sort_key: String) -> io::Result<Option<Data>> {
+        match self.call(Request::Get { partition_key, sort_key })? {
+            Response::Item { data } => Ok(data),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    pub fn insert(&self, partition_key: String, sort_key: String, value: String) -> io::Result<()> {
+        self.expect_ok(Request::Insert { partition_key, sort_key, value })
+    }
+
+    pub fn delete(&self, partition_key: String, sort_key: String) -> io::Result<()> {
+        self.expect_ok(Request::Delete { partition_key, sort_key })
+    }
+
+    pub fn batch(&self, data: Vec<Data>) -> io::Result<()> {
+        self.expect_ok(Request::Batch { items: data })
+    }
+
+    pub fn query(&self, partition_key: &str, filter: Option<&Filter>, limit: Option<usize>) -> io::Result<Vec<Data>> {
+        let request = Request::Query { partition_key: partition_key.to_string(), filter: filter.cloned(), limit };
+        match self.call(request)? {
+            Response::Items { items } => Ok(items),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Sends a single request and returns the server's response. Error responses
+    /// are converted into `io::Error`s with the server's error kind.
+    pub fn call(&self, request: Request) -> io::Result<Response> {
+        match self.pipeline(vec![request])?.remove(0) {
+            Response::Error { kind, message } => Err(io::Error::new(error_kind(&kind), message)),
+            response => Ok(response),
+        }
+    }
+
+    /// Sends several requests on one connection without waiting for each response.
+    /// Responses are returned in request order; error responses are left as-is.
+    pub fn pipeline(&self, requests: Vec<Request>) -> io::Result<Vec<Response>> {
+        let mut attempt = 0;
+        loop {
+            let result = self.acquire().and_then(|mut connection| {
+                let responses = connection.exchange(&requests)?;
+                self.release(connection);
+                Ok(responses)
+            });
+
+            match result {
+                Ok(responses) => return Ok(responses),
+                Err(e) if attempt < self.config.max_retries && is_retryable(&e) => {
+                    thread::sleep(self.config.retry_backoff * 2u32.pow(attempt));
+                    attempt += 1;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn expect_ok(&self, request: Request) -> io::Result<()> {
+        match self.call(request)? {
+            Response::Ok => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    fn acquire(&self) -> io::Result<Connection> {
+        match self.idle.lock().unwrap().pop() {
+            Some(connection) => Ok(connection),
+            None => self.open(),
+        }
+    }
+
+    fn release(&self, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.config.pool_size {
+            idle.push(connection);
+        }
+    }
+
+    fn open(&self) -> io::Result<Connection> {
+        let addrs: Vec<SocketAddr> = self.config.addr.to_socket_addrs()?.collect();
+        let mut last_error = io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, self.config.connect_timeout) {
+                Ok(stream) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_read_timeout(Some(self.config.request_timeout))?;
+                    stream.set_write_timeout(Some(self.config.request_timeout))?;
+                    return Ok(Connection {
+                        reader: BufReader::new(stream.try_clone()?),
+                        writer: BufWriter::new(stream),
+                    });
+                },
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl Connection {
+    fn exchange(&mut self, requests: &[Request]) -> io::Result<Vec<Response>> {
+        for request in requests {
+            serde_json::to_writer(&mut self.writer, request)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()?;
+
+        let mut responses = Vec::with_capacity(requests.len());
+        let mut line = String::new();
+        for _ in requests {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "server closed the connection"));
+            }
+            responses.push(serde_json::from_str(&line)?);
+        }
+        Ok(responses)
+    }
+}
+
+/// Network failures are retried on a new connection; a timed out request is not,
+/// since the server may still be working on it.
+fn is_retryable(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+fn unexpected(response: Response) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected response {:?}", response))
+}
@@ -0,0 +1,13 @@
+//! Client library for `ferret-server`'s line protocol.
+//!
+//! [`Client`] blocks the calling thread; [`AsyncClient`] returns futures. Both
+//! offer the `get`, `insert`, `delete`, `batch` and `query` operations of
+//! `Database` with the same arguments, so code can move between an embedded
+//! database and a remote one with little change. One difference: `get` returns
+//! `Ok(None)` for a missing item, where `Database::get` fails with `NotFound`.
+
+mod blocking;
+mod nonblocking;
+
+pub use self::blocking::{Client, ClientConfig};
+pub use self::nonblocking::{AsyncClient, ResponseFuture};
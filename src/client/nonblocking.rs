@@ -0,0 +1,150 @@
+use super::blocking::{Client, ClientConfig};
+use crate::db::{Data, Filter};
+use crate::server::{Request, Response};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce(&Client) + Send>;
+
+/// An async client that does not depend on any particular runtime.
+///
+/// Requests are handed to a fixed set of worker threads, one per pooled
+/// connection, and each call returns a future that completes when the worker
+/// receives the response. The futures can be awaited from any executor.
+pub struct AsyncClient {
+    client: Arc<Client>,
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// The result of an `AsyncClient` call.
+pub struct ResponseFuture<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+struct Slot<T> {
+    result: Option<T>,
+    waker: Option<Waker>,
+}
+
+impl AsyncClient {
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        AsyncClient::with_config(ClientConfig::new(addr))
+    }
+
+    pub fn with_config(config: ClientConfig) -> io::Result<Self> {
+        let worker_count = config.pool_size.max(1);
+        let client = Arc::new(Client::with_config(config)?);
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..worker_count)
+            .map(|_| {
+                let client = Arc::clone(&client);
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || run_worker(&client, &receiver))
+            })
+            .collect();
+        Ok(AsyncClient { client, jobs: Some(jobs), workers })
+    }
+
+    /// The blocking client the workers use, for callers that need both flavors.
+    pub fn blocking(&self) -> &Client {
+        &self.client
+    }
+
+    /// Fetches an item, or `None` if it doesn't exist, like [`Client::get`].
+    pub fn get(&self, partition_key: String, sort_key: String) -> ResponseFuture<io::Result<Option<Data>>> {
+        self.submit(move |client| client.get(partition_key, sort_key))
+    }
+
+    pub fn insert(&self, partition_key: String, sort_key: String, value: String) -> ResponseFuture<io::Result<()>> {
+        self.submit(move |client| client.insert(partition_key, sort_key, value))
+    }
+
+    pub fn delete(&self, partition_key: String, sort_key: String) -> ResponseFuture<io::Result<()>> {
+        self.submit(move |client| client.delete(partition_key, sort_key))
+    }
+
+    pub fn batch(&self, data: Vec<Data>) -> ResponseFuture<io::Result<()>> {
+        self.submit(move |client| client.batch(data))
+    }
+
+    pub fn query(&self, partition_key: &str, filter: Option<&Filter>, limit: Option<usize>) -> ResponseFuture<io::Result<Vec<Data>>> {
+        let partition_key = partition_key.to_string();
+        let filter = filter.cloned();
+        self.submit(move |client| client.query(&partition_key, filter.as_ref(), limit))
+    }
+
+    pub fn call(&self, request: Request) -> ResponseFuture<io::Result<Response>> {
+        self.submit(move |client| client.call(request))
+    }
+
+    pub fn pipeline(&self, requests: Vec<Request>) -> ResponseFuture<io::Result<Vec<Response>>> {
+        self.submit(move |client| client.pipeline(requests))
+    }
+
+    fn submit<T, F>(&self, operation: F) -> ResponseFuture<io::Result<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Client) -> io::Result<T> + Send + 'static,
+    {
+        let slot = Arc::new(Mutex::new(Slot { result: None, waker: None }));
+        let future = ResponseFuture { slot: Arc::clone(&slot) };
+        let job: Job = Box::new(move |client| complete(&slot, operation(client)));
+        let sender = self.jobs.as_ref().expect("jobs channel is open until drop");
+        if let Err(mpsc::SendError(job)) = sender.send(job) {
+            // Every worker has exited, which only happens if one panicked.
+            drop(job);
+            complete(&future.slot, Err(io::Error::other("client workers have stopped")));
+        }
+        future
+    }
+}
+
+impl Drop for AsyncClient {
+    /// Waits for requests that were already submitted to finish.
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<T> Future for ResponseFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut slot = self.slot.lock().unwrap();
+        match slot.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+fn run_worker(client: &Client, receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        job(client);
+    }
+}
+
+fn complete<T>(slot: &Mutex<Slot<T>>, result: T) {
+    let mut slot = slot.lock().unwrap();
+    slot.result = Some(result);
+    if let Some(waker) = slot.waker.take() {
+        waker.wake();
+    }
+}
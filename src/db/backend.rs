@@ -0,0 +1,53 @@
+use super::persistence::Data;
+use std::io;
+use std::ops::Bound;
+
+/// Where a `Database` keeps its items.
+///
+/// `Persistence`, the file-per-key layout, is the default. A `Database` opened on
+/// another backend with `Database::with_backend` has the same item API (gets,
+/// writes, batches, queries and scans, with the same caching) but none of the
+/// features tied to the directory layout, such as tables, group commit,
+/// compression, encryption, stats and fsck.
+///
+/// Implementations are shared between threads by parallel scans.
+pub trait StorageBackend: Send + Sync {
+    /// Streams items, reading them lazily where the backend can.
+    type Iter: Iterator<Item = io::Result<Data>> + Send + 'static;
+
+    fn get(&self, partition_key: &str, sort_key: &str) -> io::Result<Option<Data>>;
+
+    /// Whether the item may exist, answered without reading storage. `false` means
+    /// it certainly doesn't, and lets `Database::get` skip the read.
+    fn may_contain(&self, _partition_key: &str, _sort_key: &str) -> bool {
+        true
+    }
+
+    /// Looks up several items of one partition, in the order of `sort_keys`.
+    fn get_batch(&self, partition_key: &str, sort_keys: &[String]) -> io::Result<Vec<Option<Data>>> {
+        sort_keys.iter().map(|sort_key| self.get(partition_key, sort_key)).collect()
+    }
+
+    /// Stores `data`, replacing any item with the same keys.
+    fn put(&self, data: &Data) -> io::Result<()>;
+
+    /// Removes an item. Fails with `NotFound` if it doesn't exist.
+    fn delete(&self, partition_key: &str, sort_key: &str) -> io::Result<()>;
+
+    /// The items of one partition whose sort keys fall within `from` and `to`, in
+    /// no particular order. A missing partition yields nothing.
+    fn range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<Self::Iter>;
+
+    /// Every item of the partitions assigned to `segment` out of `total_segments`
+    /// by `segment_of`, in no particular order.
+    fn scan(&self, segment: usize, total_segments: usize) -> io::Result<Self::Iter>;
+
+    fn list_partitions(&self) -> io::Result<Vec<String>>;
+
+    /// Applies a group of inserts and deletes as one durable write. Deleting an
+    /// item that doesn't exist is not an error here.
+    fn write_batch(&self, batch: &[Data]) -> io::Result<()>;
+
+    /// Pushes any writes the backend buffers to storage.
+    fn flush(&self) -> io::Result<()>;
+}
@@ -0,0 +1,334 @@
+//! Per-partition Bloom filters over sort keys, so looking up an item that doesn't
+//! exist can skip the disk.
+//!
+//! Filters are kept in memory and saved to `.bloom/<partition key>`:
+//!
+//! ```text
+//! "FBLM" u32 checksum u32 hashes u64 capacity u64 count u64 bits [u64] words
+//! ```
+//!
+//! The checksum is the CRC-32 of everything after it, and all integers are
+//! little-endian. A saved filter must never miss a key that has an item file, so
+//! the first write to a partition removes its file before the item is written,
+//! and the filter is saved again by `flush`, `compact` or when the keyspace is
+//! closed. A partition without a saved filter gets one built from the names of its
+//! item files.
+
+use super::persistence::is_reserved;
+use super::record::checksum;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
+
+pub(crate) const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+const FILTER_DIR: &str = ".bloom";
+const MAGIC: &[u8; 4] = b"FBLM";
+const HEADER_LEN: usize = MAGIC.len() + 4 + 4 + 8 + 8 + 8;
+/// Filters are sized for at least this many keys, so small partitions can grow a
+/// little before their filter has to be rebuilt.
+const MIN_CAPACITY: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+struct BloomFilter {
+    words: Vec<u64>,
+    hashes: u32,
+    /// The number of keys the filter was sized for.
+    capacity: u64,
+    /// Keys inserted so far, counting repeats.
+    count: u64,
+}
+
+impl BloomFilter {
+    fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(MIN_CAPACITY);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(capacity as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = ((bits as f64 / capacity as f64) * ln2).round().max(1.0) as u32;
+        BloomFilter { words: vec![0; bits.div_ceil(64) as usize], hashes, capacity, count: 0 }
+    }
+
+    fn bits(&self) -> u64 {
+        self.words.len() as u64 * 64
+    }
+
+    /// The bits of `key`, by double hashing two halves of a stable 64-bit hash.
+    fn positions(&self, key: &str) -> impl Iterator<Item = u64> {
+        let hash = fnv1a(key.as_bytes());
+        let (h1, h2) = (hash, mix(hash) | 1);
+        let bits = self.bits();
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+
+    fn insert(&mut self, key: &str) {
+        for bit in self.positions(key).collect::<Vec<_>>() {
+            self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.count += 1;
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.positions(key).all(|bit| self.words[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::with_capacity(HEADER_LEN - 8 + self.words.len() * 8);
+        body.extend_from_slice(&self.hashes.to_le_bytes());
+        body.extend_from_slice(&self.capacity.to_le_bytes());
+        body.extend_from_slice(&self.count.to_le_bytes());
+        body.extend_from_slice(&self.bits().to_le_bytes());
+        for word in &self.words {
+            body.extend_from_slice(&word.to_le_bytes());
+        }
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&checksum(&body).to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes
+    }
+
+    /// `None` for a file that is truncated or fails its checksum.
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let rest = bytes.strip_prefix(MAGIC)?;
+        let (stored, body) = rest.split_first_chunk::<4>()?;
+        if checksum(body) != u32::from_le_bytes(*stored) {
+            return None;
+        }
+        let (hashes, body) = body.split_first_chunk::<4>()?;
+        let (capacity, body) = body.split_first_chunk::<8>()?;
+        let (count, body) = body.split_first_chunk::<8>()?;
+        let (bits, body) = body.split_first_chunk::<8>()?;
+        let bits = u64::from_le_bytes(*bits);
+        if bits == 0 || bits % 64 != 0 || body.len() as u64 != bits / 8 {
+            return None;
+        }
+        Some(BloomFilter {
+            words: body.chunks_exact(8).map(|word| u64::from_le_bytes(word.try_into().unwrap())).collect(),
+            hashes: u32::from_le_bytes(*hashes),
+            capacity: u64::from_le_bytes(*capacity),
+            count: u64::from_le_bytes(*count),
+        })
+    }
+}
+
+/// FNV-1a, which unlike `DefaultHasher` is the same in every build, as saved
+/// filters need.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The SplitMix64 finalizer, deriving a second hash from the first.
+fn mix(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+#[derive(Debug)]
+struct Entry {
+    filter: BloomFilter,
+    /// Whether `.bloom/<partition key>` holds this filter.
+    saved: bool,
+}
+
+/// The Bloom filters of one keyspace, shared by every `Persistence` opened on it
+/// in this process so that all of them see each other's writes.
+#[derive(Debug)]
+pub(crate) struct BloomFilters {
+    root: PathBuf,
+    false_positive_rate: Mutex<f64>,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Held shared by writers from adding a key until its item file is written, and
+    /// while an item file is removed. Held exclusively while filters are rebuilt
+    /// from the files, and by `block_writes` callers.
+    writes: RwLock<()>,
+}
+
+impl BloomFilters {
+    /// The filters of the keyspace at `root`, shared with the handles already open
+    /// on it.
+    pub fn open(root: &Path, false_positive_rate: f64) -> Arc<BloomFilters> {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let mut open = open_filters();
+        open.retain(|_, filters| filters.strong_count() > 0);
+        if let Some(filters) = open.get(&root).and_then(Weak::upgrade) {
+            return filters;
+        }
+        let filters = Arc::new(BloomFilters {
+            root: root.clone(),
+            false_positive_rate: Mutex::new(false_positive_rate),
+            entries: Mutex::new(HashMap::new()),
+            writes: RwLock::new(()),
+        });
+        open.insert(root, Arc::downgrade(&filters));
+        filters
+    }
+
+    /// Stops sharing the filters of the keyspace at `root`, which is being
+    /// removed, with handles opened there later.
+    pub fn forget(root: &Path) {
+        open_filters().remove(root);
+    }
+
+    pub fn false_positive_rate(&self) -> f64 {
+        *self.false_positive_rate.lock().unwrap()
+    }
+
+    /// Filters built from now on are sized for `rate`; `rebuild` resizes the rest.
+    pub fn set_false_positive_rate(&self, rate: f64) {
+        *self.false_positive_rate.lock().unwrap() = rate;
+    }
+
+    /// Whether `(partition_key, sort_key)` may have an item file. Errors loading or
+    /// building the filter are answered with `true`, leaving them to the read.
+    pub fn may_contain(&self, partition_key: &str, sort_key: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        match self.entry(&mut entries, partition_key) {
+            Ok(entry) => entry.filter.contains(sort_key),
+            Err(_) => true,
+        }
+    }
+
+    /// Adds a key about to be written. Hold the returned guard until its item
+    /// file is written.
+    pub fn add(&self, partition_key: &str, sort_key: &str) -> io::Result<RwLockReadGuard<'_, ()>> {
+        loop {
+            let guard = self.writes.read().unwrap();
+            {
+                let mut entries = self.entries.lock().unwrap();
+                let entry = self.entry(&mut entries, partition_key)?;
+                if entry.filter.count < entry.filter.capacity {
+                    if entry.saved {
+                        remove_file(&self.filter_path(partition_key))?;
+                        entry.saved = false;
+                    }
+                    entry.filter.insert(sort_key);
+                    return Ok(guard);
+                }
+            }
+            drop(guard);
+
+            // A full filter is rebuilt larger from the item files, once the keys
+            // still being written have their files.
+            let _exclusive = self.writes.write().unwrap();
+            remove_file(&self.filter_path(partition_key))?;
+            let filter = self.build(partition_key)?;
+            self.entries.lock().unwrap().insert(partition_key.to_string(), Entry { filter, saved: false });
+        }
+    }
+
+    /// Hold the returned guard while removing an item file.
+    pub fn removing(&self) -> RwLockReadGuard<'_, ()> {
+        self.writes.read().unwrap()
+    }
+
+    /// Waits for the item files being written or removed, and keeps new writes
+    /// and removals from starting until the guard is dropped.
+    pub fn block_writes(&self) -> RwLockWriteGuard<'_, ()> {
+        self.writes.write().unwrap()
+    }
+
+    /// Saves the filters changed since they were last saved. Filters of empty
+    /// partitions aren't saved.
+    pub fn save(&self) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        for (partition_key, entry) in entries.iter_mut().filter(|(_, entry)| !entry.saved && entry.filter.count > 0) {
+            let dir = self.root.join(FILTER_DIR);
+            // Not `create_dir_all`: a keyspace removed while open stays removed.
+            match fs::create_dir(&dir) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                _ => {},
+            }
+            let staging = dir.join(format!(".{}.tmp", partition_key));
+            fs::write(&staging, entry.filter.encode())?;
+            fs::rename(staging, self.filter_path(partition_key))?;
+            entry.saved = true;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds every filter from the item files, dropping keys since deleted, and
+    /// saves them. Filters of partitions that no longer exist are removed.
+    pub fn rebuild(&self) -> io::Result<()> {
+        let _guard = self.writes.write().unwrap();
+        let mut entries = self.entries.lock().unwrap();
+        entries.clear();
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let partition_key = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() && !is_reserved(&partition_key) {
+                let filter = self.build(&partition_key)?;
+                entries.insert(partition_key, Entry { filter, saved: false });
+            }
+        }
+        match fs::read_dir(self.root.join(FILTER_DIR)) {
+            Ok(files) => {
+                for file in files {
+                    let path = file?.path();
+                    let partition_key = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                    if !entries.contains_key(&partition_key) {
+                        remove_file(&path)?;
+                    }
+                }
+            },
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => {},
+        }
+        drop(entries);
+        self.save()
+    }
+
+    /// The filter of a partition, loaded from its file or built from its items.
+    fn entry<'a>(&self, entries: &'a mut HashMap<String, Entry>, partition_key: &str) -> io::Result<&'a mut Entry> {
+        if !entries.contains_key(partition_key) {
+            let saved = match fs::read(self.filter_path(partition_key)) {
+                Ok(bytes) => BloomFilter::decode(&bytes),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            let entry = match saved {
+                Some(filter) => Entry { filter, saved: true },
+                None => Entry { filter: self.build(partition_key)?, saved: false },
+            };
+            entries.insert(partition_key.to_string(), entry);
+        }
+        Ok(entries.get_mut(partition_key).unwrap())
+    }
+
+    /// A filter of the item files in a partition, with room for as many again.
+    fn build(&self, partition_key: &str) -> io::Result<BloomFilter> {
+        let sort_keys: Vec<String> = match fs::read_dir(self.root.join(partition_key)) {
+            Ok(entries) => entries.map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned())).collect::<io::Result<_>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let mut filter = BloomFilter::new(sort_keys.len() as u64 * 2, self.false_positive_rate());
+        for sort_key in &sort_keys {
+            filter.insert(sort_key);
+        }
+        Ok(filter)
+    }
+
+    fn filter_path(&self, partition_key: &str) -> PathBuf {
+        self.root.join(FILTER_DIR).join(partition_key)
+    }
+}
+
+impl Drop for BloomFilters {
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
+
+/// The filters of the keyspaces open in this process, by canonical path.
+fn open_filters() -> MutexGuard<'static, HashMap<PathBuf, Weak<BloomFilters>>> {
+    static OPEN: OnceLock<Mutex<HashMap<PathBuf, Weak<BloomFilters>>>> = OnceLock::new();
+    OPEN.get_or_init(Default::default).lock().unwrap()
+}
+
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -1,37 +1,337 @@
 use super::store::Store;
-use super::persistence::{Persistence, Data, OperationType};
-use std::path::PathBuf;
+use super::backend::StorageBackend;
+use super::persistence::{Persistence, Data, OperationType, Stats};
+use super::scan::{self, Filter, segment_of};
+use super::group_commit::{GroupCommit, GroupCommitConfig};
+use super::table::{self, Catalog, KeySchema, TableDescription, TableSettings, TimeToLive};
+use super::index::Index;
+use super::fsck::check_keyspace;
+use super::migrate::CorruptFile;
+use super::record::Compression;
+use super::encryption::{Keyring, Reencryption};
+use super::lock::DirLock;
+use std::collections::{HashMap, HashSet};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
 use std::io;
-use std::sync::Mutex;
+use std::slice;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
+/// The outcome of `Database::batch_get`: the items that were found and the
+/// `(partition_key, sort_key)` pairs that don't exist.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchGetResult {
+    pub found: Vec<Data>,
+    pub missing: Vec<(String, String)>,
+}
+
+/// A keyspace backed by storage, with a cache of the items it has read or written.
+///
+/// `Database` on its own is stored in the file-per-key layout of `Persistence`;
+/// `Database::with_backend` opens one on any other `StorageBackend`.
 #[derive(Debug)]
-pub struct Database {
+pub struct Database<B: StorageBackend = Persistence> {
     store: Store,
-    persistence: Persistence,
+    backend: B,
     lock: Mutex<()>,
+    group_commit: Option<Arc<GroupCommit>>,
+    /// The time to live and indexes of a table, as it was opened with.
+    time_to_live: Option<TimeToLive>,
+    indexes: Vec<Index>,
 }
 
 impl Database {
+    /// Opens the database at `path`, panicking if it can't. See `open`.
     pub fn new(path: PathBuf) -> Self {
+        Database::with_backend(Persistence::new(path))
+    }
+
+    /// Opens the database at `path` for reading and writing, creating it if needed.
+    /// Fails with a `LockError` while another process has it open for writing.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        Ok(Database::with_backend(Persistence::open(path)?))
+    }
+
+    /// Opens an existing database for reading only. It doesn't take the lock, so it
+    /// can be opened while another process writes to it. It never creates or
+    /// changes a file: writes, table changes, compaction, repairs and
+    /// re-encryption fail with a `ReadOnlyError`. Items it has cached don't reflect
+    /// the other process's later writes.
+    pub fn open_read_only(path: PathBuf) -> io::Result<Self> {
+        Ok(Database::with_backend(Persistence::open_read_only(path)?))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.backend.is_read_only()
+    }
+
+    /// The id of a process that had this database open for writing and exited
+    /// without closing it, if opening it found one.
+    pub fn stale_lock(&self) -> Option<u32> {
+        self.backend.stale_lock()
+    }
+
+    /// Opens a database whose writes go through a group commit: each `insert`,
+    /// `delete` and `batch` returns once its mutations are synced to disk, sharing
+    /// the fsync with writes issued concurrently through `group_commit()`. Panics
+    /// if it can't be opened; see `open_with_group_commit`.
+    pub fn with_group_commit(path: PathBuf, config: GroupCommitConfig) -> Self {
+        let display = path.display().to_string();
+        Database::open_with_group_commit(path, config).unwrap_or_else(|e| panic!("Failed to open {}: {}", display, e))
+    }
+
+    /// Like `with_group_commit`, but fails with a `LockError` while another process
+    /// has the database open for writing.
+    pub fn open_with_group_commit(path: PathBuf, config: GroupCommitConfig) -> io::Result<Self> {
+        let group_commit = GroupCommit::open(path.clone(), config)?;
+        Ok(Database {
+            group_commit: Some(Arc::new(group_commit)),
+            ..Database::open(path)?
+        })
+    }
+
+    pub fn create_table(&self, name: &str, key_schema: KeySchema) -> io::Result<TableDescription> {
+        self.create_table_with_settings(name, key_schema, TableSettings::default())
+    }
+
+    /// Creates a table with a time to live or indexes.
+    pub fn create_table_with_settings(&self, name: &str, key_schema: KeySchema, settings: TableSettings) -> io::Result<TableDescription> {
+        self.backend.check_writable()?;
+        self.catalog().create(name, key_schema, settings)
+    }
+
+    /// Sets or removes the time to live of a table. Handles opened on the table
+    /// before keep the setting they were opened with.
+    pub fn set_table_time_to_live(&self, name: &str, time_to_live: Option<TimeToLive>) -> io::Result<TableDescription> {
+        self.backend.check_writable()?;
+        self.catalog().set_time_to_live(name, time_to_live)
+    }
+
+    pub fn list_tables(&self) -> io::Result<Vec<String>> {
+        self.catalog().list()
+    }
+
+    pub fn describe_table(&self, name: &str) -> io::Result<TableDescription> {
+        self.catalog().describe(name)
+    }
+
+    /// Drops a table with all of its data. Fails with a `LockError` while another
+    /// process has the table open; writes through handles this process has open
+    /// on it fail with `NotFound` from then on.
+    pub fn drop_table(&self, name: &str) -> io::Result<()> {
+        self.backend.check_writable()?;
+        self.describe_table(name)?;
+        let path = self.catalog().table_path(name);
+        let _lock = DirLock::acquire(&path)?;
+        Persistence::retire(&path)?;
+        self.catalog().drop_table(name)
+    }
+
+    /// Opens a named table as its own `Database`, isolated from the root keyspace
+    /// and from other tables. It inherits this database's group commit settings,
+    /// keyring and read-only mode, and applies the table's time to live and
+    /// indexes.
+    pub fn table(&self, name: &str) -> io::Result<Database> {
+        let description = self.describe_table(name)?;
+        let path = self.catalog().table_path(name);
+        let mut table = match &self.group_commit {
+            _ if self.is_read_only() => Database::open_read_only(path.clone())?,
+            Some(group_commit) => Database::open_with_group_commit(path.clone(), group_commit.config())?,
+            None => Database::open(path.clone())?,
+        };
+        table.share_keyring(self.backend.keyring())?;
+        let settings = description.settings;
+        table.indexes = settings.indexes.into_iter().map(|schema| Index::new(&path, schema)).collect();
+        table.time_to_live = settings.time_to_live;
+        Ok(table)
+    }
+
+    /// The directory the database is stored in.
+    pub fn path(&self) -> &Path {
+        self.backend.path()
+    }
+
+    pub(crate) fn persistence(&self) -> &Persistence {
+        &self.backend
+    }
+
+    fn catalog(&self) -> Catalog {
+        Catalog::new(self.backend.path())
+    }
+
+    /// A handle other threads can use to write concurrently through the same group commit.
+    /// Writes made through the handle bypass this database's cache and a table's
+    /// indexes.
+    pub fn group_commit(&self) -> Option<Arc<GroupCommit>> {
+        self.group_commit.clone()
+    }
+
+    /// Like `load_all_data`, but an unreadable item file is skipped instead of
+    /// aborting the load. Returns the files that were skipped.
+    pub fn load_all_data_lenient(&mut self) -> io::Result<Vec<CorruptFile>> {
+        let mut corrupt = Vec::new();
+        check_keyspace(self.backend.path(), &self.backend.codec(), |data| {
+            self.store.insert(data.partition_key.clone(), data.sort_key.clone(), data);
+        }, |file| corrupt.push(file))?;
+        Ok(corrupt)
+    }
+
+    /// Sets how this keyspace compresses the records written from now on. The
+    /// setting is saved with the keyspace, so each table can have its own.
+    pub fn set_compression(&self, compression: Compression) -> io::Result<()> {
+        self.backend.set_compression(compression)?;
+        if let Some(group_commit) = &self.group_commit {
+            group_commit.persistence().set_compression(compression)?;
+        }
+        Ok(())
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.backend.compression()
+    }
+
+    /// Encrypts records written from now on with the keyring's active key, and
+    /// decrypts records written with any of its keys. `None` goes back to writing
+    /// plaintext. Tables opened afterwards use the same keyring.
+    pub fn set_keyring(&self, keyring: Option<Keyring>) -> io::Result<()> {
+        self.share_keyring(keyring.map(Arc::new))
+    }
+
+    fn share_keyring(&self, keyring: Option<Arc<Keyring>>) -> io::Result<()> {
+        self.backend.set_keyring(keyring.clone())?;
+        if let Some(group_commit) = &self.group_commit {
+            group_commit.persistence().set_keyring(keyring)?;
+        }
+        Ok(())
+    }
+
+    /// Starts rewriting, in the background, every record of this keyspace and of
+    /// its tables that isn't encrypted with the active key, so that retired keys
+    /// can be dropped from the keyring once it completes.
+    pub fn start_reencryption(&self) -> io::Result<Reencryption> {
+        self.backend.check_writable()?;
+        let keyring = self.backend.keyring()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no keyring to re-encrypt with"))?;
+        let mut keyspaces = vec![self.path().to_path_buf()];
+        for name in self.list_tables()? {
+            keyspaces.push(self.catalog().table_path(&name));
+        }
+        Ok(Reencryption::start(keyspaces, keyring))
+    }
+
+    /// Item and size counts of this keyspace. Tables are not included.
+    pub fn stats(&self) -> io::Result<Stats> {
+        self.backend.stats()
+    }
+
+    pub fn bloom_false_positive_rate(&self) -> f64 {
+        self.backend.bloom_false_positive_rate()
+    }
+
+    /// Sets the false positive rate of this keyspace's Bloom filters. See
+    /// `Persistence::set_bloom_false_positive_rate`.
+    pub fn set_bloom_false_positive_rate(&self, rate: f64) -> io::Result<()> {
+        self.backend.set_bloom_false_positive_rate(rate)
+    }
+
+    /// Removes the directories of partitions whose items have all been deleted, and
+    /// rebuilds the Bloom filters.
+    pub fn compact(&self) -> io::Result<usize> {
+        self.backend.compact()
+    }
+}
+
+impl<B: StorageBackend> Database<B> {
+    pub fn with_backend(backend: B) -> Self {
         Database {
             store: Store::new(),
-            persistence: Persistence::new(path),
+            backend,
             lock: Mutex::new(()),
+            group_commit: None,
+            time_to_live: None,
+            indexes: Vec::new(),
         }
     }
 
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
 
+    /// Fails with `NotFound` if the item doesn't exist, or has expired. Items the
+    /// backend's Bloom filter rules out are reported missing without reading storage.
     pub fn get(&mut self, partition_key: String, sort_key: String) -> io::Result<Option<Data>> {
-        match self.store.get(&partition_key, &sort_key) {
-            Some(data) => Ok(Some(data.clone())),
+        let data = match self.store.get(&partition_key, &sort_key) {
+            Some(data) => data.clone(),
             None => {
-                let data = self.persistence.load_data(partition_key.clone(), sort_key.clone())?;
+                if !self.backend.may_contain(&partition_key, &sort_key) {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "item not found"));
+                }
+                let data = self.backend.get(&partition_key, &sort_key)?
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "item not found"))?;
                 self.store.insert(partition_key.clone(), sort_key.clone(), data.clone());
-                Ok(Some(data))
+                data
+            }
+        };
+        if self.expired(&data, table::now()) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "item not found"));
+        }
+        Ok(Some(data))
+    }
+
+    /// Fetches many items at once. Keys are grouped by partition, cached items are
+    /// served from the store without I/O and the rest are read from disk once per key.
+    pub fn batch_get(&mut self, keys: Vec<(String, String)>) -> io::Result<BatchGetResult> {
+        // The sort keys of each partition in request order, and the set of them to
+        // drop repeated keys.
+        let mut partitions: Vec<(String, Vec<String>, HashSet<String>)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for (partition_key, sort_key) in keys {
+            let position = *positions.entry(partition_key.clone()).or_insert_with(|| {
+                partitions.push((partition_key.clone(), Vec::new(), HashSet::new()));
+                partitions.len() - 1
+            });
+            let (_, sort_keys, seen) = &mut partitions[position];
+            if seen.insert(sort_key.clone()) {
+                sort_keys.push(sort_key);
+            }
+        }
+
+        let mut result = BatchGetResult::default();
+        for (partition_key, sort_keys, _) in partitions {
+            let mut uncached = Vec::new();
+            for sort_key in sort_keys {
+                match self.store.get(&partition_key, &sort_key) {
+                    Some(data) => result.found.push(data.clone()),
+                    None if self.backend.may_contain(&partition_key, &sort_key) => uncached.push(sort_key),
+                    None => result.missing.push((partition_key.clone(), sort_key)),
+                }
+            }
+            if uncached.is_empty() {
+                continue;
             }
+
+            let loaded = self.backend.get_batch(&partition_key, &uncached)?;
+            for (sort_key, data) in uncached.into_iter().zip(loaded) {
+                match data {
+                    Some(data) => {
+                        self.store.insert(partition_key.clone(), sort_key, data.clone());
+                        result.found.push(data);
+                    },
+                    None => result.missing.push((partition_key.clone(), sort_key)),
+                }
+            }
+        }
+        if self.time_to_live.is_some() {
+            let now = table::now();
+            let (expired, found): (Vec<Data>, Vec<Data>) = result.found.into_iter().partition(|data| self.expired(data, now));
+            result.found = found;
+            result.missing.extend(expired.into_iter().map(|data| (data.partition_key, data.sort_key)));
         }
+        Ok(result)
     }
 
+    /// The cache is only updated once the write has succeeded.
     pub fn insert(&mut self, partition_key: String, sort_key: String, value: String) -> io::Result<()> {
         let data = Data { 
             operation_type: OperationType::Insert,
@@ -42,46 +342,250 @@ impl Database {
             
         // Lock the mutex before modifying the data.
         let _guard = self.lock.lock().unwrap();
+        let previous = self.add_index_entries(slice::from_ref(&data))?;
             
+        match &self.group_commit {
+            Some(group_commit) => group_commit.commit(vec![data.clone()])?,
+            None => self.backend.put(&data)?,
+        }
         self.store.insert(partition_key, sort_key, data.clone());
-        self.persistence.save_data(&data)
+        self.remove_index_entries(slice::from_ref(&data), previous)
     }
     
     
 
     pub fn delete(&mut self, partition_key: String, sort_key: String) -> io::Result<()> {
+        let data = Data {
+            operation_type: OperationType::Delete,
+            partition_key: partition_key.clone(),
+            sort_key: sort_key.clone(),
+            value: String::new(),
+        };
+        let previous = self.add_index_entries(slice::from_ref(&data))?;
+        match &self.group_commit {
+            Some(group_commit) => group_commit.commit(vec![data.clone()])?,
+            None => self.backend.delete(&partition_key, &sort_key)?,
+        }
         self.store.delete(&partition_key, &sort_key);
-        self.persistence.delete_data(&partition_key, &sort_key)
+        self.remove_index_entries(slice::from_ref(&data), previous)
+    }
+
+    /// For a table with indexes, reads the items `mutations` replace and adds the
+    /// index entries of the new ones. Returns the items replaced.
+    fn add_index_entries(&self, mutations: &[Data]) -> io::Result<Vec<Option<Data>>> {
+        if self.indexes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut previous = Vec::with_capacity(mutations.len());
+        for data in mutations {
+            previous.push(self.backend.get(&data.partition_key, &data.sort_key)?);
+            for index in &self.indexes {
+                index.add(data)?;
+            }
+        }
+        Ok(previous)
+    }
+
+    /// Removes the index entries of the items `mutations` replaced, once written.
+    fn remove_index_entries(&self, mutations: &[Data], previous: Vec<Option<Data>>) -> io::Result<()> {
+        for (data, previous) in mutations.iter().zip(previous) {
+            let Some(previous) = previous else {
+                continue;
+            };
+            for index in &self.indexes {
+                index.remove(&previous, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expired(&self, data: &Data, now: u64) -> bool {
+        self.time_to_live.as_ref().is_some_and(|time_to_live| time_to_live.expired(data, now))
     }
 
     // Consider adding this function if you frequently work with the whole dataset
     pub fn load_all_data(&mut self) -> io::Result<()> {
-        let data_map = self.persistence.load_all_data()?;
-        for (partition_key, partition) in data_map {
-            for (sort_key, value) in partition {
-                self.store.insert(partition_key.clone(), sort_key, value);
-            }
+        for data in self.backend.scan(0, 1)? {
+            let data = data?;
+            self.store.insert(data.partition_key.clone(), data.sort_key.clone(), data);
         }
         Ok(())
     }
 
+    pub fn list_partitions(&self) -> io::Result<Vec<String>> {
+        self.backend.list_partitions()
+    }
+
+    /// Streams every item on disk without loading the dataset into memory, expired
+    /// items of a table included.
+    pub fn iter(&self) -> io::Result<B::Iter> {
+        self.backend.scan(0, 1)
+    }
+
+    /// Streams the items of one partition straight from disk.
+    pub fn iter_partition(&self, partition_key: &str) -> io::Result<B::Iter> {
+        self.backend.range(partition_key, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.backend.flush()
+    }
+
+    /// Writes a group of inserts and deletes with one `write_batch`, or through the
+    /// group commit. Without a group commit, deleting an item that doesn't exist
+    /// by then fails with `NotFound`, like `delete`, before anything is written.
+    /// Through the group commit it is not an error.
     pub fn batch(&mut self, data: Vec<Data>) -> io::Result<()> {
-        for item in data {
+        let mutations: Vec<Data> = data
+            .into_iter()
+            .map(|mut item| {
+                if item.operation_type == OperationType::Update {
+                    item.operation_type = OperationType::Insert;
+                }
+                item
+            })
+            .collect();
+        if self.group_commit.is_none() {
+            self.check_deletes(&mutations)?;
+        }
+        let previous = self.add_index_entries(&mutations)?;
+        match &self.group_commit {
+            Some(group_commit) => group_commit.commit(mutations.clone())?,
+            None => self.backend.write_batch(&mutations)?,
+        }
+
+        for item in &mutations {
             match item.operation_type {
-                OperationType::Insert | OperationType::Update => {
-                    self.insert(item.partition_key, item.sort_key, item.value)?
-                },
-                OperationType::Delete => {
-                    self.delete(item.partition_key, item.sort_key)?
-                },
+                OperationType::Delete => self.store.delete(&item.partition_key, &item.sort_key),
+                _ => self.store.insert(item.partition_key.clone(), item.sort_key.clone(), item.clone()),
             }
         }
+        self.remove_index_entries(&mutations, previous)
+    }
+
+    /// Fails with `NotFound` if a delete of `mutations` is of an item that neither
+    /// exists nor is inserted earlier in them.
+    fn check_deletes(&self, mutations: &[Data]) -> io::Result<()> {
+        let mut written: HashMap<(&String, &String), bool> = HashMap::new();
+        for data in mutations {
+            let key = (&data.partition_key, &data.sort_key);
+            if data.operation_type == OperationType::Delete {
+                let exists = match written.get(&key) {
+                    Some(exists) => *exists,
+                    None => {
+                        self.store.get(key.0, key.1).is_some()
+                            || (self.backend.may_contain(key.0, key.1) && self.backend.get(key.0, key.1)?.is_some())
+                    },
+                };
+                if !exists {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, "item not found"));
+                }
+            }
+            written.insert(key, data.operation_type != OperationType::Delete);
+        }
         Ok(())
     }
-    
 
+    /// Returns the items of a table whose attribute indexed by `index` holds
+    /// `value`, ordered by key. Fails with `NotFound` if there is no such index.
+    pub fn query_index(&self, index: &str, value: &str, limit: Option<usize>) -> io::Result<Vec<Data>> {
+        let index = self.indexes.iter().find(|candidate| candidate.name() == index)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("index '{}' does not exist", index)))?;
+        let now = table::now();
+        let mut items = Vec::new();
+        for (partition_key, sort_key) in index.lookup(value)? {
+            if items.len() == limit.unwrap_or(usize::MAX) {
+                break;
+            }
+            // Entries can outlive the value they were added for; see `Index`.
+            match self.backend.get(&partition_key, &sort_key)? {
+                Some(data) if index.value_of(&data).as_deref() == Some(value) && !self.expired(&data, now) => items.push(data),
+                _ => {},
+            }
+        }
+        Ok(items)
+    }
+
+    /// Deletes the items of a table whose time to live has passed, returning how
+    /// many there were. Reads skip expired items already; this frees their space.
+    pub fn remove_expired(&mut self) -> io::Result<usize> {
+        let now = table::now();
+        let mut expired = Vec::new();
+        for data in self.backend.scan(0, 1)? {
+            let data = data?;
+            if self.expired(&data, now) {
+                expired.push(Data { operation_type: OperationType::Delete, value: String::new(), ..data });
+            }
+        }
+        let count = expired.len();
+        if count > 0 {
+            self.batch(expired)?;
+        }
+        Ok(count)
+    }
+
+    /// Returns the items of one partition that match `filter`, ordered by sort key.
+    /// Like the other reads, it skips the expired items of a table.
+    /// Bounds the filter puts on the sort key narrow the range read from storage.
+    pub fn query(&self, partition_key: &str, filter: Option<&Filter>, limit: Option<usize>) -> io::Result<Vec<Data>> {
+        let (from, to) = scan::sort_key_range(filter);
+        let now = table::now();
+        let mut items = Vec::new();
+        for data in self.backend.range(partition_key, from.as_ref().map(String::as_str), to.as_ref().map(String::as_str))? {
+            let data = data?;
+            if scan::matches(filter, &data) && !self.expired(&data, now) {
+                items.push(data);
+            }
+        }
+        items.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+        items.truncate(limit.unwrap_or(usize::MAX));
+        Ok(items)
+    }
+
+    /// Reads every item on disk, across all partitions, that matches `filter`.
+    pub fn scan(&self, filter: Option<&Filter>) -> io::Result<Vec<Data>> {
+        self.scan_segment(filter, 0, 1)
+    }
+
+    /// Scans only the partitions assigned to `segment` out of `total_segments`.
+    /// Running every segment, in any order or concurrently, visits each item exactly once.
+    pub fn scan_segment(&self, filter: Option<&Filter>, segment: usize, total_segments: usize) -> io::Result<Vec<Data>> {
+        self.scan_iter(filter, segment, total_segments)?.collect()
+    }
+
+    /// Lazily streams the matching items of a segment, reading one file at a time.
+    pub fn scan_iter<'a>(
+        &self,
+        filter: Option<&'a Filter>,
+        segment: usize,
+        total_segments: usize,
+    ) -> io::Result<impl Iterator<Item = io::Result<Data>> + 'a> {
+        assert!(segment < total_segments, "segment must be less than total_segments");
+        let items = self.backend.scan(segment, total_segments)?;
+        let (time_to_live, now) = (self.time_to_live.clone(), table::now());
+        Ok(items.filter(move |data| match data {
+            Ok(data) => scan::matches(filter, data) && !time_to_live.as_ref().is_some_and(|time_to_live| time_to_live.expired(data, now)),
+            Err(_) => true,
+        }))
+    }
+
+    /// Scans all `total_segments` segments on separate threads and merges the results.
+    pub fn parallel_scan(&self, filter: Option<&Filter>, total_segments: usize) -> io::Result<Vec<Data>> {
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..total_segments)
+                .map(|segment| scope.spawn(move || self.scan_segment(filter, segment, total_segments)))
+                .collect();
+
+            let mut items = Vec::new();
+            for worker in workers {
+                items.extend(worker.join().expect("scan worker panicked")?);
+            }
+            Ok(items)
+        })
+    }
 }
 
+#[derive(Default)]
 pub struct InMemoryDatabase {
     store: Store,
 }
@@ -101,6 +605,15 @@ impl InMemoryDatabase {
         self.store.get_all(&partition_key).map(|partition| partition.values().cloned().collect())
     }
 
+    /// Iterates a partition by reference instead of cloning it into a `Vec`.
+    pub fn iter_partition<'a>(&'a self, partition_key: &str) -> impl Iterator<Item = &'a Data> {
+        self.store.get_all(&partition_key.to_string()).into_iter().flat_map(|partition| partition.values())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Data> {
+        self.store.iter()
+    }
+
     pub fn insert(&mut self, partition_key: String, sort_key: String, value: String) {
         let data = Data { 
             operation_type: OperationType::Insert,
@@ -114,4 +627,42 @@ impl InMemoryDatabase {
     pub fn delete(&mut self, partition_key: String, sort_key: String) {
         self.store.delete(&partition_key, &sort_key);
     }
+
+    pub fn query(&self, partition_key: &str, filter: Option<&Filter>, limit: Option<usize>) -> Vec<Data> {
+        let mut items: Vec<Data> = self
+            .iter_partition(partition_key)
+            .filter(|data| scan::matches(filter, data))
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+        items.truncate(limit.unwrap_or(usize::MAX));
+        items
+    }
+
+    pub fn scan(&self, filter: Option<&Filter>) -> Vec<Data> {
+        self.scan_segment(filter, 0, 1)
+    }
+
+    pub fn scan_segment(&self, filter: Option<&Filter>, segment: usize, total_segments: usize) -> Vec<Data> {
+        assert!(segment < total_segments, "segment must be less than total_segments");
+        self.store
+            .iter()
+            .filter(|data| segment_of(&data.partition_key, total_segments) == segment)
+            .filter(|data| scan::matches(filter, data))
+            .cloned()
+            .collect()
+    }
+
+    pub fn parallel_scan(&self, filter: Option<&Filter>, total_segments: usize) -> Vec<Data> {
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..total_segments)
+                .map(|segment| scope.spawn(move || self.scan_segment(filter, segment, total_segments)))
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("scan worker panicked"))
+                .collect()
+        })
+    }
 }
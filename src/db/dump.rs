@@ -0,0 +1,247 @@
+//! A portable binary dump of a database directory: the root keyspace, every
+//! table with its description, and all items.
+//!
+//! ```text
+//! "FERRETDB" u16 version
+//! entries, each starting with a tag byte:
+//!   1  table: string (TableDescription as JSON)
+//!   2  item:  string table ("" for the root keyspace), string partition_key,
+//!             string sort_key, string value
+//!   0  end:   u64 number of items
+//! ```
+//!
+//! Integers are little-endian and strings are a u32 byte length followed by UTF-8.
+//! The end entry lets a reader tell a complete dump from a truncated one. Readers
+//! reject versions newer than their own.
+
+use super::database::{Database, InMemoryDatabase};
+use super::persistence::{Data, OperationType};
+use super::table::TableDescription;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+const MAGIC: &[u8; 8] = b"FERRETDB";
+pub const DUMP_VERSION: u16 = 1;
+
+const TAG_END: u8 = 0;
+const TAG_TABLE: u8 = 1;
+const TAG_ITEM: u8 = 2;
+
+const RESTORE_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DumpEntry {
+    Table(TableDescription),
+    /// An item of the named table, or of the root keyspace when `table` is `None`.
+    Item { table: Option<String>, data: Data },
+}
+
+/// Writes every item of `database` and of its tables. Returns the number of items.
+pub fn write_dump<W: Write>(database: &Database, out: W) -> io::Result<usize> {
+    let mut out = BufWriter::new(out);
+    write_header(&mut out)?;
+
+    let mut count = write_items(&mut out, "", database.iter()?)?;
+    for name in database.list_tables()? {
+        out.write_all(&[TAG_TABLE])?;
+        write_string(&mut out, &serde_json::to_string(&database.describe_table(&name)?)?)?;
+        count += write_items(&mut out, &name, database.table(&name)?.iter()?)?;
+    }
+    write_trailer(out, count)
+}
+
+/// Writes the items of an in-memory database as the root keyspace of a dump.
+pub fn write_dump_in_memory<W: Write>(database: &InMemoryDatabase, out: W) -> io::Result<usize> {
+    let mut out = BufWriter::new(out);
+    write_header(&mut out)?;
+    let count = write_items(&mut out, "", database.iter().cloned().map(Ok))?;
+    write_trailer(out, count)
+}
+
+fn write_header<W: Write>(out: &mut W) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&DUMP_VERSION.to_le_bytes())
+}
+
+fn write_items<W: Write>(out: &mut W, table: &str, items: impl Iterator<Item = io::Result<Data>>) -> io::Result<usize> {
+    let mut count = 0;
+    for data in items {
+        let data = data?;
+        out.write_all(&[TAG_ITEM])?;
+        for field in [table, &data.partition_key, &data.sort_key, &data.value] {
+            write_string(out, field)?;
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn write_trailer<W: Write>(mut out: BufWriter<W>, count: usize) -> io::Result<usize> {
+    out.write_all(&[TAG_END])?;
+    out.write_all(&(count as u64).to_le_bytes())?;
+    out.flush()?;
+    Ok(count)
+}
+
+fn write_string<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
+    let length = u32::try_from(value.len()).map_err(|_| invalid_data("string too long for a dump"))?;
+    out.write_all(&length.to_le_bytes())?;
+    out.write_all(value.as_bytes())
+}
+
+/// Loads a dump into `database`, creating the tables it contains if they don't
+/// exist yet. Items overwrite existing items with the same keys. Returns the
+/// number of items restored.
+pub fn restore<R: Read>(database: &mut Database, reader: R) -> io::Result<usize> {
+    let mut count = 0;
+    let mut current: Option<String> = None;
+    let mut table_database: Option<Database> = None;
+    let mut batch = Vec::with_capacity(RESTORE_BATCH_SIZE);
+
+    for entry in DumpReader::new(reader)? {
+        match entry? {
+            DumpEntry::Table(description) => match database.create_table_with_settings(&description.name, description.key_schema, description.settings) {
+                Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                _ => {},
+            },
+            DumpEntry::Item { table, data } => {
+                if table != current || batch.len() == RESTORE_BATCH_SIZE {
+                    flush(database, table_database.as_mut(), &mut batch)?;
+                    if table != current {
+                        table_database = table.as_deref().map(|name| database.table(name)).transpose()?;
+                        current = table;
+                    }
+                }
+                batch.push(data);
+                count += 1;
+            },
+        }
+    }
+    flush(database, table_database.as_mut(), &mut batch)?;
+    Ok(count)
+}
+
+fn flush(root: &mut Database, table: Option<&mut Database>, batch: &mut Vec<Data>) -> io::Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let items = std::mem::take(batch);
+    match table {
+        Some(table) => table.batch(items),
+        None => root.batch(items),
+    }
+}
+
+/// Loads the items of one keyspace of a dump into `database`: the root keyspace
+/// when `table` is `None`, or the named table. Returns the number of items.
+pub fn restore_in_memory<R: Read>(database: &mut InMemoryDatabase, reader: R, table: Option<&str>) -> io::Result<usize> {
+    let mut count = 0;
+    for entry in DumpReader::new(reader)? {
+        if let DumpEntry::Item { table: item_table, data } = entry? {
+            if item_table.as_deref() == table {
+                database.insert(data.partition_key, data.sort_key, data.value);
+                count += 1;
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Reads the entries of a dump one at a time. Iteration ends with an error if the
+/// dump is cut short or malformed.
+pub struct DumpReader<R: Read> {
+    reader: BufReader<R>,
+    items: u64,
+    done: bool,
+}
+
+impl<R: Read> DumpReader<R> {
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).map_err(|_| invalid_data("not a dump file"))?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a dump file"));
+        }
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version > DUMP_VERSION {
+            return Err(invalid_data(&format!("unsupported dump version {}", version)));
+        }
+        Ok(DumpReader { reader, items: 0, done: false })
+    }
+
+    fn read_entry(&mut self) -> io::Result<Option<DumpEntry>> {
+        let mut tag = [0; 1];
+        self.reader.read_exact(&mut tag).map_err(truncated)?;
+        match tag[0] {
+            TAG_END => {
+                let mut count = [0; 8];
+                self.reader.read_exact(&mut count).map_err(truncated)?;
+                if u64::from_le_bytes(count) != self.items {
+                    return Err(invalid_data("item count does not match the dump trailer"));
+                }
+                Ok(None)
+            },
+            TAG_TABLE => Ok(Some(DumpEntry::Table(serde_json::from_str(&self.read_string()?)?))),
+            TAG_ITEM => {
+                let table = self.read_string()?;
+                let data = Data {
+                    operation_type: OperationType::Insert,
+                    partition_key: self.read_string()?,
+                    sort_key: self.read_string()?,
+                    value: self.read_string()?,
+                };
+                self.items += 1;
+                Ok(Some(DumpEntry::Item { table: Some(table).filter(|table| !table.is_empty()), data }))
+            },
+            tag => Err(invalid_data(&format!("unknown entry tag {}", tag))),
+        }
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length).map_err(truncated)?;
+        let length = u32::from_le_bytes(length) as usize;
+        // Read through `take` so a corrupt length can't trigger a huge allocation.
+        let mut bytes = Vec::new();
+        self.reader.by_ref().take(length as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != length {
+            return Err(truncated(io::ErrorKind::UnexpectedEof.into()));
+        }
+        String::from_utf8(bytes).map_err(|_| invalid_data("invalid UTF-8 in dump"))
+    }
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = io::Result<DumpEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.read_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            },
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            },
+        }
+    }
+}
+
+fn truncated(error: io::Error) -> io::Error {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        io::Error::new(io::ErrorKind::UnexpectedEof, "dump is truncated")
+    } else {
+        error
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
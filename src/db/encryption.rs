@@ -0,0 +1,244 @@
+use super::persistence::{sync_dir, Persistence};
+use super::record;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// A 256-bit key for encrypting records, with the id records name it by.
+#[derive(Clone, PartialEq)]
+pub struct EncryptionKey {
+    id: u32,
+    key: [u8; KEY_LEN],
+}
+
+impl EncryptionKey {
+    pub fn new(id: u32, key: [u8; KEY_LEN]) -> Self {
+        EncryptionKey { id, key }
+    }
+
+    /// A new random key.
+    pub fn generate(id: u32) -> Self {
+        EncryptionKey { id, key: ChaCha20Poly1305::generate_key(&mut OsRng).into() }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptionKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// The keys records can be encrypted with. New records are encrypted with the
+/// active key; records encrypted with any other key of the ring stay readable,
+/// so rotating to a new key only needs the old one kept until `Reencryption`
+/// has rewritten everything.
+#[derive(Clone, PartialEq)]
+pub struct Keyring {
+    active: u32,
+    keys: BTreeMap<u32, [u8; KEY_LEN]>,
+}
+
+/// The key file: `{"active": 2, "keys": {"1": "<hex>", "2": "<hex>"}}`.
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    active: u32,
+    keys: BTreeMap<u32, String>,
+}
+
+impl Keyring {
+    pub fn new(active: EncryptionKey) -> Self {
+        Keyring { active: active.id, keys: BTreeMap::from([(active.id, active.key)]) }
+    }
+
+    pub fn active_id(&self) -> u32 {
+        self.active
+    }
+
+    /// Adds a key that records may still be encrypted with, without making it active.
+    pub fn add(&mut self, key: EncryptionKey) {
+        self.keys.insert(key.id, key.key);
+    }
+
+    /// Adds `key` and encrypts new records with it from now on.
+    pub fn rotate(&mut self, key: EncryptionKey) {
+        self.active = key.id;
+        self.add(key);
+    }
+
+    /// Drops a retired key. Records still encrypted with it can't be read anymore.
+    pub fn remove(&mut self, id: u32) -> io::Result<()> {
+        if id == self.active {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot remove the active key"));
+        }
+        self.keys.remove(&id);
+        Ok(())
+    }
+
+    /// The id one higher than any key in the ring, for the next rotation.
+    pub fn next_id(&self) -> u32 {
+        self.keys.keys().next_back().map_or(1, |id| id + 1)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Keyring> {
+        let file: KeyFile = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let mut keys = BTreeMap::new();
+        for (id, key) in file.keys {
+            let key = decode_hex(&key).and_then(|key| key.try_into().ok()).ok_or_else(|| invalid_key_file(&format!("key {} is not 64 hex digits", id)))?;
+            keys.insert(id, key);
+        }
+        if !keys.contains_key(&file.active) {
+            return Err(invalid_key_file(&format!("active key {} is not in the file", file.active)));
+        }
+        Ok(Keyring { active: file.active, keys })
+    }
+
+    /// Writes the key file, readable only by its owner on Unix. The file is synced
+    /// before it replaces the old one and the replacement is synced too, as losing
+    /// the keys loses every record encrypted with them.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let file = KeyFile { active: self.active, keys: self.keys.iter().map(|(id, key)| (*id, encode_hex(key))).collect() };
+        let staging = path.with_extension("tmp");
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut staged = options.open(&staging)?;
+        serde_json::to_writer_pretty(&mut staged, &file)?;
+        staged.sync_all()?;
+        fs::rename(staging, path)?;
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => sync_dir(dir),
+            _ => sync_dir(Path::new(".")),
+        }
+    }
+
+    /// Encrypts `plaintext` with the active key as `key id, nonce, ciphertext`,
+    /// authenticating `aad` along with it.
+    pub(crate) fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.keys[&self.active]));
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, Payload { msg: plaintext, aad })
+            .map_err(|_| io::Error::other("failed to encrypt record"))?;
+
+        let mut sealed = Vec::with_capacity(4 + NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&self.active.to_le_bytes());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Reverses `encrypt` for the record at `path`.
+    pub(crate) fn decrypt(&self, path: &Path, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        let id = key_id(sealed).ok_or_else(|| record::corrupt(path, "encrypted record is truncated"))?;
+        let key = self.keys.get(&id).ok_or_else(|| {
+            let message = format!("record at {} is encrypted with key {}, which is not in the keyring", path.display(), id);
+            io::Error::new(io::ErrorKind::PermissionDenied, message)
+        })?;
+        let (nonce, ciphertext) = sealed[4..].split_at(NONCE_LEN);
+        ChaCha20Poly1305::new(Key::from_slice(key))
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+            .map_err(|_| record::corrupt(path, format!("failed to decrypt with key {}: wrong key or tampered record", id)))
+    }
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Keyring").field("active", &self.active).field("ids", &self.keys.keys().collect::<Vec<_>>()).finish()
+    }
+}
+
+/// The id of the key a sealed record was encrypted with.
+pub(crate) fn key_id(sealed: &[u8]) -> Option<u32> {
+    if sealed.len() < 4 + NONCE_LEN {
+        return None;
+    }
+    Some(u32::from_le_bytes([sealed[0], sealed[1], sealed[2], sealed[3]]))
+}
+
+pub(crate) fn no_keyring(path: &Path) -> io::Error {
+    let message = format!("record at {} is encrypted and no keyring was supplied", path.display());
+    io::Error::new(io::ErrorKind::PermissionDenied, message)
+}
+
+fn invalid_key_file(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid key file: {}", message))
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// A background job rewriting every record that isn't encrypted with the active
+/// key, started by `Database::start_reencryption`.
+///
+/// Records are rewritten one at a time, each only if no other write replaced or
+/// deleted it since it was read. Reads carry on normally while the job runs, and
+/// writes to the keyspace only wait while a record is swapped in. If a keyspace
+/// can't be opened, for instance because another process holds a table, the job
+/// stops and `join` returns the error.
+#[derive(Debug)]
+pub struct Reencryption {
+    handle: JoinHandle<io::Result<usize>>,
+    rewritten: Arc<AtomicUsize>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Reencryption {
+    pub(crate) fn start(keyspaces: Vec<PathBuf>, keyring: Arc<Keyring>) -> Self {
+        let rewritten = Arc::new(AtomicUsize::new(0));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (job_rewritten, job_cancelled) = (rewritten.clone(), cancelled.clone());
+        let handle = thread::spawn(move || {
+            for path in keyspaces {
+                let persistence = Persistence::open(path)?;
+                persistence.set_keyring(Some(keyring.clone()))?;
+                persistence.reencrypt(&job_rewritten, &job_cancelled)?;
+            }
+            Ok(job_rewritten.load(Ordering::SeqCst))
+        });
+        Reencryption { handle, rewritten, cancelled }
+    }
+
+    /// Records rewritten so far.
+    pub fn rewritten(&self) -> usize {
+        self.rewritten.load(Ordering::SeqCst)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Stops the job after the record it is rewriting. Running it again later
+    /// picks up the records it didn't get to.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Waits for the job and returns how many records it rewrote.
+    pub fn join(self) -> io::Result<usize> {
+        self.handle.join().unwrap_or_else(|_| Err(io::Error::other("re-encryption panicked")))
+    }
+}
@@ -0,0 +1,115 @@
+use super::database::Database;
+use super::persistence::Data;
+use super::scan::{Attribute, Filter};
+use serde::Serialize;
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// A `partition_key,sort_key,value` header followed by one row per item.
+    Csv,
+    /// One `{"partition_key", "sort_key", "value"}` object per line.
+    JsonLines,
+}
+
+/// The items to export from a keyspace. Export a table by passing the `Database`
+/// returned by `Database::table`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    /// Every item of the keyspace, without the items of its tables: rows carry
+    /// no table name, so they couldn't be imported back where they came from.
+    /// Use a dump to export a database with its tables.
+    All,
+    Partition(String),
+    /// The items of one partition with sort keys between `from` and `to`, inclusive.
+    Range { partition_key: String, from: Option<String>, to: Option<String> },
+}
+
+#[derive(Serialize)]
+struct Item<'a> {
+    partition_key: &'a str,
+    sort_key: &'a str,
+    value: &'a str,
+}
+
+/// Writes the selected items to `out` and returns how many were written. Both
+/// formats can be loaded back with `import` and its default field mapping.
+pub fn export<W: Write>(database: &Database, selection: &Selection, format: ExportFormat, out: W) -> io::Result<usize> {
+    let mut writer = ExportWriter::new(out, format)?;
+    match selection {
+        Selection::All => {
+            for data in database.iter()? {
+                writer.write(&data?)?;
+            }
+        },
+        Selection::Partition(partition_key) => {
+            for data in database.iter_partition(partition_key)? {
+                writer.write(&data?)?;
+            }
+        },
+        Selection::Range { partition_key, from, to } => {
+            let filter = match (from, to) {
+                (Some(from), Some(to)) => Some(Filter::Between(Attribute::SortKey, from.clone(), to.clone())),
+                (Some(from), None) => Some(Filter::Ge(Attribute::SortKey, from.clone())),
+                (None, Some(to)) => Some(Filter::Le(Attribute::SortKey, to.clone())),
+                (None, None) => None,
+            };
+            for data in database.query(partition_key, filter.as_ref(), None)? {
+                writer.write(&data)?;
+            }
+        },
+    }
+    writer.finish()
+}
+
+/// Writes items one at a time in an export format.
+pub struct ExportWriter<W: Write> {
+    out: io::BufWriter<W>,
+    format: ExportFormat,
+    written: usize,
+}
+
+impl<W: Write> ExportWriter<W> {
+    pub fn new(out: W, format: ExportFormat) -> io::Result<Self> {
+        let mut out = io::BufWriter::new(out);
+        if format == ExportFormat::Csv {
+            out.write_all(b"partition_key,sort_key,value\n")?;
+        }
+        Ok(ExportWriter { out, format, written: 0 })
+    }
+
+    pub fn write(&mut self, data: &Data) -> io::Result<()> {
+        match self.format {
+            ExportFormat::JsonLines => {
+                let item = Item { partition_key: &data.partition_key, sort_key: &data.sort_key, value: &data.value };
+                serde_json::to_writer(&mut self.out, &item)?;
+            },
+            ExportFormat::Csv => {
+                let fields = [&data.partition_key, &data.sort_key, &data.value];
+                for (index, field) in fields.into_iter().enumerate() {
+                    if index > 0 {
+                        self.out.write_all(b",")?;
+                    }
+                    write_csv_field(&mut self.out, field)?;
+                }
+            },
+        }
+        self.out.write_all(b"\n")?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Flushes the output and returns the number of items written.
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.out.flush()?;
+        Ok(self.written)
+    }
+}
+
+fn write_csv_field<W: Write>(out: &mut W, field: &str) -> io::Result<()> {
+    if field.contains([',', '"', '\r', '\n']) {
+        write!(out, "\"{}\"", field.replace('"', "\"\""))
+    } else {
+        out.write_all(field.as_bytes())
+    }
+}
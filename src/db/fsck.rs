@@ -0,0 +1,106 @@
+use super::database::Database;
+use super::migrate::{reason, CorruptFile};
+use super::persistence::{is_reserved, read_checked, Data};
+use super::record::Codec;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+const QUARANTINE_DIR: &str = ".quarantine";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsckMode {
+    /// Only report problems.
+    Verify,
+    /// Move unusable files into quarantine and remove the partition directories
+    /// this leaves empty.
+    Repair,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct FsckReport {
+    /// Item files examined, in the root keyspace and all tables.
+    pub checked: usize,
+    pub corrupt: Vec<CorruptFile>,
+    /// Files moved into quarantine. Always zero when verifying.
+    pub quarantined: usize,
+    /// Empty partition directories removed. Always zero when verifying.
+    pub removed_partitions: usize,
+}
+
+/// Checks every item file of `database` and of its tables: that it is a file, that
+/// it passes its checksum and holds a valid record, and that the record's keys match where it is stored.
+///
+/// In `FsckMode::Repair`, each bad file is moved to `.quarantine/<partition>/<sort>`
+/// inside its keyspace, where it no longer affects reads and can be inspected or
+/// restored by hand. Partition directories are the only index this layout keeps,
+/// so rebuilding it amounts to removing the ones left empty.
+pub fn fsck(database: &Database, mode: FsckMode) -> io::Result<FsckReport> {
+    if mode == FsckMode::Repair {
+        database.persistence().check_writable()?;
+    }
+    let mut report = FsckReport::default();
+    check_database(database, mode, &mut report)?;
+    for name in database.list_tables()? {
+        check_database(&database.table(&name)?, mode, &mut report)?;
+    }
+    Ok(report)
+}
+
+fn check_database(database: &Database, mode: FsckMode, report: &mut FsckReport) -> io::Result<()> {
+    let mut corrupt = Vec::new();
+    check_keyspace(database.path(), &database.persistence().codec(), |_| report.checked += 1, |file| corrupt.push(file))?;
+    report.checked += corrupt.len();
+
+    if mode == FsckMode::Repair {
+        for file in &corrupt {
+            quarantine(database.path(), &file.path)?;
+            report.quarantined += 1;
+        }
+        report.removed_partitions += database.compact()?;
+    }
+    report.corrupt.extend(corrupt);
+    Ok(())
+}
+
+/// Reads every item file of the keyspace stored at `root`, passing each valid
+/// record to `on_item` and each unusable file to `on_corrupt`. Only failing to
+/// list a directory is an error.
+pub(crate) fn check_keyspace(root: &Path, codec: &Codec, mut on_item: impl FnMut(Data), mut on_corrupt: impl FnMut(CorruptFile)) -> io::Result<()> {
+    for partition in fs::read_dir(root)? {
+        let partition = partition?;
+        let partition_key = partition.file_name().to_string_lossy().into_owned();
+        if is_reserved(&partition_key) || !partition.file_type()?.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(partition.path())? {
+            let entry = entry?;
+            let sort_key = entry.file_name().to_string_lossy().into_owned();
+            if is_reserved(&sort_key) {
+                continue;
+            }
+            if !entry.file_type()?.is_file() {
+                on_corrupt(CorruptFile { path: entry.path(), reason: "not a file".to_string() });
+                continue;
+            }
+            match read_checked(&entry.path(), &partition_key, &sort_key, codec) {
+                Ok(data) => on_item(data),
+                // Deleted while we were looking.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => on_corrupt(CorruptFile { path: entry.path(), reason: reason(&e) }),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn quarantine(root: &Path, path: &Path) -> io::Result<()> {
+    let relative = path.strip_prefix(root).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let destination = root.join(QUARANTINE_DIR).join(relative);
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(path, destination)
+}
@@ -0,0 +1,157 @@
+use super::persistence::{Data, Persistence};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct GroupCommitConfig {
+    /// How long the first writer of a group waits for others to join it.
+    pub max_delay: Duration,
+    /// A group is written as soon as it holds this many mutations.
+    pub max_batch_size: usize,
+}
+
+impl Default for GroupCommitConfig {
+    fn default() -> Self {
+        GroupCommitConfig {
+            max_delay: Duration::from_millis(2),
+            max_batch_size: 256,
+        }
+    }
+}
+
+/// Coalesces mutations from concurrent writers into shared durable writes.
+///
+/// The first writer to arrive becomes the leader of a group: it waits up to
+/// `max_delay` (or until `max_batch_size` mutations are queued), then writes the
+/// whole group with one journal fsync. Every writer returns only once the group holding its mutations is
+/// durable, so each call keeps the guarantee of an individual synced write.
+/// Groups are written strictly in the order they close.
+///
+/// Share it between threads with an `Arc`.
+#[derive(Debug)]
+pub struct GroupCommit {
+    persistence: Persistence,
+    config: GroupCommitConfig,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    pending: Vec<Data>,
+    group: Arc<Group>,
+    leader: bool,
+    next_ticket: u64,
+    serving: u64,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    result: Mutex<Option<Result<(), (io::ErrorKind, String)>>>,
+    done: Condvar,
+}
+
+impl GroupCommit {
+    /// Opens the keyspace at `path`, panicking if it can't. See `open`.
+    pub fn new(path: PathBuf, config: GroupCommitConfig) -> Self {
+        let display = path.display().to_string();
+        GroupCommit::open(path, config).unwrap_or_else(|e| panic!("Failed to open {}: {}", display, e))
+    }
+
+    /// Opens the keyspace at `path` like `Persistence::open`, failing with a
+    /// `LockError` while another process has it open for writing.
+    pub fn open(path: PathBuf, config: GroupCommitConfig) -> io::Result<Self> {
+        Ok(GroupCommit {
+            persistence: Persistence::open(path)?,
+            config,
+            state: Mutex::new(State {
+                pending: Vec::new(),
+                group: Arc::new(Group::default()),
+                leader: false,
+                next_ticket: 0,
+                serving: 0,
+            }),
+            changed: Condvar::new(),
+        })
+    }
+
+    pub fn config(&self) -> GroupCommitConfig {
+        self.config
+    }
+
+    pub(crate) fn persistence(&self) -> &Persistence {
+        &self.persistence
+    }
+
+    /// Queues `mutations` and blocks until they have been durably written.
+    /// Mutations submitted in one call always land in the same group.
+    pub fn commit(&self, mutations: Vec<Data>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.pending.extend(mutations);
+        let group = Arc::clone(&state.group);
+
+        if state.leader {
+            if state.pending.len() >= self.config.max_batch_size {
+                self.changed.notify_all();
+            }
+            drop(state);
+            return group.wait();
+        }
+
+        state.leader = true;
+        let deadline = Instant::now() + self.config.max_delay;
+        while state.pending.len() < self.config.max_batch_size {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+
+        let batch = std::mem::take(&mut state.pending);
+        state.group = Arc::new(Group::default());
+        state.leader = false;
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+
+        // Wait for the groups that closed before this one to finish writing.
+        while state.serving != ticket {
+            state = self.changed.wait(state).unwrap();
+        }
+        drop(state);
+
+        let result = self.persistence.write_group(&batch);
+
+        let mut state = self.state.lock().unwrap();
+        state.serving += 1;
+        self.changed.notify_all();
+        drop(state);
+
+        group.finish(&result);
+        result
+    }
+}
+
+impl Group {
+    fn wait(&self) -> io::Result<()> {
+        let mut result = self.result.lock().unwrap();
+        while result.is_none() {
+            result = self.done.wait(result).unwrap();
+        }
+        match result.as_ref().unwrap() {
+            Ok(()) => Ok(()),
+            Err((kind, message)) => Err(io::Error::new(*kind, message.clone())),
+        }
+    }
+
+    fn finish(&self, result: &io::Result<()>) {
+        let mut slot = self.result.lock().unwrap();
+        *slot = Some(match result {
+            Ok(()) => Ok(()),
+            Err(e) => Err((e.kind(), e.to_string())),
+        });
+        self.done.notify_all();
+    }
+}
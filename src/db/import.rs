@@ -0,0 +1,240 @@
+use super::database::Database;
+use super::persistence::{check_key, Data, OperationType};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::io::{self, BufRead};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    /// Comma-separated values with a header row naming the columns.
+    Csv,
+    /// One JSON object per line.
+    JsonLines,
+}
+
+/// Which fields of a record become the partition key, sort key and value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldMapping {
+    pub partition_key: String,
+    pub sort_key: String,
+    /// The field holding the value. `None` stores the whole record as a JSON object.
+    pub value: Option<String>,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        FieldMapping {
+            partition_key: "partition_key".to_string(),
+            sort_key: "sort_key".to_string(),
+            value: Some("value".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub format: ImportFormat,
+    pub mapping: FieldMapping,
+    /// Records written per `Database::batch` call.
+    pub batch_size: usize,
+    /// At most this many errors are kept in the report; the rest are only counted.
+    pub max_reported_errors: usize,
+}
+
+impl ImportOptions {
+    pub fn new(format: ImportFormat) -> Self {
+        ImportOptions { format, mapping: FieldMapping::default(), batch_size: 1000, max_reported_errors: 100 }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LineError {
+    /// The line the record starts on, counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub failed: usize,
+    pub errors: Vec<LineError>,
+}
+
+/// Streams records from `reader` into `database`.
+///
+/// Only one batch of records is held in memory at a time. A record that can't be
+/// parsed, lacks a mapped field or has keys the database can't store is counted
+/// in the report and skipped; reading
+/// the input or writing to the database failing aborts the import, leaving the
+/// batches written so far in place.
+pub fn import<R: BufRead>(database: &mut Database, reader: R, options: &ImportOptions) -> io::Result<ImportReport> {
+    let mut report = ImportReport::default();
+    let mut batch = Vec::with_capacity(options.batch_size);
+    let mut records = Records::new(reader, options.format)?;
+
+    while let Some((line, record)) = records.next_record()? {
+        match record.and_then(|record| to_data(record, &options.mapping)) {
+            Ok(data) => batch.push(data),
+            Err(message) => {
+                report.failed += 1;
+                if report.errors.len() < options.max_reported_errors {
+                    report.errors.push(LineError { line, message });
+                }
+            },
+        }
+        if batch.len() >= options.batch_size.max(1) {
+            report.imported += batch.len();
+            database.batch(std::mem::take(&mut batch))?;
+        }
+    }
+    if !batch.is_empty() {
+        report.imported += batch.len();
+        database.batch(batch)?;
+    }
+    Ok(report)
+}
+
+fn to_data(record: Map<String, Value>, mapping: &FieldMapping) -> Result<Data, String> {
+    let partition_key = key_field(&record, &mapping.partition_key)?;
+    let sort_key = key_field(&record, &mapping.sort_key)?;
+    check_key(&partition_key, &sort_key).map_err(|e| e.to_string())?;
+    let value = match &mapping.value {
+        Some(field) => match record.get(field) {
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+            None => return Err(format!("missing field '{}'", field)),
+        },
+        None => Value::Object(record).to_string(),
+    };
+    Ok(Data { operation_type: OperationType::Insert, partition_key, sort_key, value })
+}
+
+/// Keys may be strings or numbers, and must not be empty.
+fn key_field(record: &Map<String, Value>, field: &str) -> Result<String, String> {
+    let key = match record.get(field) {
+        Some(Value::String(key)) => key.clone(),
+        Some(Value::Number(key)) => key.to_string(),
+        Some(_) => return Err(format!("field '{}' must be a string or a number", field)),
+        None => return Err(format!("missing field '{}'", field)),
+    };
+    if key.is_empty() {
+        return Err(format!("field '{}' is empty", field));
+    }
+    Ok(key)
+}
+
+/// A parsed record, or why it couldn't be parsed.
+type Record = Result<Map<String, Value>, String>;
+
+/// Yields records as JSON objects, paired with the line each starts on.
+struct Records<R> {
+    reader: R,
+    format: ImportFormat,
+    header: Vec<String>,
+    line: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    fn new(reader: R, format: ImportFormat) -> io::Result<Self> {
+        let mut records = Records { reader, format, header: Vec::new(), line: 0 };
+        if format == ImportFormat::Csv {
+            records.header = match records.read_csv_row()? {
+                Some(Ok(header)) => header,
+                Some(Err(message)) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("header: {}", message))),
+                None => Vec::new(),
+            };
+        }
+        Ok(records)
+    }
+
+    fn next_record(&mut self) -> io::Result<Option<(usize, Record)>> {
+        loop {
+            let start = self.line + 1;
+            let record = match self.format {
+                ImportFormat::JsonLines => {
+                    let line = match self.read_line()? {
+                        Some(line) => line,
+                        None => return Ok(None),
+                    };
+                    if line.trim_ascii().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_slice::<Value>(&line) {
+                        Ok(Value::Object(record)) => Ok(record),
+                        Ok(_) => Err("expected a JSON object".to_string()),
+                        Err(e) => Err(e.to_string()),
+                    }
+                },
+                ImportFormat::Csv => match self.read_csv_row()? {
+                    None => return Ok(None),
+                    Some(Ok(row)) if row.len() == 1 && row[0].is_empty() => continue,
+                    Some(Ok(row)) if row.len() != self.header.len() => {
+                        Err(format!("expected {} columns, found {}", self.header.len(), row.len()))
+                    },
+                    Some(Ok(row)) => Ok(self.header.iter().cloned().zip(row.into_iter().map(Value::String)).collect()),
+                    Some(Err(message)) => Err(message),
+                },
+            };
+            return Ok(Some((start, record)));
+        }
+    }
+
+    /// Reads one CSV row, which may span several lines when a quoted field contains
+    /// line breaks. Quotes inside quoted fields are escaped by doubling them.
+    fn read_csv_row(&mut self) -> io::Result<Option<Result<Vec<String>, String>>> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut read_any = false;
+        let mut invalid_utf8 = false;
+
+        loop {
+            let line = match self.read_line()? {
+                Some(line) => line,
+                None if !read_any => return Ok(None),
+                None if in_quotes => return Ok(Some(Err("unterminated quoted field".to_string()))),
+                None => break,
+            };
+            read_any = true;
+            // The row is still parsed to its end so that the next row starts in the
+            // right place, and is reported as invalid afterwards.
+            invalid_utf8 |= std::str::from_utf8(&line).is_err();
+            let line = String::from_utf8_lossy(&line);
+
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (in_quotes, c) {
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        field.push('"');
+                        chars.next();
+                    },
+                    (true, '"') => in_quotes = false,
+                    (true, c) => field.push(c),
+                    (false, '"') if field.is_empty() => in_quotes = true,
+                    (false, ',') => fields.push(std::mem::take(&mut field)),
+                    (false, '\r' | '\n') => {},
+                    (false, c) => field.push(c),
+                }
+            }
+            if !in_quotes {
+                break;
+            }
+        }
+        if invalid_utf8 {
+            return Ok(Some(Err("invalid UTF-8".to_string())));
+        }
+        fields.push(field);
+        Ok(Some(Ok(fields)))
+    }
+
+    /// Reads a raw line, so that invalid UTF-8 fails one record rather than the import.
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        if self.reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        Ok(Some(line))
+    }
+}
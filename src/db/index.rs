@@ -0,0 +1,144 @@
+//! Secondary indexes of tables.
+//!
+//! An index is a directory tree under `<table>/.indexes/<name>`, holding an empty
+//! file `<value>/<partition key>/<sort key>` for each item whose indexed attribute
+//! holds `<value>`, escaped into a file name. An item's entry is added and synced
+//! before the item is written, and removed once the item no longer holds the
+//! value, so the index never misses an item. Entries left behind by a crash in
+//! between are harmless: lookups check every entry against its item.
+
+use super::persistence::{is_reserved, sync_dir, Data, OperationType};
+use super::table::{attribute, IndexSchema};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+const INDEXES_DIR: &str = ".indexes";
+/// The longest file name most filesystems allow.
+const MAX_NAME_LEN: usize = 255;
+
+#[derive(Debug)]
+pub(crate) struct Index {
+    schema: IndexSchema,
+    path: PathBuf,
+}
+
+impl Index {
+    pub fn new(table: &Path, schema: IndexSchema) -> Self {
+        let path = table.join(INDEXES_DIR).join(&schema.name);
+        Index { schema, path }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.schema.name
+    }
+
+    /// The indexed value of an item. Items without the attribute, or with an empty
+    /// one, aren't indexed.
+    pub fn value_of(&self, data: &Data) -> Option<String> {
+        match data.operation_type {
+            OperationType::Delete => None,
+            _ => attribute(data, &self.schema.attribute).filter(|value| !value.is_empty()),
+        }
+    }
+
+    /// Adds the entry of an item about to be written, and syncs it.
+    pub fn add(&self, data: &Data) -> io::Result<()> {
+        let Some(value) = self.value_of(data) else {
+            return Ok(());
+        };
+        let name = escape(&value).ok_or_else(|| {
+            let message = format!("the value of {} is too long to index in {}", self.schema.attribute, self.schema.name);
+            io::Error::new(io::ErrorKind::InvalidInput, message)
+        })?;
+        let partition_path = self.path.join(name).join(&data.partition_key);
+        let path = partition_path.join(&data.sort_key);
+        if path.exists() {
+            return Ok(());
+        }
+        // The directories are created again if `remove` takes them away meanwhile.
+        loop {
+            let created: Vec<&Path> = partition_path.ancestors().take(4).filter(|dir| !dir.exists()).collect();
+            fs::create_dir_all(&partition_path)?;
+            match OpenOptions::new().write(true).create(true).truncate(false).open(&path) {
+                Ok(_) => {},
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+            sync_dir(&partition_path)?;
+            for dir in created {
+                sync_dir(dir.parent().expect("index directories have a parent"))?;
+            }
+            return Ok(());
+        }
+    }
+
+    /// Removes the entry of `previous` once `data` has replaced or deleted it,
+    /// unless `data` holds the same value.
+    pub fn remove(&self, previous: &Data, data: &Data) -> io::Result<()> {
+        let Some(value) = self.value_of(previous).filter(|value| Some(value) != self.value_of(data).as_ref()) else {
+            return Ok(());
+        };
+        let Some(name) = escape(&value) else {
+            return Ok(());
+        };
+        let value_path = self.path.join(name);
+        let partition_path = value_path.join(&previous.partition_key);
+        match fs::remove_file(partition_path.join(&previous.sort_key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {},
+        }
+        // Directories other entries were added to meanwhile stay.
+        let _ = fs::remove_dir(&partition_path).and_then(|_| fs::remove_dir(&value_path));
+        Ok(())
+    }
+
+    /// The keys of the items whose entries say they hold `value`, ordered by
+    /// partition key, then sort key.
+    pub fn lookup(&self, value: &str) -> io::Result<Vec<(String, String)>> {
+        let Some(name) = escape(value) else {
+            return Ok(Vec::new());
+        };
+        let value_path = self.path.join(name);
+        let mut keys = Vec::new();
+        for partition_key in list(&value_path)? {
+            for sort_key in list(&value_path.join(&partition_key))? {
+                keys.push((partition_key.clone(), sort_key));
+            }
+        }
+        Ok(keys)
+    }
+}
+
+/// The names in a directory, sorted, or none if it doesn't exist.
+fn list(path: &Path) -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if !is_reserved(&name) {
+            names.push(name);
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// The file name of an indexed value: `%`, path separators, NUL and a leading `.`
+/// are escaped as `%XX`, so different values get different names. `None` for
+/// values whose name would be too long.
+fn escape(value: &str) -> Option<String> {
+    let mut name = String::with_capacity(value.len());
+    for (i, c) in value.char_indices() {
+        match c {
+            '%' | '/' | '\\' | '\0' => name.push_str(&format!("%{:02X}", c as u32)),
+            '.' if i == 0 => name.push_str("%2E"),
+            c => name.push(c),
+        }
+    }
+    (name.len() <= MAX_NAME_LEN).then_some(name)
+}
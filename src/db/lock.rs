@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
+
+const LOCK_FILE: &str = ".lock";
+
+/// Returned, wrapped in an `io::Error` of kind `WouldBlock`, when another process
+/// has the keyspace open for writing. Use `LockError::of` to get it back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockError {
+    pub path: PathBuf,
+    /// The process holding the lock, as it recorded in the lock file.
+    pub pid: Option<u32>,
+}
+
+impl LockError {
+    /// The lock conflict an I/O error reports, if it reports one.
+    pub fn of(error: &io::Error) -> Option<&LockError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.pid {
+            Some(pid) => write!(f, "{} is locked by process {}; open it read-only to read it meanwhile", self.path.display(), pid),
+            None => write!(f, "{} is locked by another process; open it read-only to read it meanwhile", self.path.display()),
+        }
+    }
+}
+
+impl Error for LockError {}
+
+/// The exclusive lock a writer holds on a keyspace directory: an advisory lock on
+/// its `.lock` file, which also records the writer's process id.
+///
+/// Handles opened on the same directory within one process share the lock. The
+/// last one to close empties the file, so a process id left in an unlocked file
+/// is a stale lock: its writer exited without closing the keyspace, typically by
+/// crashing. The next writer takes the lock over and reports the stale id.
+#[derive(Debug)]
+pub(crate) struct DirLock {
+    file: File,
+    stale_pid: Option<u32>,
+}
+
+impl DirLock {
+    /// Locks the keyspace at `root`, or shares the lock this process already holds.
+    pub fn acquire(root: &Path) -> io::Result<Arc<DirLock>> {
+        let root = fs::canonicalize(root)?;
+        let mut held = held();
+        held.retain(|_, lock| lock.strong_count() > 0);
+        if let Some(lock) = held.get(&root).and_then(Weak::upgrade) {
+            return Ok(lock);
+        }
+
+        let path = root.join(LOCK_FILE);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+        let locked = match file.try_lock() {
+            Ok(()) => true,
+            Err(TryLockError::WouldBlock) => return Err(locked_by(&root, read_pid(&mut file)?)),
+            // Without advisory locks, the recorded process is trusted while it runs.
+            Err(TryLockError::Error(e)) if e.kind() == io::ErrorKind::Unsupported => false,
+            Err(TryLockError::Error(e)) => return Err(e),
+        };
+        let recorded = read_pid(&mut file)?;
+        if let Some(pid) = recorded.filter(|pid| !locked && *pid != process::id() && is_running(*pid)) {
+            return Err(locked_by(&root, Some(pid)));
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", process::id())?;
+        file.sync_data()?;
+        let lock = Arc::new(DirLock { file, stale_pid: recorded.filter(|pid| *pid != process::id()) });
+        held.insert(root, Arc::downgrade(&lock));
+        Ok(lock)
+    }
+
+    /// Stops sharing the lock held on the keyspace at `root`, which is being
+    /// removed, with handles opened there later.
+    pub fn forget(root: &Path) {
+        held().remove(root);
+    }
+
+    /// The process id a writer that didn't close the keyspace left behind, if the
+    /// lock was taken over from one.
+    pub fn stale_pid(&self) -> Option<u32> {
+        self.stale_pid
+    }
+}
+
+impl Drop for DirLock {
+    /// Marks the keyspace as closed cleanly. The advisory lock goes with the file.
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// The locks this process holds, by canonical keyspace path.
+fn held() -> MutexGuard<'static, HashMap<PathBuf, Weak<DirLock>>> {
+    static HELD: OnceLock<Mutex<HashMap<PathBuf, Weak<DirLock>>>> = OnceLock::new();
+    HELD.get_or_init(Default::default).lock().unwrap()
+}
+
+fn locked_by(root: &Path, pid: Option<u32>) -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, LockError { path: root.to_path_buf(), pid })
+}
+
+fn read_pid(file: &mut File) -> io::Result<Option<u32>> {
+    let mut contents = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut contents)?;
+    Ok(contents.trim().parse().ok())
+}
+
+/// Whether a process is running. Only Linux can tell; elsewhere every process is
+/// assumed to be.
+fn is_running(pid: u32) -> bool {
+    !cfg!(target_os = "linux") || Path::new("/proc").join(pid.to_string()).exists()
+}
@@ -0,0 +1,149 @@
+use super::database::Database;
+use super::persistence::{check_key, check_key_part, Data, OperationType};
+use super::record::{Codec, CorruptionError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CHECKPOINT_FILE: &str = ".migration.json";
+const MIGRATION_BATCH_SIZE: usize = 1000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct CorruptFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct MigrationReport {
+    pub migrated: usize,
+    /// Partitions skipped because an earlier, interrupted run already migrated them.
+    pub resumed_after: usize,
+    pub corrupt: Vec<CorruptFile>,
+}
+
+/// Progress of a migration, stored in the destination so an interrupted run can resume.
+#[derive(Serialize, Deserialize, Debug)]
+struct Checkpoint {
+    source: PathBuf,
+    /// Partitions are migrated in sorted order; every partition up to and including
+    /// this one has been written.
+    last_partition: Option<String>,
+    complete: bool,
+}
+
+/// Copies a directory written in the legacy layout, one `<partition>/<sort>` JSON
+/// file per item, into `destination`, which writes in its own format.
+///
+/// Every file is checked to be a `Data` record whose keys match its location.
+/// Files that fail are reported and left out; the rest of the migration carries on.
+/// Progress is checkpointed after each partition, so running the migration again
+/// after an interruption continues where it stopped, and running it after it has
+/// completed does nothing.
+pub fn migrate(source: &Path, destination: &mut Database) -> io::Result<MigrationReport> {
+    // The checkpoint is written even when there is nothing to copy.
+    destination.persistence().check_writable()?;
+    let source = source.canonicalize()?;
+    if destination.path().canonicalize()? == source {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot migrate a directory into itself"));
+    }
+
+    let checkpoint_path = destination.path().join(CHECKPOINT_FILE);
+    let mut checkpoint = match fs::read_to_string(&checkpoint_path) {
+        Ok(contents) => serde_json::from_str::<Checkpoint>(&contents)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Checkpoint { source: source.clone(), last_partition: None, complete: false },
+        Err(e) => return Err(e),
+    };
+    if checkpoint.source != source {
+        let message = format!("destination holds a migration from {}", checkpoint.source.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+    }
+
+    let mut report = MigrationReport::default();
+    if checkpoint.complete {
+        return Ok(report);
+    }
+
+    let mut partitions = Vec::new();
+    for entry in fs::read_dir(&source)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            partitions.push(entry.file_name().to_string_lossy().into_owned());
+        }
+    }
+    partitions.sort();
+
+    for partition_key in partitions {
+        if checkpoint.last_partition.as_ref().is_some_and(|last| &partition_key <= last) {
+            report.resumed_after += 1;
+            continue;
+        }
+        // Keys the database can't store, such as names starting with a dot, are
+        // reported rather than left behind unnoticed.
+        if let Err(e) = check_key_part(&partition_key) {
+            report.corrupt.push(CorruptFile { path: source.join(&partition_key), reason: e.to_string() });
+            continue;
+        }
+
+        let mut batch = Vec::new();
+        for entry in fs::read_dir(source.join(&partition_key))? {
+            let entry = entry?;
+            let sort_key = entry.file_name().to_string_lossy().into_owned();
+            match read_legacy_record(&entry.path(), &partition_key, &sort_key) {
+                Ok(data) => batch.push(data),
+                Err(reason) => report.corrupt.push(CorruptFile { path: entry.path(), reason }),
+            }
+            if batch.len() == MIGRATION_BATCH_SIZE {
+                report.migrated += batch.len();
+                destination.batch(std::mem::take(&mut batch))?;
+            }
+        }
+        report.migrated += batch.len();
+        destination.batch(batch)?;
+
+        checkpoint.last_partition = Some(partition_key);
+        save_checkpoint(&checkpoint_path, &checkpoint)?;
+    }
+
+    checkpoint.complete = true;
+    save_checkpoint(&checkpoint_path, &checkpoint)?;
+    Ok(report)
+}
+
+/// Reads one legacy item file, describing what is wrong with it if it can't be used.
+pub(crate) fn read_legacy_record(path: &Path, partition_key: &str, sort_key: &str) -> Result<Data, String> {
+    check_key(partition_key, sort_key).map_err(|e| e.to_string())?;
+    let metadata = fs::metadata(path).map_err(|e| e.to_string())?;
+    if !metadata.is_file() {
+        return Err("not a file".to_string());
+    }
+    let contents = fs::read(path).map_err(|e| e.to_string())?;
+    let data = Codec::default().decode(path, &contents).map_err(|e| reason(&e))?;
+
+    if data.partition_key != partition_key || data.sort_key != sort_key {
+        return Err(format!(
+            "record is for ({}, {}) but stored under ({}, {})",
+            data.partition_key, data.sort_key, partition_key, sort_key
+        ));
+    }
+    match data.operation_type {
+        OperationType::Delete => Err("record is a delete".to_string()),
+        _ => Ok(Data { operation_type: OperationType::Insert, ..data }),
+    }
+}
+
+/// What is wrong with a file, without the path that `CorruptFile` records anyway.
+pub(crate) fn reason(error: &io::Error) -> String {
+    match CorruptionError::of(error) {
+        Some(corruption) => corruption.reason.clone(),
+        None => error.to_string(),
+    }
+}
+
+/// Replaces the checkpoint atomically, so a crash leaves either the old or the new one.
+fn save_checkpoint(path: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    let staging = path.with_extension("tmp");
+    fs::write(&staging, serde_json::to_string(checkpoint)?)?;
+    fs::rename(staging, path)
+}
@@ -0,0 +1,125 @@
+use super::object_store::split_segment;
+use super::persistence::{Data, OperationType};
+use super::record::{self, Codec};
+use memmap2::Mmap;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io;
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+
+/// An item read from a `MappedSegment`. The strings borrow from the mapping when
+/// the record is neither compressed nor encrypted and they hold no JSON escapes,
+/// and are decoded copies otherwise.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ItemRef<'a> {
+    #[serde(borrow)]
+    pub partition_key: Cow<'a, str>,
+    #[serde(borrow)]
+    pub sort_key: Cow<'a, str>,
+    #[serde(borrow)]
+    pub value: Cow<'a, str>,
+}
+
+impl ItemRef<'_> {
+    pub fn into_data(self) -> Data {
+        Data {
+            operation_type: OperationType::Insert,
+            partition_key: self.partition_key.into_owned(),
+            sort_key: self.sort_key.into_owned(),
+            value: self.value.into_owned(),
+        }
+    }
+}
+
+/// A segment object memory-mapped from the local filesystem, opened with
+/// `ObjectStoreBackend::map_partition`.
+///
+/// Every record's checksum is checked once when the segment is mapped, and an
+/// index of sort keys is kept, so a lookup is a binary search over the mapping with
+/// no system calls. Reads then decode records without checking them again, parsing
+/// plain ones in place.
+#[derive(Debug)]
+pub struct MappedSegment {
+    path: PathBuf,
+    map: Mmap,
+    codec: Codec,
+    /// The sort key and byte range of every record, in sort key order.
+    index: Vec<(String, Range<usize>)>,
+}
+
+impl MappedSegment {
+    /// Maps the segment of `partition_key` at `path`, which must only ever be
+    /// replaced, never written in place.
+    pub(crate) fn open(path: &Path, partition_key: &str, codec: Codec) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: object files are replaced by renaming a new file over them, so the
+        // mapped file is never modified or truncated while the mapping lives.
+        let map = unsafe { Mmap::map(&file)? };
+        let mut segment = MappedSegment { path: path.to_path_buf(), map, codec, index: Vec::new() };
+
+        let base = segment.map.as_ptr() as usize;
+        let mut index = Vec::new();
+        for record in split_segment(path, &segment.map)? {
+            let start = record.as_ptr() as usize - base;
+            let range = start..start + record.len();
+            record::verify(path, record)?;
+            let item = segment.item(range.clone())?;
+            if item.partition_key != partition_key {
+                let reason = format!("record of partition {} found in segment of {}", item.partition_key, partition_key);
+                return Err(record::corrupt(path, reason));
+            }
+            index.push((item.sort_key.into_owned(), range));
+        }
+        index.sort_by(|(a, _), (b, _)| a.cmp(b));
+        segment.index = index;
+        Ok(segment)
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn get(&self, sort_key: &str) -> io::Result<Option<ItemRef<'_>>> {
+        match self.index.binary_search_by(|(key, _)| key.as_str().cmp(sort_key)) {
+            Ok(i) => self.item(self.index[i].1.clone()).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// The items with sort keys between `from` and `to`, in sort key order.
+    pub fn range<'a>(&'a self, from: Bound<&str>, to: Bound<&str>) -> impl Iterator<Item = io::Result<ItemRef<'a>>> + 'a {
+        let start = match from {
+            Bound::Included(key) => self.index.partition_point(|(k, _)| k.as_str() < key),
+            Bound::Excluded(key) => self.index.partition_point(|(k, _)| k.as_str() <= key),
+            Bound::Unbounded => 0,
+        };
+        let end = match to {
+            Bound::Included(key) => self.index.partition_point(|(k, _)| k.as_str() <= key),
+            Bound::Excluded(key) => self.index.partition_point(|(k, _)| k.as_str() < key),
+            Bound::Unbounded => self.index.len(),
+        };
+        self.index[start..end.max(start)].iter().map(|(_, range)| self.item(range.clone()))
+    }
+
+    /// Every item, in sort key order.
+    pub fn iter(&self) -> impl Iterator<Item = io::Result<ItemRef<'_>>> + '_ {
+        self.range(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    fn item(&self, range: Range<usize>) -> io::Result<ItemRef<'_>> {
+        let bytes = &self.map[range];
+        match record::plain_json(&self.path, bytes)? {
+            Some(json) => serde_json::from_slice(json).map_err(|e| record::corrupt(&self.path, format!("not a valid record: {}", e))),
+            None => {
+                let data = self.codec.decode_verified(&self.path, bytes)?;
+                Ok(ItemRef { partition_key: data.partition_key.into(), sort_key: data.sort_key.into(), value: data.value.into() })
+            },
+        }
+    }
+}
@@ -1,8 +1,40 @@
 mod store;
+mod backend;
 mod persistence;
 mod database;
+mod scan;
+mod group_commit;
+mod table;
+mod index;
+mod import;
+mod export;
+mod dump;
+mod migrate;
+mod fsck;
+mod record;
+mod encryption;
+mod bloom;
+mod lock;
+mod object_store;
+mod mmap;
+mod tiering;
 
 pub use self::store::Store;
-pub use self::persistence::{Persistence, Data, OperationType};
-pub use self::database::Database;
+pub use self::backend::StorageBackend;
+pub use self::persistence::{Persistence, Data, DataIter, OperationType, ReadOnlyError, Stats};
+pub use self::database::{Database, BatchGetResult};
 pub use self::database::InMemoryDatabase;
+pub use self::scan::{Attribute, Filter, segment_of};
+pub use self::group_commit::{GroupCommit, GroupCommitConfig};
+pub use self::table::{IndexSchema, KeySchema, TableDescription, TableSettings, TimeToLive};
+pub use self::import::{import, FieldMapping, ImportFormat, ImportOptions, ImportReport, LineError};
+pub use self::export::{export, ExportFormat, ExportWriter, Selection};
+pub use self::dump::{restore, restore_in_memory, write_dump, write_dump_in_memory, DumpEntry, DumpReader, DUMP_VERSION};
+pub use self::migrate::{migrate, CorruptFile, MigrationReport};
+pub use self::fsck::{fsck, FsckMode, FsckReport};
+pub use self::record::{Compression, CorruptionError};
+pub use self::lock::LockError;
+pub use self::encryption::{EncryptionKey, Keyring, Reencryption};
+pub use self::mmap::{ItemRef, MappedSegment};
+pub use self::object_store::{LocalObjectStore, ObjectStore, ObjectStoreBackend};
+pub use self::tiering::{TieredBackend, TieringPolicy};
@@ -0,0 +1,344 @@
+use super::backend::StorageBackend;
+use super::encryption::Keyring;
+use super::mmap::MappedSegment;
+use super::persistence::{check_key, check_key_part, Data, OperationType};
+use super::record::{self, Codec, Compression};
+use super::scan::segment_of;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SEGMENT_SUFFIX: &str = ".seg";
+
+/// A flat namespace of immutable objects, as offered by S3 and compatible stores.
+///
+/// Objects are only ever written whole: `put` replaces any object under the key
+/// atomically, so readers see either the old or the new bytes.
+pub trait ObjectStore: Send + Sync {
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()>;
+
+    /// The object under `key`, or `None` if there is none.
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Removes an object. Removing a missing object is not an error.
+    fn delete(&self, key: &str) -> io::Result<()>;
+
+    /// The keys of every object starting with `prefix`, in no particular order.
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>>;
+
+    /// Where the object under `key` is kept on the local filesystem, for stores that
+    /// keep objects there. The file must only ever be replaced, never written in
+    /// place, as readers memory-map it.
+    fn local_path(&self, _key: &str) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// An `ObjectStore` kept in a local directory, one file per object. Slashes in
+/// keys become subdirectories. Stands in for a remote store in tests and
+/// single-machine setups.
+#[derive(Debug, Clone)]
+pub struct LocalObjectStore {
+    root: PathBuf,
+}
+
+impl LocalObjectStore {
+    pub fn new(root: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&root)?;
+        Ok(LocalObjectStore { root })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn list_dir(&self, dir: &Path, prefix: &str, keys: &mut Vec<String>) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Dot files are objects being written by `put`.
+            if name.starts_with('.') {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                self.list_dir(&entry.path(), prefix, keys)?;
+                continue;
+            }
+            let key = entry.path().strip_prefix(&self.root).unwrap().to_string_lossy().replace('\\', "/");
+            if key.starts_with(prefix) {
+                keys.push(key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ObjectStore for LocalObjectStore {
+    fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let path = self.root.join(key);
+        let dir = path.parent().unwrap_or(&self.root);
+        fs::create_dir_all(dir)?;
+        let staging = dir.join(format!(".{}.tmp", path.file_name().unwrap_or_default().to_string_lossy()));
+        fs::write(&staging, bytes)?;
+        fs::rename(staging, path)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&self, key: &str) -> io::Result<()> {
+        match fs::remove_file(self.root.join(key)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        self.list_dir(&self.root, prefix, &mut keys)?;
+        Ok(keys)
+    }
+
+    fn local_path(&self, key: &str) -> Option<PathBuf> {
+        Some(self.root.join(key))
+    }
+}
+
+/// A `StorageBackend` keeping each partition as one immutable segment object,
+/// `<partition key>.seg`, in an `ObjectStore`.
+///
+/// A segment holds the partition's records in sort key order, each framed by its
+/// u32 little-endian length and encoded like an item file, so every record carries
+/// its own checksum and can be compressed and encrypted. Writes replace the whole
+/// segment, which suits partitions that are rarely written, such as the cold
+/// partitions a `TieredBackend` moves here. Each segment is replaced atomically; a
+/// batch spanning several partitions is not.
+///
+/// When the store keeps objects on the local filesystem, reads go through
+/// memory-mapped segments, kept until this backend replaces them. Writes made to
+/// the store through another handle aren't seen for partitions already mapped.
+#[derive(Debug)]
+pub struct ObjectStoreBackend<S: ObjectStore> {
+    store: S,
+    codec: Codec,
+    /// Serializes the read-modify-write of segments.
+    write_lock: Mutex<()>,
+    /// Mapped segments by partition key, `None` for partitions without one.
+    mapped: Mutex<HashMap<String, Option<Arc<MappedSegment>>>>,
+}
+
+impl<S: ObjectStore> ObjectStoreBackend<S> {
+    pub fn new(store: S) -> Self {
+        ObjectStoreBackend::with_codec(store, Codec::default())
+    }
+
+    pub(crate) fn with_codec(store: S, codec: Codec) -> Self {
+        ObjectStoreBackend { store, codec, write_lock: Mutex::new(()), mapped: Mutex::new(HashMap::new()) }
+    }
+
+    /// Compresses the records of segments written from now on.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.codec.compression = compression;
+        self
+    }
+
+    /// Encrypts the records of segments written from now on with the active key of
+    /// `keyring`, and decrypts records with any of its keys.
+    pub fn with_keyring(mut self, keyring: Arc<Keyring>) -> Self {
+        self.codec.keyring = Some(keyring);
+        self
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Memory-maps the segment of a partition, or returns the mapping already
+    /// open. `None` if the partition has no segment. Fails with `Unsupported` if
+    /// the store doesn't keep objects on the local filesystem.
+    pub fn map_partition(&self, partition_key: &str) -> io::Result<Option<Arc<MappedSegment>>> {
+        check_key_part(partition_key)?;
+        let path = self.store.local_path(&segment_key(partition_key)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Unsupported, "the object store doesn't keep objects on the local filesystem")
+        })?;
+        // Mapped with the cache locked, so a segment replaced meanwhile is either
+        // mapped after the replacement or evicted by it.
+        let mut mapped = self.mapped.lock().unwrap();
+        if let Some(segment) = mapped.get(partition_key) {
+            return Ok(segment.clone());
+        }
+        let segment = match MappedSegment::open(&path, partition_key, self.codec.clone()) {
+            Ok(segment) => Some(Arc::new(segment)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        mapped.insert(partition_key.to_string(), segment.clone());
+        Ok(segment)
+    }
+
+    /// The items of a partition by sort key. A partition without a segment is empty.
+    pub(crate) fn load_segment(&self, partition_key: &str) -> io::Result<BTreeMap<String, Data>> {
+        let key = segment_key(partition_key);
+        let bytes = match self.store.get(&key)? {
+            Some(bytes) => bytes,
+            None => return Ok(BTreeMap::new()),
+        };
+        let path = Path::new(&key);
+        let mut items = BTreeMap::new();
+        for record in split_segment(path, &bytes)? {
+            let data = self.codec.decode(path, record)?;
+            if data.partition_key != partition_key {
+                let reason = format!("record of partition {} found in segment of {}", data.partition_key, partition_key);
+                return Err(record::corrupt(path, reason));
+            }
+            items.insert(data.sort_key.clone(), data);
+        }
+        Ok(items)
+    }
+
+    /// Replaces the segment of a partition, removing it when `items` is empty.
+    pub(crate) fn save_segment(&self, partition_key: &str, items: &BTreeMap<String, Data>) -> io::Result<()> {
+        let key = segment_key(partition_key);
+        if items.is_empty() {
+            return self.store.delete(&key);
+        }
+        let mut bytes = Vec::new();
+        for data in items.values() {
+            let record = self.codec.encode(data)?;
+            bytes.extend_from_slice(&(record.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&record);
+        }
+        self.store.put(&key, &bytes)?;
+        self.mapped.lock().unwrap().remove(partition_key);
+        Ok(())
+    }
+
+    pub(crate) fn delete_segment(&self, partition_key: &str) -> io::Result<()> {
+        self.store.delete(&segment_key(partition_key))?;
+        self.mapped.lock().unwrap().remove(partition_key);
+        Ok(())
+    }
+
+    /// The items of a partition with sort keys between `from` and `to`, read
+    /// through the mapped segment when the store allows it.
+    fn read_range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<Vec<Data>> {
+        check_key_part(partition_key)?;
+        if self.store.local_path(&segment_key(partition_key)).is_none() {
+            let items = self.load_segment(partition_key)?;
+            return Ok(items.into_values().filter(|data| (from, to).contains(data.sort_key.as_str())).collect());
+        }
+        match self.map_partition(partition_key)? {
+            Some(segment) => segment.range(from, to).map(|item| item.map(|item| item.into_data())).collect(),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+impl<S: ObjectStore> StorageBackend for ObjectStoreBackend<S> {
+    /// Segments are fetched whole, so results are read before they are returned.
+    type Iter = std::vec::IntoIter<io::Result<Data>>;
+
+    fn get(&self, partition_key: &str, sort_key: &str) -> io::Result<Option<Data>> {
+        Ok(self.read_range(partition_key, Bound::Included(sort_key), Bound::Included(sort_key))?.pop())
+    }
+
+    fn get_batch(&self, partition_key: &str, sort_keys: &[String]) -> io::Result<Vec<Option<Data>>> {
+        let mut items: HashMap<String, Data> = self
+            .read_range(partition_key, Bound::Unbounded, Bound::Unbounded)?
+            .into_iter()
+            .map(|data| (data.sort_key.clone(), data))
+            .collect();
+        Ok(sort_keys.iter().map(|sort_key| items.remove(sort_key)).collect())
+    }
+
+    fn put(&self, data: &Data) -> io::Result<()> {
+        check_key(&data.partition_key, &data.sort_key)?;
+        let _guard = self.write_lock.lock().unwrap();
+        let mut items = self.load_segment(&data.partition_key)?;
+        items.insert(data.sort_key.clone(), data.clone());
+        self.save_segment(&data.partition_key, &items)
+    }
+
+    fn delete(&self, partition_key: &str, sort_key: &str) -> io::Result<()> {
+        check_key(partition_key, sort_key)?;
+        let _guard = self.write_lock.lock().unwrap();
+        let mut items = self.load_segment(partition_key)?;
+        if items.remove(sort_key).is_none() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "item not found"));
+        }
+        self.save_segment(partition_key, &items)
+    }
+
+    fn range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<Self::Iter> {
+        Ok(self.read_range(partition_key, from, to)?.into_iter().map(Ok).collect::<Vec<_>>().into_iter())
+    }
+
+    fn scan(&self, segment: usize, total_segments: usize) -> io::Result<Self::Iter> {
+        assert!(segment < total_segments, "segment must be less than total_segments");
+        let mut items = Vec::new();
+        for partition_key in self.list_partitions()? {
+            if segment_of(&partition_key, total_segments) == segment {
+                items.extend(self.read_range(&partition_key, Bound::Unbounded, Bound::Unbounded)?.into_iter().map(Ok));
+            }
+        }
+        Ok(items.into_iter())
+    }
+
+    fn list_partitions(&self) -> io::Result<Vec<String>> {
+        let keys = self.store.list("")?;
+        Ok(keys.iter().filter_map(|key| key.strip_suffix(SEGMENT_SUFFIX)).map(str::to_string).collect())
+    }
+
+    /// Rewrites each partition the batch touches once.
+    fn write_batch(&self, batch: &[Data]) -> io::Result<()> {
+        let mut by_partition: HashMap<&str, Vec<&Data>> = HashMap::new();
+        for data in batch {
+            check_key(&data.partition_key, &data.sort_key)?;
+            by_partition.entry(&data.partition_key).or_default().push(data);
+        }
+        let _guard = self.write_lock.lock().unwrap();
+        for (partition_key, group) in by_partition {
+            let mut items = self.load_segment(partition_key)?;
+            for data in group {
+                match data.operation_type {
+                    OperationType::Delete => items.remove(&data.sort_key),
+                    _ => items.insert(data.sort_key.clone(), data.clone()),
+                };
+            }
+            self.save_segment(partition_key, &items)?;
+        }
+        Ok(())
+    }
+
+    /// Segments are written when each write returns.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Splits a segment into its records.
+pub(crate) fn split_segment<'a>(path: &Path, mut bytes: &'a [u8]) -> io::Result<Vec<&'a [u8]>> {
+    let mut records = Vec::new();
+    while let Some((len, rest)) = bytes.split_first_chunk::<4>() {
+        let len = u32::from_le_bytes(*len) as usize;
+        let record = rest.get(..len).ok_or_else(|| record::corrupt(path, "segment is truncated"))?;
+        records.push(record);
+        bytes = &rest[len..];
+    }
+    if !bytes.is_empty() {
+        return Err(record::corrupt(path, "segment is truncated"));
+    }
+    Ok(records)
+}
+
+fn segment_key(partition_key: &str) -> String {
+    format!("{}{}", partition_key, SEGMENT_SUFFIX)
+}
@@ -1,8 +1,24 @@
-use std::fs::{self, File};
-use std::io::{self, Write, Read};
-use std::path::PathBuf;
-use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, Weak};
 use serde::{Serialize, Deserialize};
+use super::backend::StorageBackend;
+use super::bloom::{BloomFilters, DEFAULT_FALSE_POSITIVE_RATE};
+use super::lock::DirLock;
+use super::scan::segment_of;
+use super::encryption::{decode_hex, encode_hex, Keyring};
+use super::record::{self, Codec, Compression};
+
+const SETTINGS_FILE: &str = ".settings.json";
+/// Once the journal grows past this, a group commit checkpoints it.
+const CHECKPOINT_JOURNAL_LEN: u64 = 4 << 20;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum OperationType {
@@ -20,79 +36,858 @@ pub struct Data {
     pub value: String,
 }
 
+/// Size of the data stored in one keyspace, as reported by `Persistence::stats`.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub partitions: usize,
+    pub items: usize,
+    /// Total size of the item files.
+    pub disk_bytes: u64,
+    /// What `disk_bytes` would be if no record were compressed.
+    pub uncompressed_bytes: u64,
+    /// `uncompressed_bytes / disk_bytes`, 1 when nothing is compressed.
+    pub compression_ratio: f64,
+}
+
+/// Returned, wrapped in an `io::Error` of kind `PermissionDenied`, for a write
+/// through a database opened read-only. Use `ReadOnlyError::of` to get it back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadOnlyError {
+    pub path: PathBuf,
+}
+
+impl ReadOnlyError {
+    /// The rejected write an I/O error reports, if it reports one.
+    pub fn of(error: &io::Error) -> Option<&ReadOnlyError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for ReadOnlyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is open read-only", self.path.display())
+    }
+}
+
+impl Error for ReadOnlyError {}
+
+/// Settings of a keyspace, kept in its directory so every handle opened on it
+/// applies them.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Settings {
+    #[serde(default)]
+    compression: Compression,
+    #[serde(default)]
+    bloom_false_positive_rate: Option<f64>,
+}
+
 #[derive(Debug)]
 pub struct Persistence {
     path: PathBuf,
-    
+    codec: Mutex<Codec>,
+    /// Set while the journal holds encrypted mutations that couldn't be replayed
+    /// for lack of a keyring.
+    recovery_pending: AtomicBool,
+    /// `None` for the Bloom filters, lock and journal of a keyspace opened read-only.
+    filters: Option<Arc<BloomFilters>>,
+    lock: Option<Arc<DirLock>>,
+    unsynced: Option<Arc<Mutex<Unsynced>>>,
+}
+
+/// The item files and directories that mutations kept in the journal were applied
+/// to without syncing. Shared by the handles a process has open on a keyspace, as
+/// they share its journal, and locked while the journal or its entries are written.
+#[derive(Debug, Default)]
+struct Unsynced {
+    items: BTreeSet<PathBuf>,
+    directories: BTreeSet<PathBuf>,
+    journal_len: u64,
+    /// Set by `Persistence::retire` once the keyspace is being removed.
+    removed: bool,
+}
+
+impl Unsynced {
+    fn shared(root: &Path) -> io::Result<Arc<Mutex<Unsynced>>> {
+        let root = fs::canonicalize(root)?;
+        let mut open = Unsynced::open_journals();
+        open.retain(|_, unsynced| unsynced.strong_count() > 0);
+        if let Some(unsynced) = open.get(&root).and_then(Weak::upgrade) {
+            return Ok(unsynced);
+        }
+        let unsynced = Arc::new(Mutex::new(Unsynced::default()));
+        open.insert(root, Arc::downgrade(&unsynced));
+        Ok(unsynced)
+    }
+
+    /// The journal state of the keyspaces open in this process, by canonical path.
+    fn open_journals() -> MutexGuard<'static, HashMap<PathBuf, Weak<Mutex<Unsynced>>>> {
+        static OPEN: OnceLock<Mutex<HashMap<PathBuf, Weak<Mutex<Unsynced>>>>> = OnceLock::new();
+        OPEN.get_or_init(Default::default).lock().unwrap()
+    }
+
+    fn record(&mut self, root: &Path, data: &Data) {
+        let partition_path = root.join(&data.partition_key);
+        if data.operation_type != OperationType::Delete {
+            self.items.insert(partition_path.join(&data.sort_key));
+        }
+        self.directories.insert(partition_path);
+    }
 }
 
 impl Persistence {
+    /// Opens the keyspace at `path`, creating the directory if needed. Panics if it
+    /// can't be opened; `open` returns the error instead.
     pub fn new(path: PathBuf) -> Self {
-        // If the directory doesn't exist, create it
-        if !path.exists() {
-            fs::create_dir_all(&path).expect("Failed to create directory");
+        let display = path.display().to_string();
+        Persistence::open(path).unwrap_or_else(|e| panic!("Failed to open {}: {}", display, e))
+    }
+
+    /// Opens the keyspace at `path` for reading and writing, creating the directory
+    /// if needed. Takes the keyspace's lock, failing with a `LockError` if another
+    /// process holds it, and replays the journal of an interrupted group commit.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&path)?;
+        let lock = DirLock::acquire(&path)?;
+        let settings = read_settings(&path)?;
+        let codec = Codec { compression: settings.compression, keyring: None };
+        let false_positive_rate = settings.bloom_false_positive_rate.unwrap_or(DEFAULT_FALSE_POSITIVE_RATE);
+        let filters = BloomFilters::open(&path, false_positive_rate);
+        let unsynced = Unsynced::shared(&path)?;
+        let persistence = Persistence {
+            path,
+            codec: Mutex::new(codec),
+            recovery_pending: AtomicBool::new(false),
+            filters: Some(filters),
+            lock: Some(lock),
+            unsynced: Some(unsynced),
+        };
+        match persistence.recover() {
+            // Replayed by `set_keyring` once the keys are known.
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => persistence.recovery_pending.store(true, Ordering::SeqCst),
+            result => result?,
+        }
+        Ok(persistence)
+    }
+
+    /// Opens an existing keyspace for reading only, without taking its lock, so a
+    /// writer in another process can keep it open. Nothing in the directory is
+    /// ever created or changed through it: every write fails with a
+    /// `ReadOnlyError`. The journal is left to the writer, so mutations of a group
+    /// commit it hasn't applied yet aren't seen, and Bloom filters aren't used as
+    /// they can't see the writer's writes.
+    pub fn open_read_only(path: PathBuf) -> io::Result<Self> {
+        if !path.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not a keyspace directory", path.display())));
+        }
+        let settings = read_settings(&path)?;
+        Ok(Persistence {
+            codec: Mutex::new(Codec { compression: settings.compression, keyring: None }),
+            path,
+            recovery_pending: AtomicBool::new(false),
+            filters: None,
+            lock: None,
+            unsynced: None,
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.lock.is_none()
+    }
+
+    /// Prepares the keyspace at `root` for removal: writes through the handles this
+    /// process has open on it fail from now on, and a keyspace created there later
+    /// shares nothing with them.
+    pub(crate) fn retire(root: &Path) -> io::Result<()> {
+        let root = fs::canonicalize(root)?;
+        let unsynced = Unsynced::open_journals().remove(&root).and_then(|unsynced| unsynced.upgrade());
+        if let Some(unsynced) = unsynced {
+            unsynced.lock().unwrap().removed = true;
+        }
+        DirLock::forget(&root);
+        BloomFilters::forget(&root);
+        Ok(())
+    }
+
+    /// The process id a writer that exited without closing this keyspace left in
+    /// its lock file, if this open took the lock over from one.
+    pub fn stale_lock(&self) -> Option<u32> {
+        self.lock.as_ref().and_then(|lock| lock.stale_pid())
+    }
+
+    /// Fails with a `ReadOnlyError` if the keyspace was opened read-only.
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        match self.is_read_only() {
+            true => Err(io::Error::new(io::ErrorKind::PermissionDenied, ReadOnlyError { path: self.path.clone() })),
+            false => Ok(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.codec.lock().unwrap().compression
+    }
+
+    pub(crate) fn codec(&self) -> Codec {
+        self.codec.lock().unwrap().clone()
+    }
+
+    /// Sets how records written from now on are compressed, and saves the setting
+    /// for handles opened on this keyspace later. Existing records are left as they are.
+    pub fn set_compression(&self, compression: Compression) -> io::Result<()> {
+        self.check_writable()?;
+        self.save_settings(Settings { compression, ..self.settings() })?;
+        self.codec.lock().unwrap().compression = compression;
+        Ok(())
+    }
+
+    pub fn bloom_false_positive_rate(&self) -> f64 {
+        match &self.filters {
+            Some(filters) => filters.false_positive_rate(),
+            None => read_settings(&self.path).ok().and_then(|settings| settings.bloom_false_positive_rate).unwrap_or(DEFAULT_FALSE_POSITIVE_RATE),
+        }
+    }
+
+    /// Sets the false positive rate the Bloom filters of partitions are sized for,
+    /// and saves the setting with the keyspace. Filters built from now on use it;
+    /// `compact` rebuilds the existing ones. Lower rates cost more memory.
+    pub fn set_bloom_false_positive_rate(&self, rate: f64) -> io::Result<()> {
+        if !(rate > 0.0 && rate < 1.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the false positive rate must be between 0 and 1"));
+        }
+        self.check_writable()?;
+        self.save_settings(Settings { bloom_false_positive_rate: Some(rate), ..self.settings() })?;
+        if let Some(filters) = &self.filters {
+            filters.set_false_positive_rate(rate);
+        }
+        Ok(())
+    }
+
+    fn settings(&self) -> Settings {
+        Settings { compression: self.compression(), bloom_false_positive_rate: Some(self.bloom_false_positive_rate()) }
+    }
+
+    fn save_settings(&self, settings: Settings) -> io::Result<()> {
+        let staging = self.path.join(format!("{}.tmp", SETTINGS_FILE));
+        fs::write(&staging, serde_json::to_string(&settings)?)?;
+        fs::rename(staging, self.path.join(SETTINGS_FILE))
+    }
+
+    pub fn keyring(&self) -> Option<Arc<Keyring>> {
+        self.codec.lock().unwrap().keyring.clone()
+    }
+
+    /// Sets the keys records are encrypted and decrypted with. With a keyring,
+    /// records written from now on are encrypted with its active key; without
+    /// one they are written in plaintext. Keys are never stored with the data.
+    pub fn set_keyring(&self, keyring: Option<Arc<Keyring>>) -> io::Result<()> {
+        self.codec.lock().unwrap().keyring = keyring;
+        if self.recovery_pending.load(Ordering::SeqCst) {
+            self.recover()?;
+            self.recovery_pending.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Writes a group of mutations durably with a single fsync.
+    ///
+    /// The group is first appended to the journal and synced, then applied to the
+    /// per-key files without syncing them. Journal entries are kept until a
+    /// checkpoint syncs the files they were applied to and empties the journal,
+    /// which happens once it grows past a few megabytes, on `flush`, and before
+    /// any write that bypasses the journal. If the process dies before then, the
+    /// journal is replayed the next time the directory is opened.
+    pub fn write_group(&self, group: &[Data]) -> io::Result<()> {
+        let mut unsynced = self.unsynced()?;
+        self.check_recovered()?;
+        for data in group {
+            check_key(&data.partition_key, &data.sort_key)?;
+        }
+        let codec = self.codec();
+        let mut journal = OpenOptions::new().create(true).append(true).open(self.journal_path())?;
+        let mut buffer = String::new();
+        for data in group {
+            let line = match codec.keyring {
+                Some(_) => encode_hex(&codec.encode(data)?),
+                None => serde_json::to_string(data)?,
+            };
+            buffer.push_str(&format!("{:08x} {}\n", record::checksum(line.as_bytes()), line));
+        }
+        journal.write_all(buffer.as_bytes())?;
+        journal.sync_data()?;
+        unsynced.journal_len += buffer.len() as u64;
+
+        for data in group {
+            self.apply(data)?;
+            unsynced.record(&self.path, data);
+        }
+        if unsynced.journal_len >= CHECKPOINT_JOURNAL_LEN {
+            self.sync_journaled(&mut unsynced)?;
+        }
+        Ok(())
+    }
+
+    /// Replays the mutations left in the journal when the keyspace was last
+    /// closed, or by a process that died, then checkpoints them.
+    pub fn recover(&self) -> io::Result<()> {
+        let mut unsynced = self.unsynced()?;
+        let journal = match File::open(self.journal_path()) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // Counted before reading, so that a torn line alone still gets truncated.
+        unsynced.journal_len += journal.metadata()?.len();
+
+        let codec = self.codec();
+        for line in BufReader::new(journal).lines() {
+            // A torn final line belongs to a group that was never acknowledged.
+            match self.parse_journal_line(&line?, &codec)? {
+                Some(data) => {
+                    self.apply(&data)?;
+                    unsynced.record(&self.path, &data);
+                },
+                None => break,
+            }
+        }
+        self.sync_journaled(&mut unsynced)
+    }
+
+    /// Syncs the item files and directories that journaled mutations were applied
+    /// to, then empties the journal.
+    pub(crate) fn checkpoint(&self) -> io::Result<()> {
+        let mut unsynced = self.unsynced()?;
+        self.check_recovered()?;
+        self.sync_journaled(&mut unsynced)
+    }
+
+    /// Fails while the journal holds mutations that haven't been replayed, which
+    /// later writes would otherwise be overwritten by.
+    fn check_recovered(&self) -> io::Result<()> {
+        if self.recovery_pending.load(Ordering::SeqCst) {
+            let message = "the journal holds encrypted mutations; set the keyring to replay them first";
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, message));
+        }
+        Ok(())
+    }
+
+    /// Locks the journal of a writable handle. Fails once the keyspace is removed.
+    fn unsynced(&self) -> io::Result<MutexGuard<'_, Unsynced>> {
+        self.check_writable()?;
+        let unsynced = self.unsynced.as_ref().expect("writable handles have a journal").lock().unwrap();
+        if unsynced.removed {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has been removed", self.path.display())));
+        }
+        Ok(unsynced)
+    }
+
+    fn sync_journaled(&self, unsynced: &mut Unsynced) -> io::Result<()> {
+        if unsynced.journal_len == 0 {
+            return Ok(());
+        }
+        for path in &unsynced.items {
+            match OpenOptions::new().write(true).open(path) {
+                Ok(file) => file.sync_data()?,
+                // Deleted by a later mutation.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+                Err(e) => return Err(e),
+            }
+        }
+        for directory in &unsynced.directories {
+            sync_dir(directory)?;
+        }
+        sync_dir(&self.path)?;
+        OpenOptions::new().write(true).open(self.journal_path())?.set_len(0)?;
+        *unsynced = Unsynced::default();
+        Ok(())
+    }
+
+    fn apply(&self, data: &Data) -> io::Result<()> {
+        match data.operation_type {
+            OperationType::Insert | OperationType::Update => self.write_item(data),
+            OperationType::Delete => match self.remove_item(&data.partition_key, &data.sort_key) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn remove_item(&self, partition_key: &str, sort_key: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let path = self.item_path(partition_key, sort_key)?;
+        let _guard = self.filters.as_ref().map(|filters| filters.removing());
+        fs::remove_file(path)
+    }
+
+    /// The file an item is stored in. Fails with `InvalidInput` for keys that
+    /// aren't plain file names.
+    fn item_path(&self, partition_key: &str, sort_key: &str) -> io::Result<PathBuf> {
+        check_key(partition_key, sort_key)?;
+        Ok(self.path.join(partition_key).join(sort_key))
+    }
+
+    /// Each journal line is the CRC-32 of the rest of the line in hex, a space, and
+    /// the record as JSON, or as an encoded record in hex when it is encrypted.
+    fn journal_path(&self) -> PathBuf {
+        self.path.join(".journal")
+    }
+
+    /// Returns `None` for a line that is torn or fails its checksum. Lines without a
+    /// checksum were written by earlier versions and are accepted as they are.
+    fn parse_journal_line(&self, line: &str, codec: &Codec) -> io::Result<Option<Data>> {
+        if line.starts_with('{') {
+            return Ok(serde_json::from_str(line).ok());
+        }
+        let Some((crc, record)) = line.split_once(' ') else {
+            return Ok(None);
+        };
+        if u32::from_str_radix(crc, 16).ok() != Some(record::checksum(record.as_bytes())) {
+            return Ok(None);
+        }
+        if record.starts_with('{') {
+            return Ok(serde_json::from_str(record).ok());
+        }
+        match decode_hex(record) {
+            Some(bytes) => codec.decode(&self.journal_path(), &bytes).map(Some),
+            None => Ok(None),
         }
-    
-        Persistence { path }
     }
 
+    /// Writes an item file without going through the journal. Journaled mutations
+    /// are checkpointed first, so replaying them can't undo the write.
     pub fn save_data(&self, data: &Data) -> io::Result<()> {
+        let mut unsynced = self.unsynced()?;
+        self.check_recovered()?;
+        self.sync_journaled(&mut unsynced)?;
+        self.write_item(data)
+    }
+
+    fn write_item(&self, data: &Data) -> io::Result<()> {
+        self.check_writable()?;
+        let path = self.item_path(&data.partition_key, &data.sort_key)?;
         let partition_path = self.path.join(&data.partition_key);
         if !partition_path.exists() {
             fs::create_dir_all(&partition_path)?;
         }
         
-        let mut file = File::create(partition_path.join(&data.sort_key))?;
-        let data_string = serde_json::to_string(data)?;
-        write!(file, "{}", data_string)?;
-        Ok(())
+        let bytes = self.codec().encode(data)?;
+        let _guard = self.filters.as_ref().map(|filters| filters.add(&data.partition_key, &data.sort_key)).transpose()?;
+        replace_file(&path, &bytes, false)
     }
 
     pub fn load_data(&self, partition_key: String, sort_key: String) -> io::Result<Data> {
-        let mut file = File::open(self.path.join(&partition_key).join(&sort_key))?;
-        let mut data_string = String::new();
-        file.read_to_string(&mut data_string)?;
-        let data: Data = serde_json::from_str(&data_string)?;
-        Ok(data)
-    }
-
-    pub fn delete_data(&self, partition_key: &String, sort_key: &String) -> io::Result<()> {
-        let filename = format!("{}/{}", partition_key, sort_key);
-        let path = self.path.join(&filename);
-        println!("Trying to delete file at path: {:?}", path);
-        fs::remove_file(path)?;
-        Ok(())
+        read_data_file(&self.item_path(&partition_key, &sort_key)?, &self.codec())
+    }
+
+    /// Loads several items of one partition, returning `None` for the ones that don't exist.
+    /// A missing partition is detected once instead of probing every sort key.
+    pub fn load_batch(&self, partition_key: &str, sort_keys: &[String]) -> io::Result<Vec<Option<Data>>> {
+        for sort_key in sort_keys {
+            check_key(partition_key, sort_key)?;
+        }
+        let partition_path = self.path.join(partition_key);
+        if !partition_path.is_dir() {
+            return Ok(vec![None; sort_keys.len()]);
+        }
+        let codec = self.codec();
+
+        let mut items = Vec::with_capacity(sort_keys.len());
+        for sort_key in sort_keys {
+            match read_data_file(&partition_path.join(sort_key), &codec) {
+                Ok(data) => items.push(Some(data)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => items.push(None),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(items)
+    }
+
+    /// Removes an item file without going through the journal, checkpointing it
+    /// first like `save_data`.
+    pub fn delete_data(&self, partition_key: &str, sort_key: &str) -> io::Result<()> {
+        let mut unsynced = self.unsynced()?;
+        self.check_recovered()?;
+        self.sync_journaled(&mut unsynced)?;
+        self.remove_item(partition_key, sort_key)
     }
     
     
 
-    // Add a new method to load all data from disk into the store.
-    pub fn load_all_data(&self) -> io::Result<HashMap<String, HashMap<String, Data>>> {
-        let mut data_map = HashMap::new();
-
+    pub fn list_partitions(&self) -> io::Result<Vec<String>> {
+        let mut partitions = Vec::new();
         for partition_entry in fs::read_dir(&self.path)? {
             let partition_entry = partition_entry?;
-            let partition_path = partition_entry.path();
-            if partition_path.is_dir() {
-                if let Some(partition_key) = partition_path.file_name() {
-                    let partition_key = partition_key.to_string_lossy().into_owned();
-                    let mut partition = HashMap::new();
-                    for sort_entry in fs::read_dir(partition_path)? {
-                        let sort_entry = sort_entry?;
-                        let sort_path = sort_entry.path();
-                        if sort_path.is_file() {
-                            if let Some(sort_key) = sort_path.file_stem() {
-                                let sort_key = sort_key.to_string_lossy().into_owned();
-                                let data = self.load_data(partition_key.clone(), sort_key.clone())?;
-                                partition.insert(sort_key, data);
-                            }
-                        }
-                    }
-                    data_map.insert(partition_key, partition);
+            let partition_key = partition_entry.file_name().to_string_lossy().into_owned();
+            if partition_entry.file_type()?.is_dir() && !is_reserved(&partition_key) {
+                partitions.push(partition_key);
+            }
+        }
+        Ok(partitions)
+    }
+
+    pub fn load_partition(&self, partition_key: &str) -> io::Result<Vec<Data>> {
+        self.iter_partition(partition_key)?.collect()
+    }
+
+    /// Streams every item on disk one file at a time.
+    pub fn iter_all(&self) -> io::Result<DataIter> {
+        self.iter_segment(0, 1)
+    }
+
+    /// Streams the items of the partitions assigned to `segment` out of `total_segments`.
+    pub fn iter_segment(&self, segment: usize, total_segments: usize) -> io::Result<DataIter> {
+        assert!(segment < total_segments, "segment must be less than total_segments");
+        Ok(DataIter {
+            partitions: Some(fs::read_dir(&self.path)?),
+            current: None,
+            segment,
+            total_segments,
+            range: (Bound::Unbounded, Bound::Unbounded),
+            codec: self.codec(),
+        })
+    }
+
+    /// Streams the items of a single partition. A missing partition yields nothing.
+    pub fn iter_partition(&self, partition_key: &str) -> io::Result<DataIter> {
+        self.iter_range(partition_key, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Streams the items of a partition with sort keys between `from` and `to`.
+    /// Files outside the range are skipped by name, without being read.
+    pub fn iter_range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<DataIter> {
+        check_key_part(partition_key)?;
+        let current = match fs::read_dir(self.path.join(partition_key)) {
+            Ok(entries) => Some(entries),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let range = (from.map(str::to_string), to.map(str::to_string));
+        Ok(DataIter { partitions: None, current, segment: 0, total_segments: 1, range, codec: self.codec() })
+    }
+
+    /// Counts partitions, items and bytes. Only the header of each item file is read.
+    pub fn stats(&self) -> io::Result<Stats> {
+        let mut stats = Stats::default();
+        let mut header = Vec::with_capacity(record::STATS_HEADER_LEN);
+        for partition_key in self.list_partitions()? {
+            stats.partitions += 1;
+            for entry in fs::read_dir(self.path.join(&partition_key))? {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                if !metadata.is_file() || is_reserved(&entry.file_name().to_string_lossy()) {
+                    continue;
+                }
+                header.clear();
+                match File::open(entry.path()) {
+                    Ok(file) => file.take(record::STATS_HEADER_LEN as u64).read_to_end(&mut header)?,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                stats.items += 1;
+                stats.disk_bytes += metadata.len();
+                stats.uncompressed_bytes += record::uncompressed_len(&header, metadata.len());
+            }
+        }
+        stats.compression_ratio = if stats.disk_bytes == 0 { 1.0 } else { stats.uncompressed_bytes as f64 / stats.disk_bytes as f64 };
+        Ok(stats)
+    }
+
+    /// Removes partition directories left empty by deletes, and rebuilds the Bloom
+    /// filters without the deleted keys. Returns how many directories were removed.
+    pub fn compact(&self) -> io::Result<usize> {
+        self.check_writable()?;
+        let mut removed = 0;
+        for partition_key in self.list_partitions()? {
+            // Fails on directories that still hold items, which are left alone.
+            if fs::remove_dir(self.path.join(&partition_key)).is_ok() {
+                removed += 1;
+            }
+        }
+        if let Some(filters) = &self.filters {
+            filters.rebuild()?;
+        }
+        Ok(removed)
+    }
+
+    /// Rewrites every record `needs_reencryption` with the current codec, stopping
+    /// early if `cancelled` is set. Counts rewritten records in `rewritten`.
+    pub(crate) fn reencrypt(&self, rewritten: &AtomicUsize, cancelled: &AtomicBool) -> io::Result<()> {
+        // The journal holds records sealed with the keys in use when they were
+        // written, which may be removed once this completes.
+        self.checkpoint()?;
+        let codec = self.codec();
+        for partition_key in self.list_partitions()? {
+            let partition_path = self.path.join(&partition_key);
+            let entries = match fs::read_dir(&partition_path) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut replaced = false;
+            for entry in entries {
+                if cancelled.load(Ordering::SeqCst) {
+                    break;
+                }
+                let path = entry?.path();
+                if is_reserved(&path.file_name().unwrap_or_default().to_string_lossy()) {
+                    continue;
+                }
+                let bytes = match fs::read(&path) {
+                    Ok(bytes) => bytes,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                };
+                if !codec.needs_reencryption(&bytes) {
+                    continue;
+                }
+                let reencrypted = codec.encode(&codec.decode(&path, &bytes)?)?;
+                // Checked and replaced with writes to the keyspace held off, so a
+                // record replaced or deleted in the meantime, which needs no
+                // rewriting, is left as it is.
+                let _guard = self.filters.as_ref().map(|filters| filters.block_writes());
+                if fs::read(&path).ok().as_deref() != Some(bytes.as_slice()) {
+                    continue;
                 }
+                // Synced, as the old key may be removed once the job completes.
+                replace_file(&path, &reencrypted, true)?;
+                replaced = true;
+                rewritten.fetch_add(1, Ordering::SeqCst);
+            }
+            if replaced {
+                sync_dir(&partition_path)?;
+            }
+            if cancelled.load(Ordering::SeqCst) {
+                return Ok(());
             }
         }
+        Ok(())
+    }
 
+    pub fn load_all_data(&self) -> io::Result<HashMap<String, HashMap<String, Data>>> {
+        let mut data_map: HashMap<String, HashMap<String, Data>> = HashMap::new();
+        for data in self.iter_all()? {
+            let data = data?;
+            data_map
+                .entry(data.partition_key.clone())
+                .or_default()
+                .insert(data.sort_key.clone(), data);
+        }
         Ok(data_map)
     }
 }
+
+impl StorageBackend for Persistence {
+    type Iter = DataIter;
+
+    /// Consults the Bloom filter of the partition. Invalid keys are let through, for
+    /// `get` to reject.
+    fn may_contain(&self, partition_key: &str, sort_key: &str) -> bool {
+        check_key(partition_key, sort_key).is_err()
+            || self.filters.as_ref().is_none_or(|filters| filters.may_contain(partition_key, sort_key))
+    }
+
+    fn get(&self, partition_key: &str, sort_key: &str) -> io::Result<Option<Data>> {
+        match read_data_file(&self.item_path(partition_key, sort_key)?, &self.codec()) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn get_batch(&self, partition_key: &str, sort_keys: &[String]) -> io::Result<Vec<Option<Data>>> {
+        self.load_batch(partition_key, sort_keys)
+    }
+
+    fn put(&self, data: &Data) -> io::Result<()> {
+        self.save_data(data)
+    }
+
+    fn delete(&self, partition_key: &str, sort_key: &str) -> io::Result<()> {
+        self.delete_data(partition_key, sort_key)
+    }
+
+    fn range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<DataIter> {
+        self.iter_range(partition_key, from, to)
+    }
+
+    fn scan(&self, segment: usize, total_segments: usize) -> io::Result<DataIter> {
+        self.iter_segment(segment, total_segments)
+    }
+
+    fn list_partitions(&self) -> io::Result<Vec<String>> {
+        Persistence::list_partitions(self)
+    }
+
+    fn write_batch(&self, batch: &[Data]) -> io::Result<()> {
+        self.write_group(batch)
+    }
+
+    /// Item files are written directly, not buffered, so this only checkpoints the
+    /// journal and saves the Bloom filters. Writes that must be durable when they
+    /// return go through a group commit.
+    fn flush(&self) -> io::Result<()> {
+        match &self.filters {
+            Some(filters) => {
+                self.checkpoint()?;
+                filters.save()
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+fn read_settings(path: &Path) -> io::Result<Settings> {
+    match fs::read_to_string(path.join(SETTINGS_FILE)) {
+        Ok(contents) => Ok(serde_json::from_str(&contents)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Entries of the root directory starting with a dot hold metadata (the journal,
+/// tables, ...) rather than partitions, and those of a partition directory are
+/// item files still being written.
+pub(crate) fn is_reserved(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+/// Keys are used as directory and file names as they are, so they must be plain
+/// names: not empty, without `/`, `\` or NUL, and not starting with a dot. That
+/// rules out `.` and `..`, and names like `.journal` and `.tables` that are
+/// reserved for metadata.
+pub(crate) fn check_key(partition_key: &str, sort_key: &str) -> io::Result<()> {
+    check_key_part(partition_key)?;
+    check_key_part(sort_key)
+}
+
+pub(crate) fn check_key_part(key: &str) -> io::Result<()> {
+    if key.is_empty() || is_reserved(key) || key.contains(['/', '\\', '\0']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid key {:?}", key)));
+    }
+    Ok(())
+}
+
+/// Reads the item file at `path` and checks that it holds the record for
+/// `(partition_key, sort_key)`, as a file moved or copied by hand might not.
+pub(crate) fn read_checked(path: &Path, partition_key: &str, sort_key: &str, codec: &Codec) -> io::Result<Data> {
+    let data = read_data_file(path, codec)?;
+    if data.partition_key != partition_key || data.sort_key != sort_key {
+        let reason = format!(
+            "record is for ({}, {}) but stored under ({}, {})",
+            data.partition_key, data.sort_key, partition_key, sort_key
+        );
+        return Err(record::corrupt(path, reason));
+    }
+    Ok(data)
+}
+
+/// Writes an item file under a temporary name in its partition directory and
+/// renames it over `path`, so a reader, possibly in another process, sees either
+/// the old record or the new one and never a partly written file. Temporary
+/// names start with a dot, which no sort key does. With `sync`, the new contents
+/// are on disk before the rename.
+fn replace_file(path: &Path, bytes: &[u8], sync: bool) -> io::Result<()> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let staging = path.with_file_name(format!(".{}.{}-{}.tmp", name, process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+    let result = File::create(&staging)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            if sync { file.sync_data() } else { Ok(()) }
+        })
+        .and_then(|_| fs::rename(&staging, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&staging);
+    }
+    result
+}
+
+/// Makes the entries created, renamed or removed in a directory durable. Only
+/// Unix can sync a directory; elsewhere this does nothing.
+pub(crate) fn sync_dir(path: &Path) -> io::Result<()> {
+    if !cfg!(unix) {
+        return Ok(());
+    }
+    match File::open(path) {
+        Ok(dir) => dir.sync_all(),
+        // Removed by a compaction; there is nothing left to sync.
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_data_file(path: &Path, codec: &Codec) -> io::Result<Data> {
+    codec.decode(path, &fs::read(path)?)
+}
+
+/// A lazy iterator over persisted items.
+///
+/// Directory entries are read on demand and only one record is held in memory
+/// at a time, so dropping the iterator early stops all further I/O. Files removed
+/// by a concurrent delete while iterating are skipped.
+#[derive(Debug)]
+pub struct DataIter {
+    partitions: Option<fs::ReadDir>,
+    current: Option<fs::ReadDir>,
+    segment: usize,
+    total_segments: usize,
+    range: (Bound<String>, Bound<String>),
+    codec: Codec,
+}
+
+impl DataIter {
+    /// Whether an entry of a partition directory is an item in the range, rather
+    /// than a file still being written.
+    fn in_range(&self, entry: &fs::DirEntry) -> bool {
+        let sort_key = entry.file_name().to_string_lossy().into_owned();
+        match self.range {
+            _ if is_reserved(&sort_key) => false,
+            (Bound::Unbounded, Bound::Unbounded) => true,
+            _ => self.range.contains(&sort_key),
+        }
+    }
+}
+
+impl Iterator for DataIter {
+    type Item = io::Result<Data>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entries) = self.current.as_mut() {
+                match entries.next() {
+                    Some(Ok(entry)) => match entry.file_type() {
+                        Ok(file_type) if file_type.is_file() && self.in_range(&entry) => match read_data_file(&entry.path(), &self.codec) {
+                            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                            result => return Some(result),
+                        },
+                        Ok(_) => continue,
+                        Err(e) => return Some(Err(e)),
+                    },
+                    Some(Err(e)) => return Some(Err(e)),
+                    None => self.current = None,
+                }
+            }
+
+            match self.partitions.as_mut()?.next()? {
+                Ok(entry) => {
+                    let partition_key = entry.file_name().to_string_lossy().into_owned();
+                    if is_reserved(&partition_key) || segment_of(&partition_key, self.total_segments) != self.segment {
+                        continue;
+                    }
+                    match entry.file_type() {
+                        Ok(file_type) if file_type.is_dir() => match fs::read_dir(entry.path()) {
+                            Ok(entries) => self.current = Some(entries),
+                            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                            Err(e) => return Some(Err(e)),
+                        },
+                        Ok(_) => continue,
+                        Err(e) => return Some(Err(e)),
+                    }
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! The encoding of an item file.
+//!
+//! ```text
+//! "FREC" u8 flags u32 checksum payload
+//! ```
+//!
+//! The checksum is the CRC-32 of the flags byte and the payload, little-endian, so
+//! corruption is caught before anything is decrypted or decompressed. Without
+//! flags the payload is the record as JSON. With `FLAG_LZ4` or `FLAG_ZSTD` it is
+//! the u32 length of the JSON followed by the JSON compressed with that codec.
+//! With `FLAG_ENCRYPTED` the (compressed) JSON is replaced by the u32 id of the
+//! key, a nonce and the ChaCha20-Poly1305 ciphertext, which also authenticates the
+//! flags and length. A reader rejects flags it doesn't know. Files written before
+//! checksums were added hold only the JSON, which never starts with the magic, and
+//! are still read without one.
+
+use super::encryption::{key_id, no_keyring, Keyring};
+use super::persistence::Data;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const MAGIC: &[u8; 4] = b"FREC";
+const HEADER_LEN: usize = MAGIC.len() + 1 + 4;
+/// The header plus the uncompressed length of a compressed record.
+pub(crate) const STATS_HEADER_LEN: usize = HEADER_LEN + 4;
+
+const FLAG_LZ4: u8 = 0x01;
+const FLAG_ZSTD: u8 = 0x02;
+const FLAG_ENCRYPTED: u8 = 0x04;
+const COMPRESSION_FLAGS: u8 = FLAG_LZ4 | FLAG_ZSTD;
+const KNOWN_FLAGS: u8 = COMPRESSION_FLAGS | FLAG_ENCRYPTED;
+const ZSTD_LEVEL: i32 = 3;
+
+/// How the records of a keyspace are compressed on disk. Reads handle every
+/// codec regardless of the setting, so it can be changed at any time.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Fast, with a moderate ratio.
+    Lz4,
+    /// Slower, with a better ratio.
+    Zstd,
+}
+
+/// A persisted record that failed its checksum or can't be decoded. Reads return it
+/// wrapped in an `io::Error` of kind `InvalidData`; use `CorruptionError::of` to
+/// get it back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptionError {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+impl CorruptionError {
+    /// The corruption an I/O error reports, if it reports one.
+    pub fn of(error: &io::Error) -> Option<&CorruptionError> {
+        error.get_ref()?.downcast_ref()
+    }
+}
+
+impl fmt::Display for CorruptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "corrupt record at {}: {}", self.path.display(), self.reason)
+    }
+}
+
+impl Error for CorruptionError {}
+
+pub(crate) fn corrupt(path: &Path, reason: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, CorruptionError { path: path.to_path_buf(), reason: reason.into() })
+}
+
+pub(crate) fn checksum(bytes: &[u8]) -> u32 {
+    crc32fast::hash(bytes)
+}
+
+/// How records are written: the compression and, when a keyring is set, the key
+/// they are encrypted with. Any codec can read every record its keyring has keys for.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Codec {
+    pub compression: Compression,
+    pub keyring: Option<Arc<Keyring>>,
+}
+
+impl Codec {
+    /// Encodes a record, compressed if that makes it smaller.
+    pub fn encode(&self, data: &Data) -> io::Result<Vec<u8>> {
+        let json = serde_json::to_vec(data)?;
+        let compressed = match self.compression {
+            Compression::None => None,
+            Compression::Lz4 => Some((FLAG_LZ4, lz4_flex::compress(&json))),
+            Compression::Zstd => Some((FLAG_ZSTD, zstd::bulk::compress(&json, ZSTD_LEVEL)?)),
+        };
+        let (mut flags, mut payload, body) = match compressed {
+            Some((flags, compressed)) if compressed.len() + 4 < json.len() => {
+                (flags, (json.len() as u32).to_le_bytes().to_vec(), compressed)
+            },
+            _ => (0, Vec::new(), json),
+        };
+        match &self.keyring {
+            Some(keyring) => {
+                flags |= FLAG_ENCRYPTED;
+                let sealed = keyring.encrypt(&body, &associated_data(flags, &payload))?;
+                payload.extend_from_slice(&sealed);
+            },
+            None => payload.extend_from_slice(&body),
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(flags);
+        bytes.extend_from_slice(&record_checksum(flags, &payload).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    /// Decodes the contents of the item file at `path`, which is only used to report
+    /// where corruption was found.
+    pub fn decode(&self, path: &Path, bytes: &[u8]) -> io::Result<Data> {
+        self.decode_record(path, bytes, true)
+    }
+
+    /// Like `decode`, for a record `verify` already accepted, so its checksum isn't
+    /// computed again.
+    pub fn decode_verified(&self, path: &Path, bytes: &[u8]) -> io::Result<Data> {
+        self.decode_record(path, bytes, false)
+    }
+
+    fn decode_record(&self, path: &Path, bytes: &[u8], check_checksum: bool) -> io::Result<Data> {
+        let (flags, payload) = match unframe(path, bytes, check_checksum)? {
+            Some(framed) => framed,
+            None => return parse(path, bytes),
+        };
+
+        let compression = flags & COMPRESSION_FLAGS;
+        let (length, body) = match compression {
+            0 => (None, payload),
+            _ => match payload.split_first_chunk::<4>() {
+                Some((length, body)) => (Some(u32::from_le_bytes(*length) as usize), body),
+                None => return Err(corrupt(path, "compressed record is truncated")),
+            },
+        };
+        let body = if flags & FLAG_ENCRYPTED != 0 {
+            let keyring = self.keyring.as_ref().ok_or_else(|| no_keyring(path))?;
+            let associated_data = associated_data(flags, &payload[..payload.len() - body.len()]);
+            Cow::Owned(keyring.decrypt(path, body, &associated_data)?)
+        } else {
+            Cow::Borrowed(body)
+        };
+        match length {
+            Some(length) => parse(path, &decompress(path, compression, length, &body)?),
+            None => parse(path, &body),
+        }
+    }
+
+    /// Whether a record written by this codec would be encrypted with a different
+    /// key than the one `bytes` are, or `bytes` aren't encrypted at all.
+    pub fn needs_reencryption(&self, bytes: &[u8]) -> bool {
+        let Some(keyring) = &self.keyring else {
+            return false;
+        };
+        let Some(rest) = bytes.strip_prefix(MAGIC).filter(|rest| rest.len() >= HEADER_LEN - MAGIC.len()) else {
+            return true;
+        };
+        let flags = rest[0];
+        if flags & FLAG_ENCRYPTED == 0 {
+            return true;
+        }
+        let sealed = if flags & COMPRESSION_FLAGS != 0 { rest.get(9..) } else { rest.get(5..) };
+        sealed.and_then(key_id) != Some(keyring.active_id())
+    }
+}
+
+/// Checks the framing and checksum of a record without decoding it.
+pub(crate) fn verify(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    unframe(path, bytes, true).map(drop)
+}
+
+/// Splits a record into its flags and payload, checking the checksum if asked to,
+/// or returns `None` for a legacy record that is only JSON.
+fn unframe<'a>(path: &Path, bytes: &'a [u8], check_checksum: bool) -> io::Result<Option<(u8, &'a [u8])>> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Ok(None);
+    };
+    if rest.len() < HEADER_LEN - MAGIC.len() {
+        return Err(corrupt(path, "record header is truncated"));
+    }
+    let flags = rest[0];
+    let stored = u32::from_le_bytes([rest[1], rest[2], rest[3], rest[4]]);
+    let payload = &rest[5..];
+    if check_checksum && record_checksum(flags, payload) != stored {
+        return Err(corrupt(path, "checksum mismatch"));
+    }
+    if flags & !KNOWN_FLAGS != 0 || flags & COMPRESSION_FLAGS == COMPRESSION_FLAGS {
+        return Err(corrupt(path, format!("unknown record flags {:#04x}", flags)));
+    }
+    Ok(Some((flags, payload)))
+}
+
+/// The JSON of a record `verify` already accepted that is neither compressed nor
+/// encrypted, borrowed from `bytes`. `None` for records only `Codec::decode_verified`
+/// can read.
+pub(crate) fn plain_json<'a>(path: &Path, bytes: &'a [u8]) -> io::Result<Option<&'a [u8]>> {
+    match unframe(path, bytes, false)? {
+        Some((0, payload)) => Ok(Some(payload)),
+        Some(_) => Ok(None),
+        None => Ok(Some(bytes)),
+    }
+}
+
+/// Binds the clear parts of an encrypted record, its flags and uncompressed
+/// length, to the ciphertext.
+fn associated_data(flags: u8, clear: &[u8]) -> Vec<u8> {
+    let mut associated_data = vec![flags];
+    associated_data.extend_from_slice(clear);
+    associated_data
+}
+
+fn decompress(path: &Path, compression: u8, length: usize, compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let json = match compression {
+        FLAG_LZ4 => lz4_flex::decompress(compressed, length).map_err(|e| e.to_string()),
+        _ => zstd::bulk::decompress(compressed, length).map_err(|e| e.to_string()),
+    };
+    match json {
+        Ok(json) if json.len() == length => Ok(json),
+        Ok(_) => Err(corrupt(path, "decompressed record has the wrong length")),
+        Err(e) => Err(corrupt(path, format!("can't decompress record: {}", e))),
+    }
+}
+
+fn parse(path: &Path, json: &[u8]) -> io::Result<Data> {
+    serde_json::from_slice(json).map_err(|e| corrupt(path, format!("not a valid record: {}", e)))
+}
+
+/// The size the item file starting with `header` would have without compression.
+/// `header` holds up to the first `STATS_HEADER_LEN` bytes of a file of `file_len` bytes.
+pub(crate) fn uncompressed_len(header: &[u8], file_len: u64) -> u64 {
+    if header.len() < STATS_HEADER_LEN || !header.starts_with(MAGIC) || header[MAGIC.len()] & COMPRESSION_FLAGS == 0 {
+        return file_len;
+    }
+    let length = u32::from_le_bytes([header[HEADER_LEN], header[HEADER_LEN + 1], header[HEADER_LEN + 2], header[HEADER_LEN + 3]]);
+    HEADER_LEN as u64 + length as u64
+}
+
+fn record_checksum(flags: u8, payload: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&[flags]);
+    hasher.update(payload);
+    hasher.finalize()
+}
@@ -0,0 +1,128 @@
+use super::persistence::Data;
+use serde::{Serialize, Deserialize};
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+/// The attribute of a `Data` item a filter condition is evaluated against.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    PartitionKey,
+    SortKey,
+    Value,
+}
+
+/// A filter expression evaluated against every item visited by a scan.
+///
+/// Comparisons are lexicographic on the string form of the attribute.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Filter {
+    Eq(Attribute, String),
+    Ne(Attribute, String),
+    Lt(Attribute, String),
+    Le(Attribute, String),
+    Gt(Attribute, String),
+    Ge(Attribute, String),
+    Between(Attribute, String, String),
+    BeginsWith(Attribute, String),
+    Contains(Attribute, String),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+}
+
+impl Attribute {
+    fn of<'a>(&self, data: &'a Data) -> &'a str {
+        match self {
+            Attribute::PartitionKey => &data.partition_key,
+            Attribute::SortKey => &data.sort_key,
+            Attribute::Value => &data.value,
+        }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, data: &Data) -> bool {
+        match self {
+            Filter::Eq(attr, operand) => attr.of(data) == operand,
+            Filter::Ne(attr, operand) => attr.of(data) != operand,
+            Filter::Lt(attr, operand) => attr.of(data) < operand.as_str(),
+            Filter::Le(attr, operand) => attr.of(data) <= operand.as_str(),
+            Filter::Gt(attr, operand) => attr.of(data) > operand.as_str(),
+            Filter::Ge(attr, operand) => attr.of(data) >= operand.as_str(),
+            Filter::Between(attr, low, high) => {
+                let value = attr.of(data);
+                value >= low.as_str() && value <= high.as_str()
+            },
+            Filter::BeginsWith(attr, prefix) => attr.of(data).starts_with(prefix.as_str()),
+            Filter::Contains(attr, needle) => attr.of(data).contains(needle.as_str()),
+            Filter::And(left, right) => left.matches(data) && right.matches(data),
+            Filter::Or(left, right) => left.matches(data) || right.matches(data),
+            Filter::Not(inner) => !inner.matches(data),
+        }
+    }
+
+    pub fn and(self, other: Filter) -> Filter {
+        Filter::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Filter) -> Filter {
+        Filter::Or(Box::new(self), Box::new(other))
+    }
+
+    pub fn negate(self) -> Filter {
+        Filter::Not(Box::new(self))
+    }
+}
+
+/// Returns the segment (in `0..total_segments`) a partition belongs to.
+///
+/// Partitions are assigned with a stable FNV-1a hash so that independent
+/// workers, even in different processes, agree on the split.
+pub fn segment_of(partition_key: &str, total_segments: usize) -> usize {
+    assert!(total_segments > 0, "total_segments must be at least 1");
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in partition_key.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    (hash % total_segments as u64) as usize
+}
+
+pub(crate) fn matches(filter: Option<&Filter>, data: &Data) -> bool {
+    filter.is_none_or(|filter| filter.matches(data))
+}
+
+/// The narrowest sort key range that contains every item `filter` can match, for
+/// backends to read less. Conditions it can't bound leave the range open.
+pub(crate) fn sort_key_range(filter: Option<&Filter>) -> (Bound<String>, Bound<String>) {
+    match filter {
+        Some(Filter::Eq(Attribute::SortKey, key)) => (Bound::Included(key.clone()), Bound::Included(key.clone())),
+        Some(Filter::Lt(Attribute::SortKey, key)) => (Bound::Unbounded, Bound::Excluded(key.clone())),
+        Some(Filter::Le(Attribute::SortKey, key)) => (Bound::Unbounded, Bound::Included(key.clone())),
+        Some(Filter::Gt(Attribute::SortKey, key)) => (Bound::Excluded(key.clone()), Bound::Unbounded),
+        Some(Filter::Ge(Attribute::SortKey, key)) => (Bound::Included(key.clone()), Bound::Unbounded),
+        Some(Filter::Between(Attribute::SortKey, low, high)) => (Bound::Included(low.clone()), Bound::Included(high.clone())),
+        Some(Filter::BeginsWith(Attribute::SortKey, prefix)) => (Bound::Included(prefix.clone()), Bound::Unbounded),
+        Some(Filter::And(left, right)) => {
+            let (left_from, left_to) = sort_key_range(Some(left));
+            let (right_from, right_to) = sort_key_range(Some(right));
+            (tighter(left_from, right_from, Ordering::Greater), tighter(left_to, right_to, Ordering::Less))
+        },
+        _ => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+/// Of two bounds on the same side of a range, the one that excludes more: the
+/// greater lower bound (`Ordering::Greater`) or the lesser upper bound (`Ordering::Less`).
+fn tighter(a: Bound<String>, b: Bound<String>, narrower: Ordering) -> Bound<String> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => match x.cmp(y) {
+            Ordering::Equal if matches!(a, Bound::Excluded(_)) => a,
+            Ordering::Equal => b,
+            ordering if ordering == narrower => a,
+            _ => b,
+        },
+    }
+}
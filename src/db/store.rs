@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use super::persistence::Data;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Store {
     data: HashMap<String, HashMap<String, Data>>,
 }
@@ -15,7 +15,7 @@ impl Store {
     }
 
     pub fn insert(&mut self, partition_key: String, sort_key: String, value: Data) {
-        let partition = self.data.entry(partition_key).or_insert_with(HashMap::new);
+        let partition = self.data.entry(partition_key).or_default();
         partition.insert(sort_key, value);
     }
     
@@ -35,6 +35,14 @@ impl Store {
         }
     }
     
+    pub fn partition_keys(&self) -> impl Iterator<Item = &String> {
+        self.data.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Data> {
+        self.data.values().flat_map(|partition| partition.values())
+    }
+
     pub fn load_all(&mut self, data: HashMap<String, HashMap<String, Data>>) {
         self.data = data;
     }    
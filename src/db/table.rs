@@ -0,0 +1,228 @@
+use super::persistence::Data;
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const TABLES_DIR: &str = ".tables";
+const DESCRIPTION_FILE: &str = ".table.json";
+
+/// Names of the key attributes of a table, used by front-ends that expose
+/// items as attribute maps rather than as `Data`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct KeySchema {
+    pub partition_key: String,
+    pub sort_key: String,
+}
+
+impl Default for KeySchema {
+    fn default() -> Self {
+        KeySchema {
+            partition_key: "partition_key".to_string(),
+            sort_key: "sort_key".to_string(),
+        }
+    }
+}
+
+/// Makes the items of a table expire, like DynamoDB's time to live: an item
+/// expires once its `attribute`, a time in seconds since the Unix epoch, has
+/// passed. Items without the attribute never expire.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeToLive {
+    pub attribute: String,
+}
+
+/// A secondary index, finding the items of a table by the value of `attribute`
+/// rather than by their key. Items without the attribute aren't indexed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexSchema {
+    pub name: String,
+    pub attribute: String,
+}
+
+/// What a table is set up with besides its key schema.
+///
+/// Attributes are the fields of items whose value is a JSON object, as stored by
+/// the DynamoDB front-end. A field may hold a string or a number, or a DynamoDB
+/// attribute value of type `S`, `N` or `B`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TableSettings {
+    #[serde(default)]
+    pub time_to_live: Option<TimeToLive>,
+    /// Indexes can only be given when the table is created, so they cover every item.
+    #[serde(default)]
+    pub indexes: Vec<IndexSchema>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableDescription {
+    pub name: String,
+    pub key_schema: KeySchema,
+    /// Seconds since the Unix epoch.
+    pub created_at: u64,
+    #[serde(default)]
+    pub settings: TableSettings,
+}
+
+impl TimeToLive {
+    /// Whether `data` has expired at `now`, in seconds since the Unix epoch.
+    pub(crate) fn expired(&self, data: &Data, now: u64) -> bool {
+        attribute(data, &self.attribute).and_then(|expiry| expiry.parse::<f64>().ok()).is_some_and(|expiry| expiry <= now as f64)
+    }
+}
+
+impl TableSettings {
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        if self.time_to_live.as_ref().is_some_and(|ttl| ttl.attribute.is_empty()) {
+            return invalid("the time to live attribute must not be empty".to_string());
+        }
+        for (i, index) in self.indexes.iter().enumerate() {
+            validate_name(&index.name).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid index name '{}'", index.name)))?;
+            if index.attribute.is_empty() {
+                return invalid(format!("index '{}' needs an attribute", index.name));
+            }
+            if self.indexes[..i].iter().any(|other| other.name == index.name) {
+                return invalid(format!("index '{}' is defined twice", index.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The string form of an attribute of an item, if its value is a JSON object
+/// with a string or number field of that name. See `TableSettings`.
+pub(crate) fn attribute(data: &Data, name: &str) -> Option<String> {
+    let Ok(Value::Object(mut fields)) = serde_json::from_str::<Value>(&data.value) else {
+        return None;
+    };
+    match fields.remove(name)? {
+        Value::String(value) => Some(value),
+        Value::Number(value) => Some(value.to_string()),
+        Value::Object(typed) => ["S", "N", "B"].iter().find_map(|kind| typed.get(*kind)?.as_str().map(str::to_string)),
+        _ => None,
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Manages the named tables of a database directory.
+///
+/// Each table is a separate keyspace stored in `<root>/.tables/<name>`, laid out
+/// exactly like the root keyspace, with its description kept alongside the data.
+#[derive(Debug)]
+pub struct Catalog {
+    path: PathBuf,
+}
+
+impl Catalog {
+    pub fn new(root: &Path) -> Self {
+        Catalog { path: root.join(TABLES_DIR) }
+    }
+
+    pub fn table_path(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    pub fn create(&self, name: &str, key_schema: KeySchema, settings: TableSettings) -> io::Result<TableDescription> {
+        validate_name(name)?;
+        settings.validate()?;
+        fs::create_dir_all(&self.path)?;
+
+        // Build the table under a hidden name and rename it into place, so that a
+        // table is either fully created (with its description) or not visible at all.
+        let staging = self.path.join(format!(".creating-{}-{}", name, unique_suffix()));
+        let description = TableDescription {
+            name: name.to_string(),
+            key_schema,
+            created_at: now(),
+            settings,
+        };
+        fs::create_dir(&staging)?;
+        fs::write(staging.join(DESCRIPTION_FILE), serde_json::to_string(&description)?)?;
+
+        if self.table_path(name).exists() {
+            fs::remove_dir_all(&staging)?;
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("table '{}' already exists", name)));
+        }
+        fs::rename(&staging, self.table_path(name))?;
+        Ok(description)
+    }
+
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() && !name.starts_with('.') {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn describe(&self, name: &str) -> io::Result<TableDescription> {
+        validate_name(name)?;
+        match fs::read_to_string(self.table_path(name).join(DESCRIPTION_FILE)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(not_found(name)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replaces the time to live of a table.
+    pub fn set_time_to_live(&self, name: &str, time_to_live: Option<TimeToLive>) -> io::Result<TableDescription> {
+        let mut description = self.describe(name)?;
+        description.settings.time_to_live = time_to_live;
+        description.settings.validate()?;
+        let path = self.table_path(name);
+        let staging = path.join(format!("{}.{}.tmp", DESCRIPTION_FILE, unique_suffix()));
+        fs::write(&staging, serde_json::to_string(&description)?)?;
+        fs::rename(staging, path.join(DESCRIPTION_FILE))?;
+        Ok(description)
+    }
+
+    /// Drops a table with all of its data. The table disappears atomically: it is
+    /// renamed out of the way first, and only then are its files removed.
+    pub fn drop_table(&self, name: &str) -> io::Result<()> {
+        validate_name(name)?;
+        let tombstone = self.path.join(format!(".dropped-{}-{}", name, unique_suffix()));
+        match fs::rename(self.table_path(name), &tombstone) {
+            Ok(()) => fs::remove_dir_all(tombstone),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(not_found(name)),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn validate_name(name: &str) -> io::Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid table name '{}'", name)))
+    }
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("table '{}' does not exist", name))
+}
+
+fn unique_suffix() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("{}-{}", std::process::id(), nanos)
+}
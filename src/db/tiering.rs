@@ -0,0 +1,194 @@
+use super::backend::StorageBackend;
+use super::object_store::{ObjectStore, ObjectStoreBackend};
+use super::persistence::{Data, Persistence};
+use super::scan::segment_of;
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// When `TieredBackend::tier` moves a partition to the object store.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TieringPolicy {
+    /// How long a partition must have gone without writes.
+    pub max_idle: Duration,
+}
+
+impl TieringPolicy {
+    /// Moves partitions nothing was written to for `days` days.
+    pub fn days(days: u64) -> Self {
+        TieringPolicy { max_idle: Duration::from_secs(days * 24 * 60 * 60) }
+    }
+}
+
+/// A `StorageBackend` keeping recently written partitions in a `Persistence`
+/// directory and moving idle ones to an `ObjectStore`.
+///
+/// Every partition lives in one tier at a time. `tier` moves the partitions its
+/// policy finds idle to the object store, and the first write to a cold partition
+/// moves it back before applying the write. Reads are served from whichever tier
+/// holds the partition. Cold segments are written with the codec of the
+/// `Persistence`, so set its compression and keyring before creating the backend.
+#[derive(Debug)]
+pub struct TieredBackend<S: ObjectStore> {
+    hot: Persistence,
+    /// Shared with scans, which load cold segments as they reach them.
+    cold: Arc<ObjectStoreBackend<S>>,
+    /// Held for writing while partitions move between tiers.
+    lock: RwLock<()>,
+}
+
+impl<S: ObjectStore> TieredBackend<S> {
+    pub fn new(hot: Persistence, store: S) -> Self {
+        let cold = Arc::new(ObjectStoreBackend::with_codec(store, hot.codec()));
+        TieredBackend { hot, cold, lock: RwLock::new(()) }
+    }
+
+    pub fn hot(&self) -> &Persistence {
+        &self.hot
+    }
+
+    pub fn cold(&self) -> &ObjectStoreBackend<S> {
+        &self.cold
+    }
+
+    /// Moves every partition of the `Persistence` directory that `policy` finds
+    /// idle to the object store, returning their keys.
+    ///
+    /// A partition's segment is written before its directory is removed, so a
+    /// crash in between leaves both copies and reads keep using the directory.
+    pub fn tier(&self, policy: &TieringPolicy) -> io::Result<Vec<String>> {
+        let _guard = self.lock.write().unwrap();
+        // Replaying journaled mutations later would bring back part of a partition
+        // that has moved.
+        self.hot.checkpoint()?;
+        let now = SystemTime::now();
+        let mut moved = Vec::new();
+        for partition_key in self.hot.list_partitions()? {
+            let partition_path = self.hot.path().join(&partition_key);
+            let idle = now.duration_since(last_modified(&partition_path)?).unwrap_or_default();
+            if idle < policy.max_idle {
+                continue;
+            }
+            let items = self.hot.iter_partition(&partition_key)?.map(|data| data.map(|data| (data.sort_key.clone(), data))).collect::<io::Result<_>>()?;
+            self.cold.save_segment(&partition_key, &items)?;
+            fs::remove_dir_all(&partition_path)?;
+            moved.push(partition_key);
+        }
+        Ok(moved)
+    }
+
+    /// Moves a partition back from the object store, if it is there. Returns
+    /// whether it was.
+    pub fn recall(&self, partition_key: &str) -> io::Result<bool> {
+        let _guard = self.lock.write().unwrap();
+        self.recall_locked(partition_key)
+    }
+
+    /// `recall`, for callers already holding the lock for writing. The items go
+    /// through the journal, so a crash can't leave the partition half recalled.
+    fn recall_locked(&self, partition_key: &str) -> io::Result<bool> {
+        if self.is_hot(partition_key) {
+            return Ok(false);
+        }
+        let items: Vec<Data> = self.cold.load_segment(partition_key)?.into_values().collect();
+        if items.is_empty() {
+            return Ok(false);
+        }
+        self.hot.write_group(&items)?;
+        self.cold.delete_segment(partition_key)?;
+        Ok(true)
+    }
+
+    fn is_hot(&self, partition_key: &str) -> bool {
+        self.hot.path().join(partition_key).is_dir()
+    }
+}
+
+impl<S: ObjectStore + 'static> StorageBackend for TieredBackend<S> {
+    type Iter = Box<dyn Iterator<Item = io::Result<Data>> + Send>;
+
+    fn get(&self, partition_key: &str, sort_key: &str) -> io::Result<Option<Data>> {
+        let _guard = self.lock.read().unwrap();
+        match self.is_hot(partition_key) {
+            true => self.hot.get(partition_key, sort_key),
+            false => self.cold.get(partition_key, sort_key),
+        }
+    }
+
+    fn get_batch(&self, partition_key: &str, sort_keys: &[String]) -> io::Result<Vec<Option<Data>>> {
+        let _guard = self.lock.read().unwrap();
+        match self.is_hot(partition_key) {
+            true => self.hot.load_batch(partition_key, sort_keys),
+            false => self.cold.get_batch(partition_key, sort_keys),
+        }
+    }
+
+    fn put(&self, data: &Data) -> io::Result<()> {
+        let _guard = self.lock.write().unwrap();
+        self.recall_locked(&data.partition_key)?;
+        self.hot.save_data(data)
+    }
+
+    fn delete(&self, partition_key: &str, sort_key: &str) -> io::Result<()> {
+        let _guard = self.lock.write().unwrap();
+        self.recall_locked(partition_key)?;
+        StorageBackend::delete(&self.hot, partition_key, sort_key)
+    }
+
+    fn range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<Self::Iter> {
+        let _guard = self.lock.read().unwrap();
+        match self.is_hot(partition_key) {
+            true => Ok(Box::new(self.hot.iter_range(partition_key, from, to)?)),
+            false => Ok(Box::new(self.cold.range(partition_key, from, to)?)),
+        }
+    }
+
+    fn scan(&self, segment: usize, total_segments: usize) -> io::Result<Self::Iter> {
+        let _guard = self.lock.read().unwrap();
+        let hot = self.hot.iter_segment(segment, total_segments)?;
+        let mut cold_partitions = self.cold.list_partitions()?;
+        cold_partitions.retain(|partition_key| segment_of(partition_key, total_segments) == segment && !self.is_hot(partition_key));
+        // One cold segment is held in memory at a time, loaded once the items
+        // before it have been read.
+        let cold = Arc::clone(&self.cold);
+        let cold = cold_partitions.into_iter().flat_map(move |partition_key| match cold.load_segment(&partition_key) {
+            Ok(items) => items.into_values().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        });
+        Ok(Box::new(hot.chain(cold)))
+    }
+
+    fn list_partitions(&self) -> io::Result<Vec<String>> {
+        let _guard = self.lock.read().unwrap();
+        let mut partitions: BTreeSet<String> = self.hot.list_partitions()?.into_iter().collect();
+        partitions.extend(self.cold.list_partitions()?);
+        Ok(partitions.into_iter().collect())
+    }
+
+    fn write_batch(&self, batch: &[Data]) -> io::Result<()> {
+        let _guard = self.lock.write().unwrap();
+        let partitions: BTreeSet<&str> = batch.iter().map(|data| data.partition_key.as_str()).collect();
+        for partition_key in partitions {
+            self.recall_locked(partition_key)?;
+        }
+        self.hot.write_group(batch)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// When anything in a partition directory was last written: the directory itself
+/// changes when items are created or deleted, the files when they are overwritten.
+fn last_modified(partition_path: &Path) -> io::Result<SystemTime> {
+    let mut modified = fs::metadata(partition_path)?.modified()?;
+    for entry in fs::read_dir(partition_path)? {
+        modified = modified.max(entry?.metadata()?.modified()?);
+    }
+    Ok(modified)
+}
@@ -1,2 +1,5 @@
 pub mod db;
-pub mod utils;
\ No newline at end of file
+pub mod utils;
+pub mod server;
+pub mod client;
+pub mod repl;
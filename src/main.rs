@@ -1,112 +1,534 @@
-use std::io;
 use std::env;
-use std::path::PathBuf;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-use data_ferret::db::Data;
-use data_ferret::db::Database;
-use data_ferret::db::OperationType;
+use serde::Serialize;
+
+use data_ferret::db::{self, Attribute, Data, Database, EncryptionKey, ExportFormat, ExportWriter, Filter, FsckMode, ImportFormat, ImportOptions, Keyring, Selection};
+use data_ferret::repl;
+use data_ferret::utils::format_table;
+
+const USAGE: &str = "Usage: ferret <command> --db <path> [--table <name>] [--key-file <path>] [--read-only] [--format table|json] [arguments]
+
+Commands:
+  get <partition> <sort>
+  put <partition> <sort> [<value> | --file <path>]   reads the value from stdin if omitted or '-'
+  delete <partition> <sort>
+  query <partition> [--begins-with <prefix>] [--from <sort>] [--to <sort>] [--limit <n>]
+  scan [--limit <n>]
+  import [<file>] [--input-format csv|jsonl|dump] [--pk <field>] [--sk <field>] [--value <field> | --value '*']
+                      reads stdin if <file> is omitted; fields default to partition_key, sort_key
+                      and value, and '*' stores the whole record as the value
+  export [<file>] [--output-format jsonl|csv|dump] [--partition <partition> [--from <sort>] [--to <sort>]]
+                      writes stdout if <file> is omitted; a dump holds all tables and can be imported
+  stats
+  compact
+  migrate <legacy-dir>
+                      copies a directory in the original one-JSON-file-per-item layout into
+                      the database, reporting corrupt files; resumes if interrupted
+  rotate-key --key-file <path>
+                      adds a new active key to the key file, creating it if needed;
+                      doesn't open a database, so --db may be omitted
+  reencrypt           rewrites records that aren't encrypted with the active key
+  fsck [--mode verify|repair]
+                      checks every item file; repair moves unusable files into the
+                      keyspace's .quarantine directory
+  repl                interactive shell, type HELP for its statements
+
+--read-only opens the database without locking it, so it can be read while another process
+writes to it; writes fail.
+
+Exit status: 0 on success, 1 on errors (including records an import or migration skipped and corruption found by fsck), 2 on invalid usage, 3 if the item, table or file does not exist.";
+
+const COMMANDS: [&str; 14] = [
+    "get", "put", "delete", "query", "scan", "import", "export", "stats", "compact", "migrate", "fsck", "rotate-key", "reencrypt", "repl",
+];
+
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+const EXIT_NOT_FOUND: i32 = 3;
+
+#[derive(PartialEq)]
+enum Format {
+    Table,
+    Json,
+}
+
+struct Options {
+    db: PathBuf,
+    table: Option<String>,
+    key_file: Option<PathBuf>,
+    read_only: bool,
+    format: Format,
+    file: Option<String>,
+    begins_with: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<usize>,
+    input_format: Option<String>,
+    output_format: Option<String>,
+    partition: Option<String>,
+    mode: Option<String>,
+    partition_key_field: Option<String>,
+    sort_key_field: Option<String>,
+    value_field: Option<String>,
+    arguments: Vec<String>,
+}
 
 fn main() {
-    // Use the first command-line argument for the database file
-    let args: Vec<String> = env::args().collect();
-    let path = if args.len() > 1 {
-        PathBuf::from(&args[1])
-    } else {
-        PathBuf::from(env::current_dir().expect("Failed to get current dir"))
-    };
-
-    let mut db = Database::new(path);
-
-    loop {
-        println!("1. Insert data");
-        println!("2. Retrieve data");
-        println!("3. Delete data");
-        println!("4. Batch insert");
-        println!("5. Exit");
-
-        let mut choice = String::new();
-        io::stdin().read_line(&mut choice).expect("Failed to read line");
-
-        match choice.trim().parse() {
-            Ok(1) => {
-                let (partition_key, sort_key, value) = get_data_from_user();
-                db.insert(partition_key, sort_key, value).expect("Failed to insert data");
-            },
-            Ok(2) => {
-                let (partition_key, sort_key) = get_keys_from_user();
-                match db.get(partition_key, sort_key) {
-                    Ok(Some(data)) => println!("Retrieved data: {:?}", data),
-                    Ok(None) => println!("No data found"),
-                    Err(e) => println!("Failed to get data: {}", e),
-                }
-            },
-            Ok(3) => {
-                let (partition_key, sort_key) = get_keys_from_user();
-                match db.delete(partition_key, sort_key) {
-                    Ok(_) => println!("Data deleted successfully"),
-                    Err(e) => println!("Failed to delete data: {}", e),
+    // Exiting skips destructors, so it waits until `run` has closed the database.
+    process::exit(run());
+}
+
+/// Runs the command, returning the exit status.
+fn run() -> i32 {
+    let mut args = env::args().skip(1);
+    let command = match args.next() {
+        Some(command) if command == "-h" || command == "--help" => {
+            println!("{}", USAGE);
+            return 0;
+        },
+        Some(command) if COMMANDS.contains(&command.as_str()) => command,
+        _ => exit_with_usage(),
+    };
+    let options = parse_options(args, command != "rotate-key");
+
+    if command == "rotate-key" {
+        return exit_status(rotate_key(&options));
+    }
+
+    let opened = match options.read_only {
+        true => Database::open_read_only(options.db.clone()),
+        false => Database::open(options.db.clone()),
+    };
+    let root = match opened {
+        Ok(root) => root,
+        Err(e) => return exit_status(Err(e)),
+    };
+    if let Some(pid) = root.stale_lock() {
+        eprintln!("ferret: process {} exited without closing the database; took over its lock", pid);
+    }
+    if let Some(key_file) = &options.key_file {
+        if let Err(e) = Keyring::load(key_file).and_then(|keyring| root.set_keyring(Some(keyring))) {
+            return exit_status(Err(e));
+        }
+    }
+    let mut database = match &options.table {
+        Some(table) => match root.table(table) {
+            Ok(table) => table,
+            Err(e) => return exit_status(Err(e)),
+        },
+        None => root,
+    };
+
+    let result = match (command.as_str(), options.arguments.as_slice()) {
+        ("get", [partition_key, sort_key]) => get(&mut database, &options, partition_key, sort_key),
+        ("put", [partition_key, sort_key]) => read_value(options.file.as_deref().unwrap_or("-"))
+            .and_then(|value| database.insert(partition_key.clone(), sort_key.clone(), value)),
+        ("put", [partition_key, sort_key, value]) if options.file.is_none() => {
+            let value = if value == "-" { read_value("-") } else { Ok(value.clone()) };
+            value.and_then(|value| database.insert(partition_key.clone(), sort_key.clone(), value))
+        },
+        ("delete", [partition_key, sort_key]) => database.delete(partition_key.clone(), sort_key.clone()),
+        ("query", [partition_key]) => query(&database, &options, partition_key),
+        ("scan", []) => scan(&database, &options),
+        ("import", []) => import(&mut database, &options, "-"),
+        ("import", [file]) => import(&mut database, &options, file),
+        ("export", []) => export(&database, &options, None),
+        ("export", [file]) => export(&database, &options, Some(file)),
+        ("stats", []) => stats(&database, &options),
+        ("migrate", [source]) => migrate(&mut database, &options, source),
+        ("fsck", []) => fsck(&database, &options),
+        ("reencrypt", []) => reencrypt(&database, &options),
+        ("repl", []) => repl::run(database),
+        ("compact", []) => database.compact().and_then(|removed| match options.format {
+            Format::Json => print_json(&serde_json::json!({ "removed_partitions": removed })),
+            Format::Table => print_rows(vec![vec!["removed_partitions".to_string(), removed.to_string()]]),
+        }),
+        _ => Err(Exit::Usage.into()),
+    };
+    exit_status(result)
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>, needs_db: bool) -> Options {
+    let mut db = None;
+    let mut options = Options {
+        db: PathBuf::new(),
+        table: None,
+        key_file: None,
+        read_only: false,
+        format: Format::Table,
+        file: None,
+        begins_with: None,
+        from: None,
+        to: None,
+        limit: None,
+        input_format: None,
+        output_format: None,
+        partition: None,
+        mode: None,
+        partition_key_field: None,
+        sort_key_field: None,
+        value_field: None,
+        arguments: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            options.arguments.push(arg);
+            continue;
+        }
+        if arg == "--read-only" {
+            options.read_only = true;
+            continue;
+        }
+        let value = args.next().unwrap_or_else(|| exit_with_usage());
+        match arg.as_str() {
+            "--db" => db = Some(PathBuf::from(value)),
+            "--table" => options.table = Some(value),
+            "--key-file" => options.key_file = Some(PathBuf::from(value)),
+            "--format" => {
+                options.format = match value.as_str() {
+                    "table" => Format::Table,
+                    "json" => Format::Json,
+                    _ => exit_with_usage(),
                 }
             },
-            Ok(4) => {
-                let data = get_batch_data_from_user();
-                db.batch(data).expect("Failed to insert data in batch");
-            },
-            Ok(5) => break,
-            _ => println!("Invalid choice"),
+            "--file" => options.file = Some(value),
+            "--begins-with" => options.begins_with = Some(value),
+            "--from" => options.from = Some(value),
+            "--to" => options.to = Some(value),
+            "--input-format" => options.input_format = Some(value),
+            "--output-format" => options.output_format = Some(value),
+            "--partition" => options.partition = Some(value),
+            "--mode" => options.mode = Some(value),
+            "--pk" => options.partition_key_field = Some(value),
+            "--sk" => options.sort_key_field = Some(value),
+            "--value" => options.value_field = Some(value),
+            "--limit" => options.limit = Some(value.parse().unwrap_or_else(|_| exit_with_usage())),
+            _ => exit_with_usage(),
         }
     }
+
+    // Opening a database creates its directory, so a mistyped path must not be guessed.
+    // Only the key file commands run without one.
+    options.db = match (db, needs_db) {
+        (Some(db), _) => db,
+        (None, false) => PathBuf::new(),
+        (None, true) => exit_with_usage(),
+    };
+    options
+}
+
+fn get(database: &mut Database, options: &Options, partition_key: &str, sort_key: &str) -> io::Result<()> {
+    match database.get(partition_key.to_string(), sort_key.to_string()) {
+        Ok(Some(data)) => print_items(options, vec![data]),
+        Ok(None) => Err(io::Error::new(io::ErrorKind::NotFound, "item not found")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(io::ErrorKind::NotFound, "item not found")),
+        Err(e) => Err(e),
+    }
 }
-fn get_batch_data_from_user() -> Vec<Data> {
-    let mut data = Vec::new();
-    loop {
-        println!("Enter a partition key (or 'done' to finish):");
-        let mut partition_key = String::new();
-        io::stdin().read_line(&mut partition_key).expect("Failed to read line");
-        let partition_key = partition_key.trim().to_string();
-        if partition_key == "done" {
-            break;
+
+fn query(database: &Database, options: &Options, partition_key: &str) -> io::Result<()> {
+    let mut conditions = Vec::new();
+    if let Some(prefix) = &options.begins_with {
+        conditions.push(Filter::BeginsWith(Attribute::SortKey, prefix.clone()));
+    }
+    if let Some(from) = &options.from {
+        conditions.push(Filter::Ge(Attribute::SortKey, from.clone()));
+    }
+    if let Some(to) = &options.to {
+        conditions.push(Filter::Le(Attribute::SortKey, to.clone()));
+    }
+    let filter = conditions.into_iter().reduce(Filter::and);
+    let items = database.query(partition_key, filter.as_ref(), options.limit)?;
+    print_items(options, items)
+}
+
+fn scan(database: &Database, options: &Options) -> io::Result<()> {
+    let items = database.iter()?.take(options.limit.unwrap_or(usize::MAX));
+    if options.format == Format::Json {
+        // Streamed, so that scanning a large database doesn't hold it in memory.
+        let mut writer = ExportWriter::new(io::stdout().lock(), ExportFormat::JsonLines)?;
+        for data in items {
+            writer.write(&data?)?;
         }
+        return writer.finish().map(|_| ());
+    }
+    print_items(options, items.collect::<io::Result<_>>()?)
+}
+
+fn import(database: &mut Database, options: &Options, file: &str) -> io::Result<()> {
+    let reader: Box<dyn BufRead> = match file {
+        "-" => Box::new(io::stdin().lock()),
+        file => Box::new(BufReader::new(File::open(file)?)),
+    };
+    let format = match options.input_format.as_deref() {
+        Some("csv") => ImportFormat::Csv,
+        Some("jsonl") => ImportFormat::JsonLines,
+        Some("dump") => return restore(database, options, reader),
+        Some(_) => return Err(Exit::Usage.into()),
+        None if file.ends_with(".csv") => ImportFormat::Csv,
+        None if file.ends_with(".dump") => return restore(database, options, reader),
+        None => ImportFormat::JsonLines,
+    };
+
+    let mut import_options = ImportOptions::new(format);
+    if let Some(field) = &options.partition_key_field {
+        import_options.mapping.partition_key = field.clone();
+    }
+    if let Some(field) = &options.sort_key_field {
+        import_options.mapping.sort_key = field.clone();
+    }
+    if let Some(field) = &options.value_field {
+        import_options.mapping.value = if field == "*" { None } else { Some(field.clone()) };
+    }
+
+    let report = db::import(database, reader, &import_options)?;
+    match options.format {
+        Format::Json => print_json(&report)?,
+        Format::Table => {
+            for error in &report.errors {
+                eprintln!("line {}: {}", error.line, error.message);
+            }
+            print_rows(vec![
+                vec!["imported".to_string(), report.imported.to_string()],
+                vec!["failed".to_string(), report.failed.to_string()],
+            ])?;
+        },
+    }
+    if report.failed > 0 {
+        return Err(Exit::Failure.into());
+    }
+    Ok(())
+}
+
+fn restore(database: &mut Database, options: &Options, reader: impl Read) -> io::Result<()> {
+    let restored = db::restore(database, reader)?;
+    match options.format {
+        Format::Json => print_json(&serde_json::json!({ "imported": restored })),
+        Format::Table => print_rows(vec![vec!["imported".to_string(), restored.to_string()]]),
+    }
+}
 
-        println!("Enter a sort key:");
-        let mut sort_key = String::new();
-        io::stdin().read_line(&mut sort_key).expect("Failed to read line");
-        let sort_key = sort_key.trim().to_string();
+fn export(database: &Database, options: &Options, file: Option<&str>) -> io::Result<()> {
+    // `None` is a dump.
+    let format = match options.output_format.as_deref() {
+        Some("csv") => Some(ExportFormat::Csv),
+        Some("jsonl") => Some(ExportFormat::JsonLines),
+        Some("dump") => None,
+        Some(_) => return Err(Exit::Usage.into()),
+        None if file.is_some_and(|file| file.ends_with(".csv")) => Some(ExportFormat::Csv),
+        None if file.is_some_and(|file| file.ends_with(".dump")) => None,
+        None => Some(ExportFormat::JsonLines),
+    };
 
-        println!("Enter a value:");
-        let mut value = String::new();
-        io::stdin().read_line(&mut value).expect("Failed to read line");
-        let value = value.trim().to_string();
+    let selection = match (&options.partition, &options.from, &options.to) {
+        (None, None, None) => Selection::All,
+        (None, _, _) => return Err(Exit::Usage.into()),
+        // A dump always holds the whole database.
+        _ if format.is_none() => return Err(Exit::Usage.into()),
+        (Some(partition_key), None, None) => Selection::Partition(partition_key.clone()),
+        (Some(partition_key), from, to) => Selection::Range { partition_key: partition_key.clone(), from: from.clone(), to: to.clone() },
+    };
 
-        data.push(Data { operation_type: OperationType::Insert, partition_key, sort_key, value });
+    // Opened only once the options are valid, so a usage error leaves an existing file alone.
+    let out: Box<dyn Write> = match file {
+        None | Some("-") => Box::new(io::stdout().lock()),
+        Some(file) => Box::new(File::create(file)?),
+    };
+    match format {
+        Some(format) => db::export(database, &selection, format, out).map(|_| ()),
+        None => db::write_dump(database, out).map(|_| ()),
     }
+}
 
-    data
+fn migrate(database: &mut Database, options: &Options, source: &str) -> io::Result<()> {
+    let report = db::migrate(Path::new(source), database)?;
+    match options.format {
+        Format::Json => print_json(&report)?,
+        Format::Table => {
+            for corrupt in &report.corrupt {
+                eprintln!("{}: {}", corrupt.path.display(), corrupt.reason);
+            }
+            print_rows(vec![
+                vec!["migrated".to_string(), report.migrated.to_string()],
+                vec!["resumed_after".to_string(), report.resumed_after.to_string()],
+                vec!["corrupt".to_string(), report.corrupt.len().to_string()],
+            ])?;
+        },
+    }
+    if !report.corrupt.is_empty() {
+        return Err(Exit::Failure.into());
+    }
+    Ok(())
 }
 
-fn get_data_from_user() -> (String, String, String) {
-    let mut partition_key = String::new();
-    println!("Enter a partition key:");
-    io::stdin().read_line(&mut partition_key).expect("Failed to read line");
+fn fsck(database: &Database, options: &Options) -> io::Result<()> {
+    let mode = match options.mode.as_deref() {
+        None | Some("verify") => FsckMode::Verify,
+        Some("repair") => FsckMode::Repair,
+        Some(_) => return Err(Exit::Usage.into()),
+    };
+    let report = db::fsck(database, mode)?;
+    match options.format {
+        Format::Json => print_json(&report)?,
+        Format::Table => {
+            for corrupt in &report.corrupt {
+                eprintln!("{}: {}", corrupt.path.display(), corrupt.reason);
+            }
+            print_rows(vec![
+                vec!["checked".to_string(), report.checked.to_string()],
+                vec!["corrupt".to_string(), report.corrupt.len().to_string()],
+                vec!["quarantined".to_string(), report.quarantined.to_string()],
+                vec!["removed_partitions".to_string(), report.removed_partitions.to_string()],
+            ])?;
+        },
+    }
+    // Once repaired, the corruption that was found no longer affects the database.
+    if mode == FsckMode::Verify && !report.corrupt.is_empty() {
+        return Err(Exit::Failure.into());
+    }
+    Ok(())
+}
 
-    let mut sort_key = String::new();
-    println!("Enter a sort key:");
-    io::stdin().read_line(&mut sort_key).expect("Failed to read line");
+/// Adds a freshly generated key to the key file and makes it the active one.
+fn rotate_key(options: &Options) -> io::Result<()> {
+    let key_file = options.key_file.as_deref().unwrap_or_else(|| exit_with_usage());
+    let keyring = match Keyring::load(key_file) {
+        Ok(mut keyring) => {
+            keyring.rotate(EncryptionKey::generate(keyring.next_id()));
+            keyring
+        },
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Keyring::new(EncryptionKey::generate(1)),
+        Err(e) => return Err(e),
+    };
+    keyring.save(key_file)?;
+    match options.format {
+        Format::Json => print_json(&serde_json::json!({ "active_key": keyring.active_id() })),
+        Format::Table => print_rows(vec![vec!["active_key".to_string(), keyring.active_id().to_string()]]),
+    }
+}
+
+fn reencrypt(database: &Database, options: &Options) -> io::Result<()> {
+    let rewritten = database.start_reencryption()?.join()?;
+    match options.format {
+        Format::Json => print_json(&serde_json::json!({ "rewritten": rewritten })),
+        Format::Table => print_rows(vec![vec!["rewritten".to_string(), rewritten.to_string()]]),
+    }
+}
 
+fn stats(database: &Database, options: &Options) -> io::Result<()> {
+    let stats = database.stats()?;
+    match options.format {
+        Format::Json => print_json(&stats),
+        Format::Table => print_rows(vec![
+            vec!["partitions".to_string(), stats.partitions.to_string()],
+            vec!["items".to_string(), stats.items.to_string()],
+            vec!["disk_bytes".to_string(), stats.disk_bytes.to_string()],
+            vec!["uncompressed_bytes".to_string(), stats.uncompressed_bytes.to_string()],
+            vec!["compression_ratio".to_string(), format!("{:.2}", stats.compression_ratio)],
+        ]),
+    }
+}
+
+/// Reads a value from a file, or from stdin for `-`. A single trailing newline is
+/// dropped so that `echo value | ferret put ...` stores `value`.
+fn read_value(file: &str) -> io::Result<String> {
     let mut value = String::new();
-    println!("Enter a value:");
-    io::stdin().read_line(&mut value).expect("Failed to read line");
+    match file {
+        "-" => io::stdin().read_to_string(&mut value)?,
+        file => File::open(file)?.read_to_string(&mut value)?,
+    };
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
+
+fn print_items(options: &Options, items: Vec<Data>) -> io::Result<()> {
+    if options.format == Format::Json {
+        let mut writer = ExportWriter::new(io::stdout().lock(), ExportFormat::JsonLines)?;
+        for data in &items {
+            writer.write(data)?;
+        }
+        return writer.finish().map(|_| ());
+    }
+
+    let mut rows = vec![vec!["PARTITION_KEY".to_string(), "SORT_KEY".to_string(), "VALUE".to_string()]];
+    rows.extend(items.into_iter().map(|data| {
+        vec![data.partition_key, data.sort_key, data.value.replace('\n', "\\n")]
+    }));
+    print_rows(rows)
+}
+
+fn print_json<T: Serialize>(value: &T) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    serde_json::to_writer(&mut out, value)?;
+    writeln!(out)
+}
+
+fn print_rows(rows: Vec<Vec<String>>) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    out.write_all(format_table(&rows).as_bytes())?;
+    out.flush()
+}
 
-    (partition_key.trim().into(), sort_key.trim().into(), value.trim().into())
+/// Reports the outcome of a command and returns its exit status.
+fn exit_status(result: io::Result<()>) -> i32 {
+    let error = match result {
+        Ok(()) => return 0,
+        Err(error) => error,
+    };
+    match error.get_ref().and_then(|inner| inner.downcast_ref::<Exit>()) {
+        Some(Exit::Usage) => {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        },
+        Some(Exit::Failure) => return EXIT_FAILURE,
+        None => {},
+    }
+    // A closed pipe (`ferret scan | head`) is not a failure.
+    if error.kind() == io::ErrorKind::BrokenPipe {
+        return 0;
+    }
+    eprintln!("ferret: {}", error);
+    if error.kind() == io::ErrorKind::NotFound { EXIT_NOT_FOUND } else { EXIT_FAILURE }
 }
 
-fn get_keys_from_user() -> (String, String) {
-    let mut partition_key = String::new();
-    println!("Enter a partition key:");
-    io::stdin().read_line(&mut partition_key).expect("Failed to read line");
+/// Only used before a database is opened; afterwards commands return `Exit::Usage`
+/// so that the database is closed before exiting.
+fn exit_with_usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(EXIT_USAGE);
+}
 
-    let mut sort_key = String::new();
-    println!("Enter a sort key:");
-    io::stdin().read_line(&mut sort_key).expect("Failed to read line");
+/// Ends a command with a failure it has already reported, or with the usage.
+#[derive(Debug)]
+enum Exit {
+    Usage,
+    Failure,
+}
 
-    (partition_key.trim().into(), sort_key.trim().into())
-}
\ No newline at end of file
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Exit::Usage => f.write_str("invalid usage"),
+            Exit::Failure => f.write_str("failed"),
+        }
+    }
+}
+
+impl std::error::Error for Exit {}
+
+impl From<Exit> for io::Error {
+    fn from(exit: Exit) -> Self {
+        io::Error::other(exit)
+    }
+}
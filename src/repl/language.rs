@@ -0,0 +1,238 @@
+use crate::db::{Attribute, Filter};
+use std::io;
+
+/// The keyspace a statement runs against: a named table, or the root keyspace
+/// written as `.`.
+pub const ROOT_KEYSPACE: &str = ".";
+
+pub const KEYWORDS: [&str; 9] = ["GET", "PUT", "DELETE", "QUERY", "SCAN", "TABLES", "HELP", "EXIT", "QUIT"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Get { table: String, partition_key: String, sort_key: Option<String> },
+    Put { table: String, partition_key: String, sort_key: String, value: String },
+    Delete { table: String, partition_key: String, sort_key: String },
+    Query { table: String, partition_key: String, filter: Option<Filter>, limit: Option<usize> },
+    Scan { table: String, limit: Option<usize> },
+    Tables,
+    Help,
+    Exit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    /// Quoted tokens are always values, never keywords or operators.
+    quoted: bool,
+}
+
+/// Parses one statement:
+///
+/// ```text
+/// GET <table> <partition> [<sort>]
+/// PUT <table> <partition> <sort> <value>
+/// DELETE <table> <partition> <sort>
+/// QUERY <table> PK=<partition> [SK <op> <value> | SK BETWEEN <a> AND <b> | SK BEGINS_WITH <prefix>] [LIMIT <n>]
+/// SCAN <table> [LIMIT <n>]
+/// TABLES | HELP | EXIT
+/// ```
+///
+/// Keywords are case-insensitive. `=`, `<`, `<=`, `>` and `>=` are operators even
+/// without surrounding spaces, so values containing them or whitespace must be
+/// quoted with `"` or `'`.
+pub fn parse(input: &str) -> io::Result<Statement> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens: &tokens, position: 0 };
+    let command = parser.word("a statement")?;
+
+    let statement = match command.to_ascii_uppercase().as_str() {
+        "GET" => {
+            let table = parser.value("a table")?;
+            let partition_key = parser.value("a partition key")?;
+            let sort_key = parser.optional_value();
+            Statement::Get { table, partition_key, sort_key }
+        },
+        "PUT" => Statement::Put {
+            table: parser.value("a table")?,
+            partition_key: parser.value("a partition key")?,
+            sort_key: parser.value("a sort key")?,
+            value: parser.value("a value")?,
+        },
+        "DELETE" => Statement::Delete {
+            table: parser.value("a table")?,
+            partition_key: parser.value("a partition key")?,
+            sort_key: parser.value("a sort key")?,
+        },
+        "QUERY" => {
+            let table = parser.value("a table")?;
+            parser.keyword("PK")?;
+            parser.operator("=")?;
+            let partition_key = parser.value("a partition key")?;
+            let filter = if parser.accept_keyword("SK") { Some(parser.sort_key_condition()?) } else { None };
+            let limit = parser.limit()?;
+            Statement::Query { table, partition_key, filter, limit }
+        },
+        "SCAN" => Statement::Scan { table: parser.value("a table")?, limit: parser.limit()? },
+        "TABLES" => Statement::Tables,
+        "HELP" => Statement::Help,
+        "EXIT" | "QUIT" => Statement::Exit,
+        _ => return Err(syntax_error(format!("unknown statement '{}'", command))),
+    };
+
+    match parser.peek() {
+        None => Ok(statement),
+        Some(token) => Err(syntax_error(format!("unexpected '{}'", token.text))),
+    }
+}
+
+fn tokenize(input: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' || c == '\'' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') => match chars.next() {
+                        Some('n') => text.push('\n'),
+                        Some('t') => text.push('\t'),
+                        Some(escaped) => text.push(escaped),
+                        None => return Err(syntax_error("unterminated string".to_string())),
+                    },
+                    Some(end) if end == c => break,
+                    Some(other) => text.push(other),
+                    None => return Err(syntax_error("unterminated string".to_string())),
+                }
+            }
+            tokens.push(Token { text, quoted: true });
+        } else if c == '=' || c == '<' || c == '>' {
+            chars.next();
+            let mut text = c.to_string();
+            if c != '=' && chars.peek() == Some(&'=') {
+                text.push('=');
+                chars.next();
+            }
+            tokens.push(Token { text, quoted: false });
+        } else {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>') {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+            tokens.push(Token { text, quoted: false });
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self, expected: &str) -> io::Result<&'a Token> {
+        let token = self.tokens.get(self.position).ok_or_else(|| syntax_error(format!("expected {}", expected)))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn word(&mut self, expected: &str) -> io::Result<String> {
+        Ok(self.next(expected)?.text.clone())
+    }
+
+    fn value(&mut self, expected: &str) -> io::Result<String> {
+        let token = self.next(expected)?;
+        if !token.quoted && is_operator(&token.text) {
+            return Err(syntax_error(format!("expected {}, found '{}'", expected, token.text)));
+        }
+        Ok(token.text.clone())
+    }
+
+    fn optional_value(&mut self) -> Option<String> {
+        let token = self.peek()?;
+        if !token.quoted && is_operator(&token.text) {
+            return None;
+        }
+        self.position += 1;
+        Some(token.text.clone())
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|token| !token.quoted && token.text.eq_ignore_ascii_case(keyword))
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn keyword(&mut self, keyword: &str) -> io::Result<()> {
+        if self.accept_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(syntax_error(format!("expected {}", keyword)))
+        }
+    }
+
+    fn operator(&mut self, operator: &str) -> io::Result<()> {
+        match self.peek() {
+            Some(token) if !token.quoted && token.text == operator => {
+                self.position += 1;
+                Ok(())
+            },
+            _ => Err(syntax_error(format!("expected '{}'", operator))),
+        }
+    }
+
+    fn sort_key_condition(&mut self) -> io::Result<Filter> {
+        let token = self.next("a sort key condition")?;
+        let attribute = Attribute::SortKey;
+        if token.quoted {
+            return Err(syntax_error(format!("expected a sort key condition, found '{}'", token.text)));
+        }
+        Ok(match token.text.to_ascii_uppercase().as_str() {
+            "=" => Filter::Eq(attribute, self.value("a sort key")?),
+            "<" => Filter::Lt(attribute, self.value("a sort key")?),
+            "<=" => Filter::Le(attribute, self.value("a sort key")?),
+            ">" => Filter::Gt(attribute, self.value("a sort key")?),
+            ">=" => Filter::Ge(attribute, self.value("a sort key")?),
+            "BETWEEN" => {
+                let low = self.value("a sort key")?;
+                self.keyword("AND")?;
+                Filter::Between(attribute, low, self.value("a sort key")?)
+            },
+            "BEGINS_WITH" => Filter::BeginsWith(attribute, self.value("a prefix")?),
+            _ => return Err(syntax_error(format!("unknown sort key condition '{}'", token.text))),
+        })
+    }
+
+    fn limit(&mut self) -> io::Result<Option<usize>> {
+        if !self.accept_keyword("LIMIT") {
+            return Ok(None);
+        }
+        let limit = self.value("a limit")?;
+        limit.parse().map(Some).map_err(|_| syntax_error(format!("invalid limit '{}'", limit)))
+    }
+}
+
+fn is_operator(text: &str) -> bool {
+    matches!(text, "=" | "<" | "<=" | ">" | ">=")
+}
+
+fn syntax_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
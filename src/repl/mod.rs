@@ -0,0 +1,238 @@
+//! An interactive shell for inspecting and editing a database, started with
+//! `ferret repl --db <path>`. See [`parse`] for the statements it accepts.
+
+mod language;
+
+pub use self::language::{parse, Statement, KEYWORDS, ROOT_KEYSPACE};
+
+use crate::db::{Data, Database};
+use crate::utils::format_table;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const HELP: &str = "\
+GET <table> <partition> [<sort>]
+PUT <table> <partition> <sort> <value>
+DELETE <table> <partition> <sort>
+QUERY <table> PK=<partition> [SK <op> <value> | SK BETWEEN <a> AND <b> | SK BEGINS_WITH <prefix>] [LIMIT <n>]
+SCAN <table> [LIMIT <n>]
+TABLES
+EXIT
+
+<table> is a table name, or . for the root keyspace. <op> is one of = < <= > >=.
+Quote values containing spaces or operators with \" or '.
+";
+
+/// Executes statements against a database and its tables.
+pub struct Session {
+    root: Database,
+    tables: HashMap<String, Database>,
+}
+
+impl Session {
+    pub fn new(root: Database) -> Self {
+        Session { root, tables: HashMap::new() }
+    }
+
+    /// Runs a statement and returns the text to show for it.
+    pub fn execute(&mut self, statement: Statement) -> io::Result<String> {
+        match statement {
+            Statement::Get { table, partition_key, sort_key: Some(sort_key) } => {
+                match self.keyspace(&table)?.get(partition_key, sort_key) {
+                    Ok(Some(data)) => Ok(format!("{}\n", pretty_value(&data.value))),
+                    Ok(None) => Ok("(not found)\n".to_string()),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok("(not found)\n".to_string()),
+                    Err(e) => Err(e),
+                }
+            },
+            Statement::Get { table, partition_key, sort_key: None } => {
+                Ok(format_items(self.keyspace(&table)?.query(&partition_key, None, None)?))
+            },
+            Statement::Put { table, partition_key, sort_key, value } => {
+                self.keyspace(&table)?.insert(partition_key, sort_key, value)?;
+                Ok("OK\n".to_string())
+            },
+            Statement::Delete { table, partition_key, sort_key } => {
+                self.keyspace(&table)?.delete(partition_key, sort_key)?;
+                Ok("OK\n".to_string())
+            },
+            Statement::Query { table, partition_key, filter, limit } => {
+                Ok(format_items(self.keyspace(&table)?.query(&partition_key, filter.as_ref(), limit)?))
+            },
+            Statement::Scan { table, limit } => {
+                let items = self.keyspace(&table)?.iter()?.take(limit.unwrap_or(usize::MAX));
+                Ok(format_items(items.collect::<io::Result<_>>()?))
+            },
+            Statement::Tables => {
+                let mut output = format!("{}\n", ROOT_KEYSPACE);
+                for name in self.root.list_tables()? {
+                    output.push_str(&name);
+                    output.push('\n');
+                }
+                Ok(output)
+            },
+            Statement::Help => Ok(HELP.to_string()),
+            Statement::Exit => Ok(String::new()),
+        }
+    }
+
+    /// Completes the word before `position`: statements first, then tables, then
+    /// partition keys. Returns where the replaced word starts and the candidates.
+    pub fn complete(&mut self, line: &str, position: usize) -> (usize, Vec<String>) {
+        let before = &line[..position];
+        let mut start = before.rfind(char::is_whitespace).map_or(0, |index| index + 1);
+        let mut prefix = &before[start..];
+        let words: Vec<&str> = before[..start].split_whitespace().collect();
+
+        let candidates = match words.as_slice() {
+            [] => KEYWORDS.iter().map(|keyword| keyword.to_string()).collect(),
+            [command] if is_keyspace_command(command) => {
+                let mut tables = vec![ROOT_KEYSPACE.to_string()];
+                tables.extend(self.root.list_tables().unwrap_or_default());
+                tables
+            },
+            [command, table] if is_keyspace_command(command) && !command.eq_ignore_ascii_case("QUERY") => {
+                self.partition_keys(table)
+            },
+            [command, table] if command.eq_ignore_ascii_case("QUERY") && prefix.get(..3).is_some_and(|p| p.eq_ignore_ascii_case("PK=")) => {
+                start += 3;
+                prefix = &prefix[3..];
+                self.partition_keys(table)
+            },
+            _ => Vec::new(),
+        };
+
+        let matches = candidates
+            .into_iter()
+            .filter(|candidate| {
+                if words.is_empty() {
+                    candidate.to_ascii_uppercase().starts_with(&prefix.to_ascii_uppercase())
+                } else {
+                    candidate.starts_with(prefix)
+                }
+            })
+            .map(|candidate| quote(&candidate))
+            .collect();
+        (start, matches)
+    }
+
+    fn partition_keys(&mut self, table: &str) -> Vec<String> {
+        let mut keys = self.keyspace(table).and_then(|database| database.list_partitions()).unwrap_or_default();
+        keys.sort();
+        keys
+    }
+
+    fn keyspace(&mut self, table: &str) -> io::Result<&mut Database> {
+        if table == ROOT_KEYSPACE {
+            return Ok(&mut self.root);
+        }
+        if !self.tables.contains_key(table) {
+            let database = self.root.table(table)?;
+            self.tables.insert(table.to_string(), database);
+        }
+        Ok(self.tables.get_mut(table).unwrap())
+    }
+}
+
+/// Reads statements from the terminal until `EXIT` or end of input. History is
+/// kept in `~/.ferret_history`.
+pub fn run(root: Database) -> io::Result<()> {
+    let session = Rc::new(RefCell::new(Session::new(root)));
+    let mut editor: Editor<Completion, FileHistory> = Editor::new().map_err(io::Error::other)?;
+    editor.set_helper(Some(Completion { session: Rc::clone(&session) }));
+
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".ferret_history"));
+    if let Some(history) = &history {
+        // There is no history on first use.
+        let _ = editor.load_history(history);
+    }
+
+    loop {
+        let line = match editor.readline("ferret> ") {
+            Ok(line) => line,
+            // Ctrl-C abandons the current line, as in a shell.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        match parse(&line) {
+            Ok(Statement::Exit) => break,
+            Ok(statement) => match session.borrow_mut().execute(statement) {
+                Ok(output) => print!("{}", output),
+                Err(e) => eprintln!("error: {}", e),
+            },
+            Err(e) => eprintln!("error: {}", e),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+struct Completion {
+    session: Rc<RefCell<Session>>,
+}
+
+impl Completer for Completion {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, position: usize, _: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.session.borrow_mut().complete(line, position))
+    }
+}
+
+impl Hinter for Completion {
+    type Hint = String;
+}
+
+impl Highlighter for Completion {}
+
+impl Validator for Completion {}
+
+impl Helper for Completion {}
+
+fn is_keyspace_command(command: &str) -> bool {
+    ["GET", "PUT", "DELETE", "QUERY", "SCAN"].iter().any(|keyword| command.eq_ignore_ascii_case(keyword))
+}
+
+fn format_items(items: Vec<Data>) -> String {
+    let count = items.len();
+    let mut rows = vec![vec!["PARTITION_KEY".to_string(), "SORT_KEY".to_string(), "VALUE".to_string()]];
+    rows.extend(items.into_iter().map(|data| vec![data.partition_key, data.sort_key, data.value.replace('\n', "\\n")]));
+    format!("{}({} {})\n", format_table(&rows), count, if count == 1 { "item" } else { "items" })
+}
+
+/// JSON objects and arrays are indented; anything else is shown as stored.
+fn pretty_value(value: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(value) {
+        Ok(json) if json.is_object() || json.is_array() => serde_json::to_string_pretty(&json).unwrap_or_else(|_| value.to_string()),
+        _ => value.to_string(),
+    }
+}
+
+/// Quotes a completion candidate that the statement parser would otherwise split.
+fn quote(candidate: &str) -> String {
+    if candidate.is_empty() || candidate.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>')) {
+        format!("{:?}", candidate)
+    } else {
+        candidate.to_string()
+    }
+}
@@ -0,0 +1,551 @@
+//! A local stand-in for the DynamoDB JSON API, so SDK-based code can be tested
+//! against data-ferret.
+//!
+//! Requests are `POST /` with an `X-Amz-Target: DynamoDB_20120810.<Operation>`
+//! header. Supported operations: `PutItem`, `GetItem`, `DeleteItem`, `Query`,
+//! `Scan`, `BatchWriteItem` and `BatchGetItem`, plus `CreateTable`,
+//! `DescribeTable`, `DeleteTable`, `ListTables`, `UpdateTimeToLive` and
+//! `DescribeTimeToLive` to manage the tables they operate on.
+//!
+//! Every DynamoDB table is a named table of the hosted `Database`, and each item
+//! is stored as one `Data` whose value holds the item's attributes in DynamoDB
+//! JSON. Key attributes may be of type `S`, `N` or `B` and are compared as
+//! strings. Global secondary indexes map to the table's indexes, so they are
+//! keyed by a single attribute, project every attribute and can only be queried
+//! by equality. Condition, filter and projection expressions, pagination, local
+//! secondary indexes and index sort keys are not supported; requests using them
+//! are rejected with a `ValidationException` rather than silently ignored.
+
+use super::http::{serve_http, HttpRequest, HttpResponse};
+use super::tables::TableHandles;
+use super::tcp::{accept_loop, ShutdownHandle};
+use crate::db::{Attribute, Data, Database, Filter, IndexSchema, KeySchema, OperationType, TableDescription, TableSettings, TimeToLive};
+use serde_json::{json, Map, Value};
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+const TARGET_PREFIX: &str = "DynamoDB_20120810.";
+const CONTENT_TYPE: &str = "application/x-amz-json-1.0";
+/// Sort key under which items of tables without a range key are stored.
+const NO_SORT_KEY: &str = "$";
+
+pub struct DynamoServer {
+    listener: TcpListener,
+    database: Arc<Mutex<Database>>,
+    tables: Arc<TableHandles>,
+    shutdown: ShutdownHandle,
+}
+
+/// The hosted database and the handles of its tables.
+struct Hosted<'a> {
+    database: &'a Mutex<Database>,
+    tables: &'a TableHandles,
+}
+
+#[derive(Debug)]
+struct DynamoError {
+    status: u16,
+    kind: &'static str,
+    message: String,
+}
+
+type DynamoResult<T> = Result<T, DynamoError>;
+
+impl DynamoServer {
+    /// Binds a server for `database`, opening its tables through `tables`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, database: Arc<Mutex<Database>>, tables: Arc<TableHandles>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle::new(listener.local_addr()?);
+        Ok(DynamoServer { listener, database, tables, shutdown })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn run(self) -> io::Result<()> {
+        let (database, tables) = (self.database, self.tables);
+        accept_loop(self.listener, &self.shutdown, move |stream| {
+            serve_http(stream, |request| handle(&Hosted { database: &database, tables: &tables }, request))
+        })
+    }
+}
+
+impl DynamoError {
+    fn validation(message: impl Into<String>) -> Self {
+        DynamoError { status: 400, kind: "ValidationException", message: message.into() }
+    }
+}
+
+impl From<io::Error> for DynamoError {
+    fn from(error: io::Error) -> Self {
+        let (status, kind) = match error.kind() {
+            io::ErrorKind::NotFound => (400, "ResourceNotFoundException"),
+            io::ErrorKind::AlreadyExists => (400, "ResourceInUseException"),
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => (400, "ValidationException"),
+            _ => (500, "InternalServerError"),
+        };
+        DynamoError { status, kind, message: error.to_string() }
+    }
+}
+
+fn handle(hosted: &Hosted, request: &HttpRequest) -> HttpResponse {
+    let result = match request.header("X-Amz-Target").and_then(|target| target.strip_prefix(TARGET_PREFIX)) {
+        Some(operation) if request.method == "POST" => serde_json::from_slice::<Value>(&request.body)
+            .map_err(|e| DynamoError::validation(e.to_string()))
+            .and_then(|body| dispatch(hosted, operation, &body)),
+        _ => Err(DynamoError { status: 400, kind: "UnknownOperationException", message: "missing or unknown X-Amz-Target".to_string() }),
+    };
+
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err(e) => (e.status, json!({ "__type": format!("com.amazonaws.dynamodb.v20120810#{}", e.kind), "message": e.message })),
+    };
+    HttpResponse { content_type: CONTENT_TYPE, ..HttpResponse::json(status, &body) }
+}
+
+fn dispatch(hosted: &Hosted, operation: &str, body: &Value) -> DynamoResult<Value> {
+    let database = hosted.database;
+    match operation {
+        "CreateTable" => create_table(database, body),
+        "DescribeTable" => {
+            let description = database.lock().unwrap().describe_table(table_name(body)?)?;
+            Ok(json!({ "Table": describe(&description) }))
+        },
+        "DeleteTable" => {
+            let name = table_name(body)?;
+            let description = database.lock().unwrap().describe_table(name)?;
+            hosted.tables.drop_table(database, name)?;
+            Ok(json!({ "TableDescription": describe(&description) }))
+        },
+        "ListTables" => Ok(json!({ "TableNames": database.lock().unwrap().list_tables()? })),
+        "UpdateTimeToLive" => update_time_to_live(hosted, body),
+        "DescribeTimeToLive" => {
+            let time_to_live = database.lock().unwrap().describe_table(table_name(body)?)?.settings.time_to_live;
+            let description = match time_to_live {
+                Some(time_to_live) => json!({ "TimeToLiveStatus": "ENABLED", "AttributeName": time_to_live.attribute }),
+                None => json!({ "TimeToLiveStatus": "DISABLED" }),
+            };
+            Ok(json!({ "TimeToLiveDescription": description }))
+        },
+        "PutItem" => put_item(hosted, body),
+        "GetItem" => get_item(hosted, body),
+        "DeleteItem" => delete_item(hosted, body),
+        "Query" => query(hosted, body),
+        "Scan" => scan(hosted, body),
+        "BatchWriteItem" => batch_write_item(hosted, body),
+        "BatchGetItem" => batch_get_item(hosted, body),
+        _ => Err(DynamoError { status: 400, kind: "UnknownOperationException", message: format!("unsupported operation {}", operation) }),
+    }
+}
+
+fn create_table(database: &Mutex<Database>, body: &Value) -> DynamoResult<Value> {
+    reject_unsupported(body, &["LocalSecondaryIndexes"])?;
+    let (partition_key, sort_key) = parse_key_schema(&body["KeySchema"])?;
+    let mut settings = TableSettings::default();
+    for index in body["GlobalSecondaryIndexes"].as_array().into_iter().flatten() {
+        let name = index["IndexName"].as_str().ok_or_else(|| DynamoError::validation("IndexName is required"))?;
+        match parse_key_schema(&index["KeySchema"])? {
+            (attribute, sort_key) if sort_key.is_empty() => settings.indexes.push(IndexSchema { name: name.to_string(), attribute }),
+            _ => return Err(DynamoError::validation("index sort keys are not supported")),
+        }
+    }
+
+    let description = database.lock().unwrap().create_table_with_settings(table_name(body)?, KeySchema { partition_key, sort_key }, settings)?;
+    Ok(json!({ "TableDescription": describe(&description) }))
+}
+
+/// The HASH and RANGE attributes of a `KeySchema`; the latter empty if there is none.
+fn parse_key_schema(key_schema: &Value) -> DynamoResult<(String, String)> {
+    let mut partition_key = None;
+    let mut sort_key = String::new();
+    for element in key_schema.as_array().ok_or_else(|| DynamoError::validation("KeySchema is required"))? {
+        let name = element["AttributeName"].as_str().ok_or_else(|| DynamoError::validation("AttributeName is required"))?;
+        match element["KeyType"].as_str() {
+            Some("HASH") => partition_key = Some(name.to_string()),
+            Some("RANGE") => sort_key = name.to_string(),
+            _ => return Err(DynamoError::validation("KeyType must be HASH or RANGE")),
+        }
+    }
+    let partition_key = partition_key.ok_or_else(|| DynamoError::validation("KeySchema needs a HASH key"))?;
+    Ok((partition_key, sort_key))
+}
+
+fn update_time_to_live(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    let name = table_name(body)?;
+    let specification = &body["TimeToLiveSpecification"];
+    let attribute = specification["AttributeName"].as_str().ok_or_else(|| DynamoError::validation("AttributeName is required"))?;
+    let enabled = specification["Enabled"].as_bool().ok_or_else(|| DynamoError::validation("Enabled is required"))?;
+    let time_to_live = enabled.then(|| TimeToLive { attribute: attribute.to_string() });
+    hosted.database.lock().unwrap().set_table_time_to_live(name, time_to_live)?;
+    // Reopened with the new setting by the next request.
+    hosted.tables.close(name);
+    Ok(json!({ "TimeToLiveSpecification": specification }))
+}
+
+fn put_item(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    reject_unsupported(body, &["ConditionExpression", "Expected"])?;
+    let (table, schema) = open_table(hosted, table_name(body)?)?;
+    let mut table = table.lock().unwrap();
+    let data = item_to_data(&schema, item_map(&body["Item"], "Item")?)?;
+    table.insert(data.partition_key, data.sort_key, data.value)?;
+    Ok(json!({}))
+}
+
+fn get_item(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    reject_unsupported(body, &["ProjectionExpression", "AttributesToGet"])?;
+    let (table, schema) = open_table(hosted, table_name(body)?)?;
+    let mut table = table.lock().unwrap();
+    let (partition_key, sort_key) = key_of(&schema, item_map(&body["Key"], "Key")?)?;
+    match table.get(partition_key, sort_key) {
+        Ok(Some(data)) => Ok(json!({ "Item": data_to_item(&schema, &data) })),
+        Ok(None) => Ok(json!({})),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(json!({})),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn delete_item(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    reject_unsupported(body, &["ConditionExpression", "Expected"])?;
+    let (table, schema) = open_table(hosted, table_name(body)?)?;
+    let mut table = table.lock().unwrap();
+    let (partition_key, sort_key) = key_of(&schema, item_map(&body["Key"], "Key")?)?;
+    match table.delete(partition_key, sort_key) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(json!({})),
+    }
+}
+
+fn query(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    reject_unsupported(body, &["FilterExpression", "ProjectionExpression", "ExclusiveStartKey", "KeyConditions"])?;
+    let name = table_name(body)?;
+    let (table, schema) = open_table(hosted, name)?;
+    let table = table.lock().unwrap();
+    let expression = body["KeyConditionExpression"]
+        .as_str()
+        .ok_or_else(|| DynamoError::validation("KeyConditionExpression is required"))?;
+
+    let mut items = match body["IndexName"].as_str() {
+        Some(index) => {
+            let indexes = hosted.database.lock().unwrap().describe_table(name)?.settings.indexes;
+            let attribute = indexes.into_iter().find(|candidate| candidate.name == index)
+                .ok_or_else(|| DynamoError::validation(format!("the table has no index {}", index)))?
+                .attribute;
+            let (value, _) = parse_key_condition(expression, body, &KeySchema { partition_key: attribute, sort_key: String::new() })?;
+            table.query_index(index, &value, None)?
+        },
+        None => {
+            let (partition_key, filter) = parse_key_condition(expression, body, &schema)?;
+            table.query(&partition_key, filter.as_ref(), None)?
+        },
+    };
+    if body["ScanIndexForward"] == Value::Bool(false) {
+        items.reverse();
+    }
+    if let Some(limit) = body["Limit"].as_u64() {
+        items.truncate(limit as usize);
+    }
+    Ok(items_response(&schema, &items))
+}
+
+fn scan(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    reject_unsupported(body, &["FilterExpression", "ProjectionExpression", "IndexName", "ExclusiveStartKey", "ScanFilter"])?;
+    let (table, schema) = open_table(hosted, table_name(body)?)?;
+    let table = table.lock().unwrap();
+    let mut items = match (body["Segment"].as_u64(), body["TotalSegments"].as_u64()) {
+        (Some(segment), Some(total)) if segment < total => table.scan_segment(None, segment as usize, total as usize)?,
+        (None, None) => table.scan(None)?,
+        _ => return Err(DynamoError::validation("Segment must be less than TotalSegments and both must be set")),
+    };
+    items.sort_by(|a, b| (&a.partition_key, &a.sort_key).cmp(&(&b.partition_key, &b.sort_key)));
+    if let Some(limit) = body["Limit"].as_u64() {
+        items.truncate(limit as usize);
+    }
+    Ok(items_response(&schema, &items))
+}
+
+fn batch_write_item(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    let request_items = body["RequestItems"].as_object().ok_or_else(|| DynamoError::validation("RequestItems is required"))?;
+    for (name, requests) in request_items {
+        let (table, schema) = open_table(hosted, name)?;
+        let mut table = table.lock().unwrap();
+        let mut batch = Vec::new();
+        for request in requests.as_array().ok_or_else(|| DynamoError::validation("RequestItems entries must be lists"))? {
+            if let Some(put) = request.get("PutRequest") {
+                batch.push(item_to_data(&schema, item_map(&put["Item"], "Item")?)?);
+            } else if let Some(delete) = request.get("DeleteRequest") {
+                let (partition_key, sort_key) = key_of(&schema, item_map(&delete["Key"], "Key")?)?;
+                batch.push(Data { operation_type: OperationType::Delete, partition_key, sort_key, value: String::new() });
+            } else {
+                return Err(DynamoError::validation("expected PutRequest or DeleteRequest"));
+            }
+        }
+        // Deleting an item that doesn't exist is not an error in DynamoDB.
+        for item in batch {
+            let result = match item.operation_type {
+                OperationType::Delete => table.delete(item.partition_key, item.sort_key),
+                _ => table.insert(item.partition_key, item.sort_key, item.value),
+            };
+            if let Err(e) = result {
+                if e.kind() != io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+    Ok(json!({ "UnprocessedItems": {} }))
+}
+
+fn batch_get_item(hosted: &Hosted, body: &Value) -> DynamoResult<Value> {
+    let request_items = body["RequestItems"].as_object().ok_or_else(|| DynamoError::validation("RequestItems is required"))?;
+    let mut responses = Map::new();
+    for (name, request) in request_items {
+        reject_unsupported(request, &["ProjectionExpression", "AttributesToGet"])?;
+        let (table, schema) = open_table(hosted, name)?;
+        let mut table = table.lock().unwrap();
+        let mut keys = Vec::new();
+        for key in request["Keys"].as_array().ok_or_else(|| DynamoError::validation("Keys is required"))? {
+            keys.push(key_of(&schema, item_map(key, "Key")?)?);
+        }
+        let found = table.batch_get(keys)?.found;
+        responses.insert(name.clone(), found.iter().map(|data| data_to_item(&schema, data)).collect());
+    }
+    Ok(json!({ "Responses": responses, "UnprocessedKeys": {} }))
+}
+
+fn open_table(hosted: &Hosted, name: &str) -> DynamoResult<(Arc<Mutex<Database>>, KeySchema)> {
+    let schema = hosted.database.lock().unwrap().describe_table(name)?.key_schema;
+    Ok((hosted.tables.get(hosted.database, name)?, schema))
+}
+
+fn table_name(body: &Value) -> DynamoResult<&str> {
+    body["TableName"].as_str().ok_or_else(|| DynamoError::validation("TableName is required"))
+}
+
+fn reject_unsupported(body: &Value, parameters: &[&str]) -> DynamoResult<()> {
+    match parameters.iter().find(|parameter| body.get(**parameter).is_some()) {
+        Some(parameter) => Err(DynamoError::validation(format!("{} is not supported", parameter))),
+        None => Ok(()),
+    }
+}
+
+fn item_map<'a>(value: &'a Value, what: &str) -> DynamoResult<&'a Map<String, Value>> {
+    value.as_object().ok_or_else(|| DynamoError::validation(format!("{} must be a map of attributes", what)))
+}
+
+/// The string form of a scalar attribute value (`S`, `N` or `B`).
+fn scalar(value: &Value) -> Option<String> {
+    ["S", "N", "B"].iter().find_map(|kind| value.get(*kind).and_then(Value::as_str)).map(str::to_string)
+}
+
+fn key_of(schema: &KeySchema, item: &Map<String, Value>) -> DynamoResult<(String, String)> {
+    let attribute = |name: &str| {
+        item.get(name)
+            .and_then(scalar)
+            .ok_or_else(|| DynamoError::validation(format!("missing key attribute {}", name)))
+    };
+    let partition_key = attribute(&schema.partition_key)?;
+    let sort_key = if schema.sort_key.is_empty() { NO_SORT_KEY.to_string() } else { attribute(&schema.sort_key)? };
+    Ok((partition_key, sort_key))
+}
+
+fn item_to_data(schema: &KeySchema, item: &Map<String, Value>) -> DynamoResult<Data> {
+    let (partition_key, sort_key) = key_of(schema, item)?;
+    Ok(Data {
+        operation_type: OperationType::Insert,
+        partition_key,
+        sort_key,
+        value: Value::Object(item.clone()).to_string(),
+    })
+}
+
+/// Items written through this API store their attributes as the value; items
+/// written through other front-ends are exposed with their value as a `value`
+/// string attribute.
+fn data_to_item(schema: &KeySchema, data: &Data) -> Value {
+    if let Ok(Value::Object(item)) = serde_json::from_str::<Value>(&data.value) {
+        return Value::Object(item);
+    }
+    let mut item = Map::new();
+    item.insert(schema.partition_key.clone(), json!({ "S": data.partition_key }));
+    if !schema.sort_key.is_empty() {
+        item.insert(schema.sort_key.clone(), json!({ "S": data.sort_key }));
+    }
+    item.insert("value".to_string(), json!({ "S": data.value }));
+    Value::Object(item)
+}
+
+fn items_response(schema: &KeySchema, items: &[Data]) -> Value {
+    let items: Vec<Value> = items.iter().map(|data| data_to_item(schema, data)).collect();
+    json!({ "Items": items, "Count": items.len(), "ScannedCount": items.len() })
+}
+
+fn describe(description: &TableDescription) -> Value {
+    let schema = &description.key_schema;
+    let mut key_schema = vec![json!({ "AttributeName": schema.partition_key, "KeyType": "HASH" })];
+    let mut definitions = vec![json!({ "AttributeName": schema.partition_key, "AttributeType": "S" })];
+    if !schema.sort_key.is_empty() {
+        key_schema.push(json!({ "AttributeName": schema.sort_key, "KeyType": "RANGE" }));
+        definitions.push(json!({ "AttributeName": schema.sort_key, "AttributeType": "S" }));
+    }
+    for index in &description.settings.indexes {
+        if !definitions.iter().any(|definition| definition["AttributeName"] == index.attribute.as_str()) {
+            definitions.push(json!({ "AttributeName": index.attribute, "AttributeType": "S" }));
+        }
+    }
+    let indexes: Vec<Value> = description.settings.indexes.iter().map(|index| json!({
+        "IndexName": index.name,
+        "KeySchema": [{ "AttributeName": index.attribute, "KeyType": "HASH" }],
+        "Projection": { "ProjectionType": "ALL" },
+        "IndexStatus": "ACTIVE",
+    })).collect();
+    let mut table = json!({
+        "TableName": description.name,
+        "TableStatus": "ACTIVE",
+        "KeySchema": key_schema,
+        "AttributeDefinitions": definitions,
+        "CreationDateTime": description.created_at,
+    });
+    if !indexes.is_empty() {
+        table["GlobalSecondaryIndexes"] = Value::Array(indexes);
+    }
+    table
+}
+
+/// Parses a `KeyConditionExpression` such as `#pk = :p AND begins_with(sk, :s)`
+/// into the partition key it selects and a filter on the sort key.
+fn parse_key_condition(expression: &str, body: &Value, schema: &KeySchema) -> DynamoResult<(String, Option<Filter>)> {
+    let tokens = tokenize(expression)?;
+    let mut parser = ConditionParser { tokens: &tokens, position: 0, body };
+
+    let mut partition_key = None;
+    let mut filter = None;
+    loop {
+        let (attribute, condition) = parser.condition()?;
+        if attribute == schema.partition_key {
+            match condition {
+                Filter::Eq(_, value) if partition_key.is_none() => partition_key = Some(value),
+                _ => return Err(DynamoError::validation("the partition key condition must be a single equality")),
+            }
+        } else if attribute == schema.sort_key && filter.is_none() {
+            filter = Some(condition);
+        } else {
+            return Err(DynamoError::validation(format!("invalid key condition on {}", attribute)));
+        }
+
+        match parser.next() {
+            None => break,
+            Some(token) if token.eq_ignore_ascii_case("AND") => continue,
+            Some(token) => return Err(DynamoError::validation(format!("unexpected token {}", token))),
+        }
+    }
+
+    let partition_key = partition_key.ok_or_else(|| DynamoError::validation("the partition key must be specified"))?;
+    Ok((partition_key, filter))
+}
+
+fn tokenize(expression: &str) -> DynamoResult<Vec<String>> {
+    let mut tokens = Vec::new();
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {},
+            '(' | ')' | ',' | '=' => tokens.push(c.to_string()),
+            '<' | '>' => match chars.peek() {
+                Some('=') => {
+                    chars.next();
+                    tokens.push(format!("{}=", c));
+                },
+                _ => tokens.push(c.to_string()),
+            },
+            c if c.is_alphanumeric() || matches!(c, '#' | ':' | '_' | '.' | '-') => {
+                let mut token = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_alphanumeric() || matches!(next, '_' | '.' | '-') {
+                        token.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(token);
+            },
+            _ => return Err(DynamoError::validation(format!("unexpected character {} in key condition", c))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct ConditionParser<'a> {
+    tokens: &'a [String],
+    position: usize,
+    body: &'a Value,
+}
+
+impl ConditionParser<'_> {
+    fn next(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+        Some(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> DynamoResult<()> {
+        match self.next() {
+            Some(token) if token.eq_ignore_ascii_case(expected) => Ok(()),
+            other => Err(DynamoError::validation(format!("expected {} but found {}", expected, other.unwrap_or("end of expression")))),
+        }
+    }
+
+    fn name(&mut self) -> DynamoResult<String> {
+        let token = self.next().ok_or_else(|| DynamoError::validation("expected an attribute name"))?.to_string();
+        if token.starts_with('#') {
+            self.body["ExpressionAttributeNames"][&token]
+                .as_str()
+                .map(str::to_string)
+                .ok_or_else(|| DynamoError::validation(format!("undefined attribute name {}", token)))
+        } else {
+            Ok(token)
+        }
+    }
+
+    fn value(&mut self) -> DynamoResult<String> {
+        let token = self.next().ok_or_else(|| DynamoError::validation("expected a value placeholder"))?.to_string();
+        scalar(&self.body["ExpressionAttributeValues"][&token])
+            .ok_or_else(|| DynamoError::validation(format!("undefined attribute value {}", token)))
+    }
+
+    fn condition(&mut self) -> DynamoResult<(String, Filter)> {
+        if self.tokens.get(self.position).is_some_and(|token| token.eq_ignore_ascii_case("begins_with")) {
+            self.position += 1;
+            self.expect("(")?;
+            let name = self.name()?;
+            self.expect(",")?;
+            let prefix = self.value()?;
+            self.expect(")")?;
+            return Ok((name, Filter::BeginsWith(Attribute::SortKey, prefix)));
+        }
+
+        let name = self.name()?;
+        let attribute = Attribute::SortKey;
+        let operator = self.next().ok_or_else(|| DynamoError::validation("expected a comparison"))?.to_ascii_uppercase();
+        let condition = match operator.as_str() {
+            "=" => Filter::Eq(attribute, self.value()?),
+            "<" => Filter::Lt(attribute, self.value()?),
+            "<=" => Filter::Le(attribute, self.value()?),
+            ">" => Filter::Gt(attribute, self.value()?),
+            ">=" => Filter::Ge(attribute, self.value()?),
+            "BETWEEN" => {
+                let low = self.value()?;
+                self.expect("AND")?;
+                Filter::Between(attribute, low, self.value()?)
+            },
+            other => return Err(DynamoError::validation(format!("unsupported comparison {}", other))),
+        };
+        Ok((name, condition))
+    }
+}
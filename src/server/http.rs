@@ -0,0 +1,252 @@
+//! A small HTTP/1.1 implementation shared by the HTTP front-ends.
+//!
+//! Only what the front-ends need is supported: requests with a `Content-Length`
+//! body (no chunked uploads), persistent connections and pipelining.
+
+use super::tcp::read_line_limited;
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::TcpStream;
+
+const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+/// The longest request line or header line read, line break included.
+const MAX_LINE_LEN: usize = 16 * 1024;
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug)]
+pub(crate) struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub(crate) struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The percent-decoded, non-empty segments of the path. Fails with
+    /// `InvalidInput` if an escape decodes to a `/`, or a segment is `.` or `..`.
+    pub fn segments(&self) -> io::Result<Vec<String>> {
+        self.path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(|segment| match percent_decode(segment, false) {
+                decoded if decoded.contains('/') || decoded == "." || decoded == ".." => {
+                    Err(invalid(&format!("invalid path segment '{}'", segment)))
+                },
+                decoded => Ok(decoded),
+            })
+            .collect()
+    }
+
+    fn keep_alive(&self) -> bool {
+        !self.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+}
+
+impl HttpResponse {
+    pub fn json<T: Serialize>(status: u16, body: &T) -> Self {
+        match serde_json::to_vec(body) {
+            Ok(body) => HttpResponse { status, content_type: "application/json", body },
+            Err(e) => HttpResponse::error(500, &e.to_string()),
+        }
+    }
+
+    pub fn empty(status: u16) -> Self {
+        HttpResponse { status, content_type: "application/json", body: Vec::new() }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        HttpResponse::json(status, &serde_json::json!({ "error": message }))
+    }
+
+    pub fn from_io_error(error: &io::Error) -> Self {
+        HttpResponse::error(status_for(error.kind()), &error.to_string())
+    }
+}
+
+pub(crate) fn status_for(kind: io::ErrorKind) -> u16 {
+    match kind {
+        io::ErrorKind::NotFound => 404,
+        io::ErrorKind::AlreadyExists => 409,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => 400,
+        io::ErrorKind::PermissionDenied => 403,
+        _ => 500,
+    }
+}
+
+/// Reads requests from the connection and answers each with `handler` until the
+/// client closes it or asks for `Connection: close`.
+pub(crate) fn serve_http<F>(stream: TcpStream, handler: F) -> io::Result<()>
+where
+    F: Fn(&HttpRequest) -> HttpResponse,
+{
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let request = match read_request(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                write_response(&mut writer, &HttpResponse::error(400, &e.to_string()), false)?;
+                break;
+            },
+        };
+
+        let keep_alive = request.keep_alive();
+        write_response(&mut writer, &handler(&request), keep_alive)?;
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+        if !keep_alive {
+            break;
+        }
+    }
+    writer.flush()
+}
+
+/// Reads one request. Memory is bounded by the data received: lines, the number
+/// of headers and the body all have a maximum size.
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<HttpRequest>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target.to_string()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| invalid("connection closed inside headers"))?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(invalid("too many headers"));
+        }
+        let (name, value) = header.split_once(':').ok_or_else(|| invalid("malformed header"))?;
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), parse_query(query)),
+        None => (target, Vec::new()),
+    };
+    let mut request = HttpRequest { method, path, query, headers, body: Vec::new() };
+
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(invalid("chunked request bodies are not supported"));
+    }
+    if let Some(length) = request.header("Content-Length") {
+        let length: usize = length.parse().map_err(|_| invalid("invalid Content-Length"))?;
+        if length > MAX_BODY_SIZE {
+            return Err(invalid("request body too large"));
+        }
+        // Read as it arrives rather than allocated upfront from the header.
+        reader.take(length as u64).read_to_end(&mut request.body)?;
+        if request.body.len() < length {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed inside the request body"));
+        }
+    }
+    Ok(Some(request))
+}
+
+/// Reads a line of at most `MAX_LINE_LEN` bytes, or `None` at the end of input.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if read_line_limited(reader, &mut line, MAX_LINE_LEN)? == 0 {
+        return Ok(None);
+    }
+    String::from_utf8(line).map(Some).map_err(|_| invalid("request is not valid UTF-8"))
+}
+
+fn write_response<W: Write>(writer: &mut W, response: &HttpResponse, keep_alive: bool) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+    )?;
+    writer.write_all(&response.body)
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key, true), percent_decode(value, true)),
+            None => (percent_decode(pair, true), String::new()),
+        })
+        .collect()
+}
+
+/// Decodes `%XX` escapes. In query strings `+` also stands for a space.
+pub(crate) fn percent_decode(input: &str, plus_is_space: bool) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                (Some(high), Some(low)) => {
+                    decoded.push(high << 4 | low);
+                    i += 2;
+                },
+                _ => decoded.push(b'%'),
+            },
+            b'+' if plus_is_space => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message.to_string())
+}
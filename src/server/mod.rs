@@ -0,0 +1,14 @@
+mod dynamo;
+mod http;
+mod protocol;
+mod resp;
+mod rest;
+mod tables;
+mod tcp;
+
+pub use self::dynamo::DynamoServer;
+pub use self::protocol::{error_kind, execute, Request, Response};
+pub use self::resp::RespServer;
+pub use self::rest::RestServer;
+pub use self::tables::TableHandles;
+pub use self::tcp::{Server, ShutdownHandle};
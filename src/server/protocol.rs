@@ -0,0 +1,99 @@
+//! The line-based protocol spoken by `Server`.
+//!
+//! Every request and every response is a single JSON object terminated by a
+//! newline. Requests carry an `op` field:
+//!
+//! ```text
+//! {"op":"get","partition_key":"p","sort_key":"s"}
+//! {"op":"insert","partition_key":"p","sort_key":"s","value":"v"}
+//! {"op":"delete","partition_key":"p","sort_key":"s"}
+//! {"op":"batch","items":[{"type":"Data","operation_type":"Insert","partition_key":"p","sort_key":"s","value":"v"}]}
+//! {"op":"query","partition_key":"p","filter":{"BeginsWith":["SortKey","2023-"]},"limit":10}
+//! ```
+//!
+//! Responses carry a `type` field and are sent in the same order as the
+//! requests, so clients may pipeline several requests before reading:
+//!
+//! ```text
+//! {"type":"ok"}
+//! {"type":"item","data":{...}}          // "data" is null when the key doesn't exist
+//! {"type":"items","items":[{...}]}
+//! {"type":"error","kind":"NotFound","message":"..."}
+//! ```
+//!
+//! A malformed line, or one longer than 64 MiB, is answered with an error and
+//! the connection stays open.
+
+use crate::db::{Data, Database, Filter};
+use serde::{Serialize, Deserialize};
+use std::io;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    Get { partition_key: String, sort_key: String },
+    Insert { partition_key: String, sort_key: String, value: String },
+    Delete { partition_key: String, sort_key: String },
+    Batch { items: Vec<Data> },
+    Query {
+        partition_key: String,
+        #[serde(default)]
+        filter: Option<Filter>,
+        #[serde(default)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Item { data: Option<Data> },
+    Items { items: Vec<Data> },
+    Error { kind: String, message: String },
+}
+
+impl Response {
+    pub fn error(error: &io::Error) -> Self {
+        Response::Error {
+            kind: format!("{:?}", error.kind()),
+            message: error.to_string(),
+        }
+    }
+}
+
+/// Maps the `kind` of an error response back to an `io::ErrorKind`.
+pub fn error_kind(kind: &str) -> io::ErrorKind {
+    match kind {
+        "NotFound" => io::ErrorKind::NotFound,
+        "AlreadyExists" => io::ErrorKind::AlreadyExists,
+        "InvalidInput" => io::ErrorKind::InvalidInput,
+        "InvalidData" => io::ErrorKind::InvalidData,
+        "PermissionDenied" => io::ErrorKind::PermissionDenied,
+        "TimedOut" => io::ErrorKind::TimedOut,
+        "Unsupported" => io::ErrorKind::Unsupported,
+        _ => io::ErrorKind::Other,
+    }
+}
+
+/// Runs a request against the database and builds its response.
+pub fn execute(database: &mut Database, request: Request) -> Response {
+    let result = match request {
+        Request::Get { partition_key, sort_key } => match database.get(partition_key, sort_key) {
+            Ok(data) => Ok(Response::Item { data }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Response::Item { data: None }),
+            Err(e) => Err(e),
+        },
+        Request::Insert { partition_key, sort_key, value } => {
+            database.insert(partition_key, sort_key, value).map(|_| Response::Ok)
+        },
+        Request::Delete { partition_key, sort_key } => {
+            database.delete(partition_key, sort_key).map(|_| Response::Ok)
+        },
+        Request::Batch { items } => database.batch(items).map(|_| Response::Ok),
+        Request::Query { partition_key, filter, limit } => {
+            database.query(&partition_key, filter.as_ref(), limit).map(|items| Response::Items { items })
+        },
+    };
+    result.unwrap_or_else(|e| Response::error(&e))
+}
@@ -0,0 +1,325 @@
+//! A Redis (RESP2) front-end, so redis-cli and Redis client libraries can read
+//! and write a `Database`.
+//!
+//! Redis keys are partition keys:
+//!
+//! ```text
+//! GET key / SET key value          the item (key, "$")
+//! HSET key field value [...]       the items (key, field)
+//! HGET key field / HDEL key f [..]
+//! HGETALL key                      every item of the partition except "$"
+//! DEL key [..] / EXISTS key [..]   whole partitions
+//! SCAN cursor [MATCH pat] [COUNT n]   partition keys
+//! ```
+//!
+//! `PING`, `ECHO`, `SELECT 0`, `COMMAND` and `QUIT` are accepted as well so that
+//! common clients can connect. Both RESP arrays and inline commands are parsed.
+
+use super::tcp::{accept_loop, read_line_limited, ShutdownHandle};
+use crate::db::Database;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+/// Sort key under which plain `GET`/`SET` string values are stored.
+const STRING_SORT_KEY: &str = "$";
+const MAX_BULK_SIZE: usize = 512 * 1024 * 1024;
+const MAX_ARGUMENTS: usize = 1024;
+/// The longest inline command or header line read, line break included.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+pub struct RespServer {
+    listener: TcpListener,
+    database: Arc<Mutex<Database>>,
+    shutdown: ShutdownHandle,
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+impl RespServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A, database: Arc<Mutex<Database>>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle::new(listener.local_addr()?);
+        Ok(RespServer { listener, database, shutdown })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn run(self) -> io::Result<()> {
+        let database = self.database;
+        accept_loop(self.listener, &self.shutdown, move |stream| serve_connection(stream, &database))
+    }
+}
+
+fn serve_connection(stream: TcpStream, database: &Mutex<Database>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    loop {
+        let command = match read_command(&mut reader) {
+            Ok(Some(command)) => command,
+            Ok(None) => break,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                write_reply(&mut writer, &Reply::Error(format!("ERR Protocol error: {}", e)))?;
+                break;
+            },
+            Err(e) => return Err(e),
+        };
+        if command.is_empty() {
+            continue;
+        }
+
+        let quit = command[0].eq_ignore_ascii_case("QUIT");
+        let reply = if quit { Reply::Status("OK") } else { execute(&mut database.lock().unwrap(), &command) };
+        write_reply(&mut writer, &reply)?;
+        if quit {
+            break;
+        }
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    writer.flush()
+}
+
+fn read_command<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<String>>> {
+    let line = match read_line(reader)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let line = line.trim_end_matches(['\r', '\n']);
+
+    let count = match line.strip_prefix('*') {
+        Some(count) => count
+            .parse::<usize>()
+            .ok()
+            .filter(|count| *count <= MAX_ARGUMENTS)
+            .ok_or_else(|| protocol_error("invalid multibulk length"))?,
+        // Inline command, as typed into telnet.
+        None => return Ok(Some(line.split_whitespace().map(str::to_string).collect())),
+    };
+
+    // Nothing is allocated from the lengths the client announces, only for the
+    // bytes that actually arrive.
+    let mut arguments = Vec::new();
+    for _ in 0..count {
+        let header = read_line(reader)?.unwrap_or_default();
+        let length = header
+            .trim_end()
+            .strip_prefix('$')
+            .and_then(|length| length.parse::<usize>().ok())
+            .filter(|length| *length <= MAX_BULK_SIZE)
+            .ok_or_else(|| protocol_error("expected a bulk string"))?;
+        let mut argument = Vec::new();
+        if reader.take(length as u64 + 2).read_to_end(&mut argument)? < length + 2 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed inside a bulk string"));
+        }
+        argument.truncate(length);
+        arguments.push(String::from_utf8(argument).map_err(|_| protocol_error("arguments must be UTF-8"))?);
+    }
+    Ok(Some(arguments))
+}
+
+/// Reads a line of at most `MAX_LINE_LEN` bytes, or `None` at the end of input.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if read_line_limited(reader, &mut line, MAX_LINE_LEN)? == 0 {
+        return Ok(None);
+    }
+    String::from_utf8(line).map(Some).map_err(|_| protocol_error("commands must be UTF-8"))
+}
+
+fn write_reply<W: Write>(writer: &mut W, reply: &Reply) -> io::Result<()> {
+    match reply {
+        Reply::Status(status) => write!(writer, "+{}\r\n", status),
+        Reply::Error(message) => write!(writer, "-{}\r\n", message.replace(['\r', '\n'], " ")),
+        Reply::Integer(value) => write!(writer, ":{}\r\n", value),
+        Reply::Bulk(None) => write!(writer, "$-1\r\n"),
+        Reply::Bulk(Some(value)) => write!(writer, "${}\r\n{}\r\n", value.len(), value),
+        Reply::Array(items) => {
+            write!(writer, "*{}\r\n", items.len())?;
+            items.iter().try_for_each(|item| write_reply(writer, item))
+        },
+    }
+}
+
+fn execute(database: &mut Database, command: &[String]) -> Reply {
+    let name = command[0].to_ascii_uppercase();
+    let arguments = &command[1..];
+    let result = match (name.as_str(), arguments) {
+        ("PING", []) => Ok(Reply::Status("PONG")),
+        ("PING", [message]) | ("ECHO", [message]) => Ok(Reply::Bulk(Some(message.clone()))),
+        ("SELECT", [index]) if index == "0" => Ok(Reply::Status("OK")),
+        ("SELECT", [_]) => Ok(Reply::Error("ERR DB index is out of range".to_string())),
+        ("COMMAND", _) => Ok(Reply::Array(Vec::new())),
+        ("GET", [key]) => get(database, key, STRING_SORT_KEY).map(Reply::Bulk),
+        ("SET", [key, value]) => database
+            .insert(key.clone(), STRING_SORT_KEY.to_string(), value.clone())
+            .map(|_| Reply::Status("OK")),
+        ("SET", [_, _, ..]) => Ok(Reply::Error("ERR SET options are not supported".to_string())),
+        ("DEL", [_, ..]) => delete_partitions(database, arguments),
+        ("EXISTS", [_, ..]) => arguments
+            .iter()
+            .try_fold(0, |count, key| partition_exists(database, key).map(|exists| count + exists as i64))
+            .map(Reply::Integer),
+        ("HSET", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            hset(database, key, pairs).map(Reply::Integer)
+        },
+        ("HGET", [key, field]) => get(database, key, field).map(Reply::Bulk),
+        ("HDEL", [key, fields @ ..]) if !fields.is_empty() => hdel(database, key, fields).map(Reply::Integer),
+        ("HGETALL", [key]) => database.query(key, None, None).map(|items| {
+            Reply::Array(
+                items
+                    .into_iter()
+                    .filter(|data| data.sort_key != STRING_SORT_KEY)
+                    .flat_map(|data| [Reply::Bulk(Some(data.sort_key)), Reply::Bulk(Some(data.value))])
+                    .collect(),
+            )
+        }),
+        ("SCAN", [cursor, options @ ..]) => scan(database, cursor, options),
+        ("GET" | "SET" | "DEL" | "EXISTS" | "HSET" | "HGET" | "HDEL" | "HGETALL" | "SCAN" | "PING" | "ECHO" | "SELECT", _) => {
+            Ok(Reply::Error(format!("ERR wrong number of arguments for '{}' command", command[0].to_lowercase())))
+        },
+        _ => Ok(Reply::Error(format!("ERR unknown command '{}'", command[0]))),
+    };
+    result.unwrap_or_else(|e| Reply::Error(format!("ERR {}", e)))
+}
+
+fn get(database: &mut Database, partition_key: &str, sort_key: &str) -> io::Result<Option<String>> {
+    match database.get(partition_key.to_string(), sort_key.to_string()) {
+        Ok(data) => Ok(data.map(|data| data.value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn partition_exists(database: &Database, partition_key: &str) -> io::Result<bool> {
+    database.iter_partition(partition_key)?.next().transpose().map(|data| data.is_some())
+}
+
+fn delete_partitions(database: &mut Database, keys: &[String]) -> io::Result<Reply> {
+    let mut deleted = 0;
+    for key in keys {
+        let items = database.query(key, None, None)?;
+        if !items.is_empty() {
+            deleted += 1;
+        }
+        for data in items {
+            database.delete(data.partition_key, data.sort_key)?;
+        }
+    }
+    Ok(Reply::Integer(deleted))
+}
+
+fn hset(database: &mut Database, key: &str, pairs: &[String]) -> io::Result<i64> {
+    let mut added = 0;
+    for pair in pairs.chunks(2) {
+        if get(database, key, &pair[0])?.is_none() {
+            added += 1;
+        }
+        database.insert(key.to_string(), pair[0].clone(), pair[1].clone())?;
+    }
+    Ok(added)
+}
+
+fn hdel(database: &mut Database, key: &str, fields: &[String]) -> io::Result<i64> {
+    let mut deleted = 0;
+    for field in fields {
+        if get(database, key, field)?.is_some() {
+            database.delete(key.to_string(), field.clone())?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// `SCAN` over partition keys. The cursor is an offset into the sorted list of
+/// partitions, so keys added or removed between calls may be missed or repeated,
+/// as Redis also allows.
+fn scan(database: &Database, cursor: &str, options: &[String]) -> io::Result<Reply> {
+    let cursor: usize = match cursor.parse() {
+        Ok(cursor) => cursor,
+        Err(_) => return Ok(Reply::Error("ERR invalid cursor".to_string())),
+    };
+    let mut pattern = None;
+    let mut count = 10;
+    for option in options.chunks(2) {
+        match (option[0].to_ascii_uppercase().as_str(), option.get(1)) {
+            ("MATCH", Some(value)) => pattern = Some(value.as_str()),
+            ("COUNT", Some(value)) => match value.parse::<usize>() {
+                Ok(value) if value > 0 => count = value,
+                _ => return Ok(Reply::Error("ERR value is not an integer or out of range".to_string())),
+            },
+            _ => return Ok(Reply::Error("ERR syntax error".to_string())),
+        }
+    }
+
+    let mut partitions = database.list_partitions()?;
+    partitions.sort();
+    let end = (cursor + count).min(partitions.len());
+    let next_cursor = if end >= partitions.len() { 0 } else { end };
+    let keys = partitions
+        .get(cursor..end)
+        .unwrap_or_default()
+        .iter()
+        .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern.as_bytes(), key.as_bytes())))
+        .map(|key| Reply::Bulk(Some(key.clone())))
+        .collect();
+    Ok(Reply::Array(vec![Reply::Bulk(Some(next_cursor.to_string())), Reply::Array(keys)]))
+}
+
+/// Redis-style glob matching with `*`, `?` and `\` escapes.
+///
+/// Only the last `*` is ever backtracked to, as letting an earlier one match more
+/// can't help once a later one has matched, so patterns sent by clients take
+/// linear time per `*` rather than exponential time.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where matching resumes after the last `*`, and how much text it has taken.
+    let mut star = None;
+    while t < text.len() {
+        let matched = match pattern.get(p..) {
+            Some([b'*', ..]) => {
+                p += 1;
+                star = Some((p, t));
+                continue;
+            },
+            Some([b'?', ..]) => 1,
+            Some([b'\\', escaped, ..]) if *escaped == text[t] => 2,
+            Some([b'\\', _, ..]) => 0,
+            Some([byte, ..]) if *byte == text[t] => 1,
+            _ => 0,
+        };
+        if matched > 0 {
+            p += matched;
+            t += 1;
+            continue;
+        }
+        match star {
+            Some((after_star, taken)) => {
+                star = Some((after_star, taken + 1));
+                p = after_star;
+                t = taken + 1;
+            },
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+fn protocol_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
@@ -0,0 +1,196 @@
+//! REST resources over HTTP/JSON.
+//!
+//! ```text
+//! GET    /{partition}/{sort}          fetch an item
+//! PUT    /{partition}/{sort}          store the request body as the item's value
+//! DELETE /{partition}/{sort}          delete an item
+//! GET    /{partition}                 items of a partition, ordered by sort key;
+//!                                     accepts ?begins_with=, ?from=, ?to= and ?limit=
+//! POST   /_batch                      body: JSON array of `Data`
+//! POST   /_query                      body: {"partition_key": .., "filter": .., "limit": ..}
+//!
+//! GET    /_tables                     list tables
+//! PUT    /_tables/{table}             create a table; optional body: a `KeySchema`,
+//!                                     with the fields of `TableSettings` if any
+//! GET    /_tables/{table}             describe a table
+//! DELETE /_tables/{table}             drop a table
+//! GET    /_tables/{table}/_indexes/{index}/{value}
+//!                                     items whose indexed attribute holds the value,
+//!                                     ordered by key; accepts ?limit=
+//! ...    /_tables/{table}/...         any item route above, scoped to the table
+//! ```
+//!
+//! Items are returned as the serde JSON of `Data`, errors as `{"error": ".."}`.
+
+use super::http::{serve_http, status_for, HttpRequest, HttpResponse};
+use super::protocol::{error_kind, execute, Request, Response};
+use super::tables::TableHandles;
+use super::tcp::{accept_loop, ShutdownHandle};
+use crate::db::{Attribute, Data, Database, Filter, KeySchema, OperationType, TableSettings};
+use serde::Deserialize;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+pub struct RestServer {
+    listener: TcpListener,
+    database: Arc<Mutex<Database>>,
+    tables: Arc<TableHandles>,
+    shutdown: ShutdownHandle,
+}
+
+#[derive(Deserialize, Default)]
+struct CreateTableBody {
+    #[serde(flatten)]
+    key_schema: KeySchema,
+    #[serde(flatten)]
+    settings: TableSettings,
+}
+
+#[derive(Deserialize)]
+struct QueryBody {
+    partition_key: String,
+    #[serde(default)]
+    filter: Option<Filter>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+impl RestServer {
+    /// Binds a server for `database`, opening its tables through `tables`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, database: Arc<Mutex<Database>>, tables: Arc<TableHandles>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle::new(listener.local_addr()?);
+        Ok(RestServer { listener, database, tables, shutdown })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    pub fn run(self) -> io::Result<()> {
+        let (database, tables) = (self.database, self.tables);
+        accept_loop(self.listener, &self.shutdown, move |stream| {
+            serve_http(stream, |request| route(&database, &tables, request))
+        })
+    }
+}
+
+fn route(database: &Mutex<Database>, tables: &TableHandles, request: &HttpRequest) -> HttpResponse {
+    let segments = match request.segments() {
+        Ok(segments) => segments,
+        Err(e) => return HttpResponse::from_io_error(&e),
+    };
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let result = match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["_tables"]) => database.lock().unwrap().list_tables().map(|tables| HttpResponse::json(200, &tables)),
+        ("PUT", ["_tables", table]) => parse_body_or_default::<CreateTableBody>(request)
+            .and_then(|body| database.lock().unwrap().create_table_with_settings(table, body.key_schema, body.settings))
+            .map(|description| HttpResponse::json(201, &description)),
+        ("GET", ["_tables", table]) => database.lock().unwrap().describe_table(table)
+            .map(|description| HttpResponse::json(200, &description)),
+        ("DELETE", ["_tables", table]) => tables.drop_table(database, table)
+            .map(|_| HttpResponse::empty(204)),
+        ("GET", ["_tables", table, "_indexes", index, value]) => limit_param(request)
+            .and_then(|limit| tables.get(database, table)?.lock().unwrap().query_index(index, value, limit))
+            .map(|items| HttpResponse::json(200, &items)),
+        (_, ["_tables", table, rest @ ..]) => {
+            tables.get(database, table).map(|table| items(&mut table.lock().unwrap(), request, rest))
+        },
+        (_, rest) => Ok(items(&mut database.lock().unwrap(), request, rest)),
+    };
+    result.unwrap_or_else(|e| HttpResponse::from_io_error(&e))
+}
+
+fn items(database: &mut Database, request: &HttpRequest, segments: &[&str]) -> HttpResponse {
+    let method = request.method.as_str();
+    let (request, stored) = match (method, segments) {
+        ("POST", ["_batch"]) => match parse_body::<Vec<Data>>(request) {
+            Ok(items) => (Request::Batch { items }, None),
+            Err(e) => return HttpResponse::from_io_error(&e),
+        },
+        ("POST", ["_query"]) => match parse_body::<QueryBody>(request) {
+            Ok(QueryBody { partition_key, filter, limit }) => (Request::Query { partition_key, filter, limit }, None),
+            Err(e) => return HttpResponse::from_io_error(&e),
+        },
+        ("GET", [partition_key]) => match query_from_params(partition_key, request) {
+            Ok(query) => (query, None),
+            Err(e) => return HttpResponse::from_io_error(&e),
+        },
+        ("GET", [partition_key, sort_key]) => {
+            (Request::Get { partition_key: partition_key.to_string(), sort_key: sort_key.to_string() }, None)
+        },
+        ("PUT", [partition_key, sort_key]) => {
+            let data = Data {
+                operation_type: OperationType::Insert,
+                partition_key: partition_key.to_string(),
+                sort_key: sort_key.to_string(),
+                value: String::from_utf8_lossy(&request.body).into_owned(),
+            };
+            let insert = Request::Insert {
+                partition_key: data.partition_key.clone(),
+                sort_key: data.sort_key.clone(),
+                value: data.value.clone(),
+            };
+            (insert, Some(data))
+        },
+        ("DELETE", [partition_key, sort_key]) => {
+            (Request::Delete { partition_key: partition_key.to_string(), sort_key: sort_key.to_string() }, None)
+        },
+        (_, [_] | [_, _]) => return HttpResponse::error(405, "method not allowed"),
+        _ => return HttpResponse::error(404, "no such resource"),
+    };
+
+    match execute(database, request) {
+        Response::Ok => match stored {
+            Some(data) => HttpResponse::json(200, &data),
+            None => HttpResponse::empty(204),
+        },
+        Response::Item { data: Some(data) } => HttpResponse::json(200, &data),
+        Response::Item { data: None } => HttpResponse::error(404, "item not found"),
+        Response::Items { items } => HttpResponse::json(200, &items),
+        Response::Error { kind, message } => HttpResponse::error(status_for(error_kind(&kind)), &message),
+    }
+}
+
+fn query_from_params(partition_key: &str, request: &HttpRequest) -> io::Result<Request> {
+    let mut conditions = Vec::new();
+    if let Some(prefix) = request.query_param("begins_with") {
+        conditions.push(Filter::BeginsWith(Attribute::SortKey, prefix.to_string()));
+    }
+    if let Some(from) = request.query_param("from") {
+        conditions.push(Filter::Ge(Attribute::SortKey, from.to_string()));
+    }
+    if let Some(to) = request.query_param("to") {
+        conditions.push(Filter::Le(Attribute::SortKey, to.to_string()));
+    }
+    Ok(Request::Query {
+        partition_key: partition_key.to_string(),
+        filter: conditions.into_iter().reduce(Filter::and),
+        limit: limit_param(request)?,
+    })
+}
+
+fn limit_param(request: &HttpRequest) -> io::Result<Option<usize>> {
+    match request.query_param("limit") {
+        Some(limit) => Ok(Some(limit.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid limit"))?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_body<T: for<'de> Deserialize<'de>>(request: &HttpRequest) -> io::Result<T> {
+    serde_json::from_slice(&request.body).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+fn parse_body_or_default<T: for<'de> Deserialize<'de> + Default>(request: &HttpRequest) -> io::Result<T> {
+    if request.body.iter().all(u8::is_ascii_whitespace) {
+        Ok(T::default())
+    } else {
+        parse_body(request)
+    }
+}
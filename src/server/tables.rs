@@ -0,0 +1,48 @@
+use crate::db::Database;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// The open tables of a hosted `Database`, shared by every front-end serving it.
+///
+/// Each table is opened once, on first use, and kept open until it is dropped
+/// through `drop_table`. Operations on a table lock its handle, so they are
+/// serialized like those on the root keyspace, and the journal of a table is only
+/// replayed when it is first opened, never while another handle writes to it.
+///
+/// Create one per hosted database and pass it to each server that serves its
+/// tables; servers given different handles would each open the tables anew.
+#[derive(Debug, Default)]
+pub struct TableHandles {
+    open: Mutex<HashMap<String, Arc<Mutex<Database>>>>,
+}
+
+impl TableHandles {
+    pub fn new() -> Arc<Self> {
+        Arc::new(TableHandles::default())
+    }
+
+    /// The handle of a table, opened if it isn't yet.
+    pub(crate) fn get(&self, database: &Mutex<Database>, name: &str) -> io::Result<Arc<Mutex<Database>>> {
+        let mut open = self.open.lock().unwrap();
+        if let Some(table) = open.get(name) {
+            return Ok(Arc::clone(table));
+        }
+        let table = Arc::new(Mutex::new(database.lock().unwrap().table(name)?));
+        open.insert(name.to_string(), Arc::clone(&table));
+        Ok(table)
+    }
+
+    /// Closes the handle of a table, so the next `get` opens it again with the
+    /// settings it has then. Operations already holding the handle keep it.
+    pub(crate) fn close(&self, name: &str) {
+        self.open.lock().unwrap().remove(name);
+    }
+
+    /// Closes the handle of a table, then drops the table.
+    pub(crate) fn drop_table(&self, database: &Mutex<Database>, name: &str) -> io::Result<()> {
+        let mut open = self.open.lock().unwrap();
+        open.remove(name);
+        database.lock().unwrap().drop_table(name)
+    }
+}
@@ -0,0 +1,196 @@
+use super::protocol::{execute, Request, Response};
+use crate::db::Database;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// The longest request line read, newline included.
+const MAX_REQUEST_LEN: usize = 64 * 1024 * 1024;
+
+/// Serves a `Database` over TCP using the line-based protocol described in
+/// `server::protocol`. Each connection is handled on its own thread.
+pub struct Server {
+    listener: TcpListener,
+    database: Arc<Mutex<Database>>,
+    shutdown: ShutdownHandle,
+}
+
+/// Stops a running `Server`. Requests already received are answered before
+/// their connection is closed.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownState>,
+}
+
+#[derive(Debug)]
+struct ShutdownState {
+    requested: AtomicBool,
+    addr: SocketAddr,
+    next_connection: AtomicU64,
+    connections: Mutex<HashMap<u64, TcpStream>>,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A, database: Arc<Mutex<Database>>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = ShutdownHandle::new(listener.local_addr()?);
+        Ok(Server { listener, database, shutdown })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Accepts connections until `ShutdownHandle::shutdown` is called, then waits
+    /// for every open connection to finish its in-flight requests.
+    pub fn run(self) -> io::Result<()> {
+        let database = self.database;
+        accept_loop(self.listener, &self.shutdown, move |stream| serve_connection(stream, &database))
+    }
+}
+
+/// Runs `handler` on its own thread for every accepted connection until shutdown
+/// is requested, then closes the connections and waits for the handlers to return.
+pub(crate) fn accept_loop<F>(listener: TcpListener, shutdown: &ShutdownHandle, handler: F) -> io::Result<()>
+where
+    F: Fn(TcpStream) -> io::Result<()> + Send + Sync + 'static,
+{
+    let handler = Arc::new(handler);
+    let mut workers = Vec::new();
+    for stream in listener.incoming() {
+        if shutdown.is_requested() {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let id = shutdown.register(&stream)?;
+        let handler = Arc::clone(&handler);
+        let shutdown = shutdown.clone();
+        workers.push(thread::spawn(move || {
+            // A client hanging up mid-request is not a server error.
+            let _ = handler(stream);
+            shutdown.unregister(id);
+        }));
+        workers.retain(|worker| !worker.is_finished());
+    }
+
+    shutdown.close_connections();
+    for worker in workers {
+        let _ = worker.join();
+    }
+    Ok(())
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(addr: SocketAddr) -> Self {
+        ShutdownHandle {
+            inner: Arc::new(ShutdownState {
+                requested: AtomicBool::new(false),
+                addr,
+                next_connection: AtomicU64::new(0),
+                connections: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        if !self.inner.requested.swap(true, Ordering::SeqCst) {
+            // Wake the accept loop so it notices the request.
+            let _ = TcpStream::connect(self.inner.addr);
+        }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.inner.requested.load(Ordering::SeqCst)
+    }
+
+    fn register(&self, stream: &TcpStream) -> io::Result<u64> {
+        let id = self.inner.next_connection.fetch_add(1, Ordering::SeqCst);
+        self.inner.connections.lock().unwrap().insert(id, stream.try_clone()?);
+        Ok(id)
+    }
+
+    fn unregister(&self, id: u64) {
+        self.inner.connections.lock().unwrap().remove(&id);
+    }
+
+    fn close_connections(&self) {
+        // Closing only the read side lets each connection answer what it has
+        // already read before it sees end of input.
+        for stream in self.inner.connections.lock().unwrap().values() {
+            let _ = stream.shutdown(Shutdown::Read);
+        }
+    }
+}
+
+fn serve_connection(stream: TcpStream, database: &Mutex<Database>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let response = match read_line_limited(&mut reader, &mut line, MAX_REQUEST_LEN) {
+            Ok(0) => break,
+            Ok(_) if line.trim_ascii().is_empty() => continue,
+            Ok(_) => match serde_json::from_slice::<Request>(&line) {
+                Ok(request) => execute(&mut database.lock().unwrap(), request),
+                Err(e) => Response::error(&io::Error::new(io::ErrorKind::InvalidInput, e)),
+            },
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                skip_line(&mut reader)?;
+                Response::error(&e)
+            },
+            Err(e) => return Err(e),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+
+        // Batch the responses of pipelined requests into as few writes as possible.
+        if reader.buffer().is_empty() {
+            writer.flush()?;
+        }
+    }
+    writer.flush()
+}
+
+/// Reads a line into `line` like `BufRead::read_until`, but reads at most `limit`
+/// bytes, failing with `InvalidData` if they hold no newline. Returns 0 at the
+/// end of input.
+pub(crate) fn read_line_limited<R: BufRead>(reader: &mut R, line: &mut Vec<u8>, limit: usize) -> io::Result<usize> {
+    let read = reader.take(limit as u64).read_until(b'\n', line)?;
+    if read == limit && !line.ends_with(b"\n") {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("line longer than {} bytes", limit)));
+    }
+    Ok(read)
+}
+
+/// Discards the input up to and including the next newline, without buffering it.
+fn skip_line<R: BufRead>(reader: &mut R) -> io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            },
+            None => {
+                let length = buffer.len();
+                reader.consume(length);
+            },
+        }
+    }
+}
@@ -1,5 +1,7 @@
 mod logger;
 mod config;
+mod table;
 
 pub use self::logger::Logger;
 pub use self::config::Config;
+pub use self::table::format_table;
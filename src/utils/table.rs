@@ -0,0 +1,22 @@
+/// Lays out rows as columns padded to the widest cell. The last cell of a row is
+/// not padded, so there is no trailing whitespace. Each row ends with a newline.
+pub fn format_table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.chars().count()).max().unwrap_or(0))
+        .collect();
+
+    let mut output = String::new();
+    for row in rows {
+        let last = row.len().saturating_sub(1);
+        for (column, cell) in row.iter().enumerate() {
+            if column == last {
+                output.push_str(cell);
+            } else {
+                output.push_str(&format!("{:width$}  ", cell, width = widths[column]));
+            }
+        }
+        output.push('\n');
+    }
+    output
+}
@@ -0,0 +1,305 @@
+use data_ferret::db::{segment_of, Attribute, Compression, CorruptionError, Data, Database, Filter, ItemRef, LocalObjectStore, ObjectStore, ObjectStoreBackend, OperationType, Persistence, StorageBackend, TieredBackend, TieringPolicy};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::ops::{Bound, RangeBounds};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An in-memory backend that fails every operation once `budget` operations have
+/// succeeded, and records how many reads reach it.
+#[derive(Debug, Default, Clone)]
+struct FaultyBackend {
+    items: Arc<Mutex<BTreeMap<(String, String), Data>>>,
+    budget: Arc<AtomicUsize>,
+    reads: Arc<AtomicUsize>,
+}
+
+impl FaultyBackend {
+    fn new() -> Self {
+        FaultyBackend { budget: Arc::new(AtomicUsize::new(usize::MAX)), ..Default::default() }
+    }
+
+    fn fail_after(&self, operations: usize) {
+        self.budget.store(operations, Ordering::SeqCst);
+    }
+
+    fn operation(&self) -> io::Result<()> {
+        match self.budget.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |budget| budget.checked_sub(1)) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(io::Error::other("injected fault")),
+        }
+    }
+
+    fn collect(&self, keep: impl Fn(&Data) -> bool) -> io::Result<std::vec::IntoIter<io::Result<Data>>> {
+        self.operation()?;
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        let items: Vec<_> = self.items.lock().unwrap().values().filter(|data| keep(data)).cloned().map(Ok).collect();
+        Ok(items.into_iter())
+    }
+}
+
+impl StorageBackend for FaultyBackend {
+    type Iter = std::vec::IntoIter<io::Result<Data>>;
+
+    fn get(&self, partition_key: &str, sort_key: &str) -> io::Result<Option<Data>> {
+        self.operation()?;
+        self.reads.fetch_add(1, Ordering::SeqCst);
+        Ok(self.items.lock().unwrap().get(&(partition_key.to_string(), sort_key.to_string())).cloned())
+    }
+
+    fn put(&self, data: &Data) -> io::Result<()> {
+        self.operation()?;
+        let key = (data.partition_key.clone(), data.sort_key.clone());
+        self.items.lock().unwrap().insert(key, data.clone());
+        Ok(())
+    }
+
+    fn delete(&self, partition_key: &str, sort_key: &str) -> io::Result<()> {
+        self.operation()?;
+        match self.items.lock().unwrap().remove(&(partition_key.to_string(), sort_key.to_string())) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "item not found")),
+        }
+    }
+
+    fn range(&self, partition_key: &str, from: Bound<&str>, to: Bound<&str>) -> io::Result<Self::Iter> {
+        self.collect(|data| data.partition_key == partition_key && (from, to).contains(data.sort_key.as_str()))
+    }
+
+    fn scan(&self, segment: usize, total_segments: usize) -> io::Result<Self::Iter> {
+        self.collect(|data| segment_of(&data.partition_key, total_segments) == segment)
+    }
+
+    fn list_partitions(&self) -> io::Result<Vec<String>> {
+        self.operation()?;
+        let mut partitions: Vec<String> = self.items.lock().unwrap().keys().map(|(partition_key, _)| partition_key.clone()).collect();
+        partitions.dedup();
+        Ok(partitions)
+    }
+
+    /// All or nothing: the whole batch is checked against the budget first.
+    fn write_batch(&self, batch: &[Data]) -> io::Result<()> {
+        self.operation()?;
+        let mut items = self.items.lock().unwrap();
+        for data in batch {
+            let key = (data.partition_key.clone(), data.sort_key.clone());
+            match data.operation_type {
+                OperationType::Delete => items.remove(&key),
+                _ => items.insert(key, data.clone()),
+            };
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.operation()
+    }
+}
+
+fn item(partition_key: &str, sort_key: &str, value: &str) -> Data {
+    Data {
+        operation_type: OperationType::Insert,
+        partition_key: partition_key.to_string(),
+        sort_key: sort_key.to_string(),
+        value: value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_backend() {
+        let backend = FaultyBackend::new();
+        let mut database = Database::with_backend(backend.clone());
+
+        database.insert("p".to_string(), "b".to_string(), "1".to_string()).unwrap();
+        database.batch(vec![item("p", "a", "2"), item("p", "c", "3"), item("q", "a", "4")]).unwrap();
+        database.flush().unwrap();
+
+        // Written items are served from the cache.
+        assert_eq!("1", database.get("p".to_string(), "b".to_string()).unwrap().unwrap().value);
+        assert_eq!(0, backend.reads.load(Ordering::SeqCst));
+        assert_eq!(io::ErrorKind::NotFound, database.get("p".to_string(), "z".to_string()).unwrap_err().kind());
+
+        let sort_keys: Vec<String> = database.query("p", None, None).unwrap().into_iter().map(|data| data.sort_key).collect();
+        assert_eq!(vec!["a", "b", "c"], sort_keys);
+        let filter = Filter::Ge(Attribute::SortKey, "b".to_string());
+        assert_eq!(2, database.query("p", Some(&filter), None).unwrap().len());
+        assert_eq!(4, database.parallel_scan(None, 3).unwrap().len());
+        assert_eq!(vec!["p", "q"], database.list_partitions().unwrap());
+
+        let result = database.batch_get(vec![("q".to_string(), "a".to_string()), ("q".to_string(), "z".to_string())]).unwrap();
+        assert_eq!((1, 1), (result.found.len(), result.missing.len()));
+
+        database.delete("q".to_string(), "a".to_string()).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, database.delete("q".to_string(), "a".to_string()).unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_backend_faults() {
+        let backend = FaultyBackend::new();
+        let mut database = Database::with_backend(backend.clone());
+        database.insert("p".to_string(), "a".to_string(), "old".to_string()).unwrap();
+
+        // A failed write leaves neither the backend nor the cache changed.
+        backend.fail_after(0);
+        assert!(database.insert("p".to_string(), "a".to_string(), "new".to_string()).is_err());
+        assert!(database.delete("p".to_string(), "a".to_string()).is_err());
+        assert!(database.batch(vec![item("p", "a", "new"), item("p", "b", "new")]).is_err());
+        assert!(database.flush().is_err());
+        assert_eq!("old", database.get("p".to_string(), "a".to_string()).unwrap().unwrap().value);
+        assert!(database.get("p".to_string(), "b".to_string()).is_err());
+
+        // Read failures are reported, and a later read succeeds once the fault clears.
+        assert!(database.scan(None).is_err());
+        assert!(database.query("p", None, None).is_err());
+        backend.fail_after(usize::MAX);
+        assert_eq!(vec![item("p", "a", "old")], database.scan(None).unwrap());
+
+        // A fault part way through a sequence of writes stops at the failed one.
+        backend.fail_after(1);
+        database.insert("p".to_string(), "c".to_string(), "1".to_string()).unwrap();
+        assert!(database.insert("p".to_string(), "d".to_string(), "2".to_string()).is_err());
+        backend.fail_after(usize::MAX);
+        let mut fresh = Database::with_backend(backend.clone());
+        fresh.load_all_data().unwrap();
+        assert!(fresh.get("p".to_string(), "c".to_string()).is_ok());
+        assert!(fresh.get("p".to_string(), "d".to_string()).is_err());
+    }
+
+    fn setup(path: &str) -> PathBuf {
+        let path = PathBuf::from(path);
+        let _ = fs::remove_dir_all(&path);
+        path
+    }
+
+    #[test]
+    fn test_object_store_backend() {
+        let path = setup("./test_backend_store1");
+        let store = LocalObjectStore::new(path.clone()).unwrap();
+        let mut database = Database::with_backend(ObjectStoreBackend::new(store.clone()).with_compression(Compression::Zstd));
+
+        database.insert("p".to_string(), "a".to_string(), "x".repeat(100)).unwrap();
+        database.batch(vec![item("p", "b", "2"), item("q", "a", "3")]).unwrap();
+        database.delete("p".to_string(), "b".to_string()).unwrap();
+
+        // Each partition is one object.
+        let mut keys = store.list("").unwrap();
+        keys.sort();
+        assert_eq!(vec!["p.seg", "q.seg"], keys);
+
+        let mut reopened = Database::with_backend(ObjectStoreBackend::new(store.clone()));
+        assert_eq!("x".repeat(100), reopened.get("p".to_string(), "a".to_string()).unwrap().unwrap().value);
+        assert!(reopened.get("p".to_string(), "b".to_string()).is_err());
+        assert_eq!(2, reopened.scan(None).unwrap().len());
+
+        // Deleting the last item of a partition removes its object.
+        reopened.delete("q".to_string(), "a".to_string()).unwrap();
+        assert_eq!(None, store.get("q.seg").unwrap());
+
+        // Every record in a segment is checksummed.
+        let mut bytes = store.get("p.seg").unwrap().unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        store.put("p.seg", &bytes).unwrap();
+        let error = Database::with_backend(ObjectStoreBackend::new(store)).get("p".to_string(), "a".to_string()).unwrap_err();
+        assert_eq!("checksum mismatch", CorruptionError::of(&error).unwrap().reason);
+
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn test_tiering() {
+        let hot_path = setup("./test_backend_db1");
+        let cold_path = setup("./test_backend_store2");
+        let store = LocalObjectStore::new(cold_path.clone()).unwrap();
+        let mut database = Database::with_backend(TieredBackend::new(Persistence::new(hot_path.clone()), store.clone()));
+        database.batch(vec![item("p", "a", "1"), item("p", "b", "2"), item("q", "a", "3")]).unwrap();
+
+        // Nothing is old enough to move.
+        assert!(database.backend().tier(&TieringPolicy::days(1)).unwrap().is_empty());
+        assert!(hot_path.join("p").is_dir());
+
+        let mut moved = database.backend().tier(&TieringPolicy { max_idle: Duration::ZERO }).unwrap();
+        moved.sort();
+        assert_eq!(vec!["p", "q"], moved);
+        assert!(!hot_path.join("p").exists());
+        assert!(store.get("p.seg").unwrap().is_some());
+
+        // Cold partitions are still read, queried and scanned.
+        let mut reopened = Database::with_backend(TieredBackend::new(Persistence::new(hot_path.clone()), store.clone()));
+        assert_eq!("2", reopened.get("p".to_string(), "b".to_string()).unwrap().unwrap().value);
+        let filter = Filter::Ge(Attribute::SortKey, "b".to_string());
+        assert_eq!(1, reopened.query("p", Some(&filter), None).unwrap().len());
+        assert_eq!(3, reopened.parallel_scan(None, 2).unwrap().len());
+        assert_eq!(vec!["p", "q"], reopened.list_partitions().unwrap());
+
+        // Writing to a cold partition moves it back first.
+        reopened.insert("p".to_string(), "c".to_string(), "4".to_string()).unwrap();
+        assert!(hot_path.join("p").is_dir());
+        assert_eq!(None, store.get("p.seg").unwrap());
+        let sort_keys: Vec<String> = reopened.query("p", None, None).unwrap().into_iter().map(|data| data.sort_key).collect();
+        assert_eq!(vec!["a", "b", "c"], sort_keys);
+        assert!(!reopened.backend().recall("p").unwrap());
+        assert!(reopened.backend().recall("q").unwrap());
+        assert_eq!(3, reopened.backend().hot().load_partition("p").unwrap().len());
+
+        fs::remove_dir_all(hot_path).unwrap();
+        fs::remove_dir_all(cold_path).unwrap();
+    }
+
+    #[test]
+    fn test_mapped_segment() {
+        let path = setup("./test_backend_store3");
+        let store = LocalObjectStore::new(path.clone()).unwrap();
+        let backend = ObjectStoreBackend::new(store.clone());
+        backend.write_batch(&[item("p", "a", "plain"), item("p", "b", "needs \"escaping\""), item("p", "c", "3")]).unwrap();
+
+        let segment = backend.map_partition("p").unwrap().unwrap();
+        assert_eq!(3, segment.len());
+        assert!(backend.map_partition("missing").unwrap().is_none());
+
+        // Plain values are read in place; escaped ones have to be unescaped.
+        let plain = segment.get("a").unwrap().unwrap();
+        assert!(matches!(plain.value, Cow::Borrowed("plain")));
+        let escaped = segment.get("b").unwrap().unwrap();
+        assert!(matches!(escaped.value, Cow::Owned(_)));
+        assert_eq!("needs \"escaping\"", escaped.value);
+        assert_eq!(None, segment.get("z").unwrap());
+
+        let sort_keys: Vec<String> = segment
+            .range(Bound::Excluded("a"), Bound::Unbounded)
+            .map(|item| item.unwrap().sort_key.into_owned())
+            .collect();
+        assert_eq!(vec!["b", "c"], sort_keys);
+        assert_eq!(item("p", "c", "3"), segment.iter().last().unwrap().unwrap().into_data());
+
+        // A write replaces the mapping; the old one stays readable.
+        backend.put(&item("p", "a", "new")).unwrap();
+        assert_eq!("new", backend.get("p", "a").unwrap().unwrap().value);
+        assert_eq!("plain", segment.get("a").unwrap().unwrap().value);
+
+        // Compressed records are decoded rather than borrowed.
+        let compressed = ObjectStoreBackend::new(store.clone()).with_compression(Compression::Zstd);
+        compressed.put(&item("q", "a", &"x".repeat(100))).unwrap();
+        let segment = compressed.map_partition("q").unwrap().unwrap();
+        let ItemRef { value, .. } = segment.get("a").unwrap().unwrap();
+        assert!(matches!(value, Cow::Owned(_)));
+        assert_eq!("x".repeat(100), value);
+
+        // Records are checked when the segment is mapped.
+        let mut bytes = store.get("q.seg").unwrap().unwrap();
+        bytes[10] ^= 0xff;
+        store.put("q.seg", &bytes).unwrap();
+        let error = ObjectStoreBackend::new(store).map_partition("q").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+
+        fs::remove_dir_all(path).unwrap();
+    }
+}
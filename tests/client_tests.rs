@@ -0,0 +1,134 @@
+use data_ferret::client::{AsyncClient, Client, ClientConfig};
+use data_ferret::db::{Database, Data, OperationType, Attribute, Filter};
+use data_ferret::server::{Request, Response, Server, ShutdownHandle};
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::io;
+
+#[cfg(test)]
+mod tests {
+
+    use std::future::Future;
+    use std::net::SocketAddr;
+    use std::pin::pin;
+    use std::sync::{Arc, Mutex};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, JoinHandle, Thread};
+
+    use super::*;
+
+    fn setup(database_path: &str) -> PathBuf {
+        let path = PathBuf::from(database_path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn teardown(path: PathBuf) {
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    fn start_server(addr: &str, path: &Path) -> (SocketAddr, ShutdownHandle, JoinHandle<()>) {
+        let database = Arc::new(Mutex::new(Database::new(path.to_path_buf())));
+        let server = Server::bind(addr, database).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        (addr, shutdown, thread::spawn(move || server.run().unwrap()))
+    }
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = pin!(future);
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_client() {
+        let path = setup("./test_client_db1");
+        let (addr, shutdown, handle) = start_server("127.0.0.1:0", &path);
+        let client = Client::connect(&addr.to_string()).unwrap();
+
+        client.insert("p".to_string(), "a".to_string(), "1".to_string()).unwrap();
+        client
+            .batch(vec![
+                Data { operation_type: OperationType::Insert, partition_key: "p".to_string(), sort_key: "b".to_string(), value: "2".to_string() },
+                Data { operation_type: OperationType::Insert, partition_key: "p".to_string(), sort_key: "c".to_string(), value: "3".to_string() },
+            ])
+            .unwrap();
+        assert_eq!(client.get("p".to_string(), "a".to_string()).unwrap().unwrap().value, "1");
+        assert!(client.get("p".to_string(), "missing".to_string()).unwrap().is_none());
+
+        let filter = Filter::Ge(Attribute::SortKey, "b".to_string());
+        let items = client.query("p", Some(&filter), Some(1)).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].sort_key, "b");
+
+        client.delete("p".to_string(), "a".to_string()).unwrap();
+        let error = client.delete("p".to_string(), "a".to_string()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        let responses = client
+            .pipeline(vec![
+                Request::Get { partition_key: "p".to_string(), sort_key: "c".to_string() },
+                Request::Delete { partition_key: "p".to_string(), sort_key: "missing".to_string() },
+            ])
+            .unwrap();
+        assert!(matches!(&responses[0], Response::Item { data: Some(data) } if data.value == "3"));
+        assert!(matches!(&responses[1], Response::Error { .. }));
+
+        // The pooled connection dies with the server; the next call reconnects.
+        shutdown.shutdown();
+        handle.join().unwrap();
+        let (_, shutdown, handle) = start_server(&addr.to_string(), &path);
+        assert_eq!(client.get("p".to_string(), "b".to_string()).unwrap().unwrap().value, "2");
+
+        shutdown.shutdown();
+        handle.join().unwrap();
+        let mut config = ClientConfig::new(&addr.to_string());
+        config.max_retries = 0;
+        assert!(Client::with_config(config).is_err());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_async_client() {
+        let path = setup("./test_client_db2");
+        let (addr, shutdown, handle) = start_server("127.0.0.1:0", &path);
+        let mut config = ClientConfig::new(&addr.to_string());
+        config.pool_size = 4;
+        let client = AsyncClient::with_config(config).unwrap();
+
+        let inserts: Vec<_> = (0..20)
+            .map(|i| client.insert("p".to_string(), format!("{:02}", i), i.to_string()))
+            .collect();
+        for insert in inserts {
+            block_on(insert).unwrap();
+        }
+
+        let data = block_on(client.get("p".to_string(), "07".to_string())).unwrap().unwrap();
+        assert_eq!(data.value, "7");
+        assert_eq!(block_on(client.query("p", None, None)).unwrap().len(), 20);
+        block_on(client.delete("p".to_string(), "07".to_string())).unwrap();
+        assert!(block_on(client.get("p".to_string(), "07".to_string())).unwrap().is_none());
+        assert_eq!(client.blocking().query("p", None, None).unwrap().len(), 19);
+
+        drop(client);
+        shutdown.shutdown();
+        handle.join().unwrap();
+        teardown(path);
+    }
+}
@@ -1,11 +1,12 @@
-use data_ferret::db::{Database, Data, OperationType};
+use data_ferret::db::{Database, Data, Persistence, OperationType, Attribute, Filter, GroupCommitConfig, KeySchema, TableSettings, TimeToLive, IndexSchema, import, FieldMapping, ImportFormat, ImportOptions, LineError, export, ExportFormat, Selection, write_dump, write_dump_in_memory, restore, restore_in_memory, DumpEntry, DumpReader, DUMP_VERSION, migrate, MigrationReport, fsck, FsckMode, CorruptionError, Compression, EncryptionKey, Keyring, LockError, ReadOnlyError};
+use std::io;
 use std::path::PathBuf;
 use std::fs;
 
 #[cfg(test)]
 mod tests {
 
-    use std::{thread, sync::{Arc, Mutex}};
+    use std::{thread, sync::{Arc, Mutex}, time::Duration};
 
     use data_ferret::db::InMemoryDatabase;
 
@@ -166,6 +167,14 @@ mod tests {
     
         let result2 = database.get("partition2".to_string(), "sort2".to_string()).unwrap();
         assert_eq!(Some(Data { operation_type: OperationType::Insert, partition_key: "partition2".to_string(), sort_key: "sort2".to_string(), value: "value2".to_string() }), result2);
+
+        // Deleting a missing item fails like `delete` does, and nothing is written.
+        let delete = |partition_key: &str| Data { operation_type: OperationType::Delete, partition_key: partition_key.to_string(), sort_key: "sort1".to_string(), value: String::new() };
+        let insert = |partition_key: &str| Data { operation_type: OperationType::Insert, value: "value".to_string(), ..delete(partition_key) };
+        assert_eq!(io::ErrorKind::NotFound, database.batch(vec![delete("partition1"), delete("partition3")]).unwrap_err().kind());
+        assert!(database.get("partition1".to_string(), "sort1".to_string()).is_ok());
+        database.batch(vec![insert("partition3"), delete("partition3"), delete("partition1")]).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, database.batch(vec![delete("partition1")]).unwrap_err().kind());
     
         teardown(path);
     }
@@ -304,4 +313,1059 @@ mod tests {
         let result = database.get_all(partition_key.clone());
         println!("{:?}", result);
     }
+
+    #[test]
+    fn test_scan() {
+        let path = setup("./test_db9");
+        let mut database = Database::new(path.clone());
+
+        for i in 0..20 {
+            database.insert(format!("partition{}", i % 4), format!("sort{}", i), format!("value{}", i)).unwrap();
+        }
+
+        let result = database.scan(None).unwrap();
+        assert_eq!(20, result.len());
+
+        let filter = Filter::Between(Attribute::SortKey, "sort10".to_string(), "sort12".to_string())
+            .or(Filter::Eq(Attribute::Value, "value3".to_string()));
+        let mut result: Vec<String> = database.scan(Some(&filter)).unwrap().into_iter().map(|data| data.sort_key).collect();
+        result.sort();
+        assert_eq!(vec!["sort10", "sort11", "sort12", "sort3"], result);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_parallel_scan() {
+        let path = setup("./test_db10");
+        let mut database = Database::new(path.clone());
+
+        for i in 0..30 {
+            database.insert(format!("partition{}", i), format!("sort{}", i), format!("value{}", i)).unwrap();
+        }
+
+        let segmented: usize = (0..3).map(|segment| database.scan_segment(None, segment, 3).unwrap().len()).sum();
+        assert_eq!(30, segmented);
+
+        let filter = Filter::Ge(Attribute::PartitionKey, "partition2".to_string());
+        let mut result = database.parallel_scan(Some(&filter), 3).unwrap();
+        let mut expected = database.scan(Some(&filter)).unwrap();
+        result.sort_by(|a, b| a.partition_key.cmp(&b.partition_key));
+        expected.sort_by(|a, b| a.partition_key.cmp(&b.partition_key));
+        assert_eq!(expected, result);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_iter() {
+        let path = setup("./test_db11");
+        let mut database = Database::new(path.clone());
+
+        for i in 0..10 {
+            database.insert(format!("partition{}", i % 2), format!("sort.{}", i), format!("value{}", i)).unwrap();
+        }
+
+        let items: Vec<Data> = database.iter().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(10, items.len());
+
+        let first_three: Vec<Data> = database.iter().unwrap().take(3).collect::<Result<_, _>>().unwrap();
+        assert_eq!(3, first_three.len());
+
+        let mut partition: Vec<String> = database.iter_partition("partition1").unwrap()
+            .map(|data| data.unwrap().sort_key)
+            .collect();
+        partition.sort();
+        assert_eq!(vec!["sort.1", "sort.3", "sort.5", "sort.7", "sort.9"], partition);
+
+        assert_eq!(0, database.iter_partition("missing").unwrap().count());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_batch_get() {
+        let path = setup("./test_db12");
+        let mut database = Database::new(path.clone());
+
+        database.insert("partition1".to_string(), "sort1".to_string(), "value1".to_string()).unwrap();
+        database.insert("partition1".to_string(), "sort2".to_string(), "value2".to_string()).unwrap();
+        database.insert("partition2".to_string(), "sort1".to_string(), "value3".to_string()).unwrap();
+
+        // Reopen with an empty cache so only partition1/sort1 is cached below.
+        let mut database = Database::new(path.clone());
+        database.get("partition1".to_string(), "sort1".to_string()).unwrap();
+        // Cached items are served without touching the file.
+        fs::remove_file(path.join("partition1").join("sort1")).unwrap();
+
+        let result = database.batch_get(vec![
+            ("partition1".to_string(), "sort1".to_string()),
+            ("partition2".to_string(), "sort1".to_string()),
+            ("partition1".to_string(), "sort3".to_string()),
+            ("partition3".to_string(), "sort1".to_string()),
+            ("partition1".to_string(), "sort2".to_string()),
+            // Repeated keys are answered once.
+            ("partition1".to_string(), "sort2".to_string()),
+            ("partition1".to_string(), "sort3".to_string()),
+        ]).unwrap();
+
+        let mut found: Vec<String> = result.found.into_iter().map(|data| data.value).collect();
+        found.sort();
+        assert_eq!(vec!["value1", "value2", "value3"], found);
+        assert_eq!(vec![
+            ("partition1".to_string(), "sort3".to_string()),
+            ("partition3".to_string(), "sort1".to_string()),
+        ], result.missing);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let path = setup("./test_db13");
+        let config = GroupCommitConfig { max_delay: Duration::from_millis(20), max_batch_size: 8 };
+        let mut database = Database::with_group_commit(path.clone(), config);
+        let group_commit = database.group_commit().unwrap();
+        let mut handles = vec![];
+
+        for i in 0..16 {
+            let group_commit = Arc::clone(&group_commit);
+            let handle = thread::spawn(move || {
+                group_commit.commit(vec![Data {
+                    operation_type: OperationType::Insert,
+                    partition_key: format!("partition{}", i % 4),
+                    sort_key: format!("sort{}", i),
+                    value: format!("value{}", i),
+                }]).unwrap();
+            });
+            handles.push(handle);
+        }
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        database.insert("partition0".to_string(), "extra".to_string(), "value".to_string()).unwrap();
+        database.delete("partition0".to_string(), "sort0".to_string()).unwrap();
+
+        // Every acknowledged write is applied, and stays in the journal until a
+        // checkpoint has synced its file.
+        assert!(fs::metadata(path.join(".journal")).unwrap().len() > 0);
+        database.flush().unwrap();
+        assert_eq!(0, fs::metadata(path.join(".journal")).unwrap().len());
+        let mut reopened = Database::new(path.clone());
+        assert_eq!(16, reopened.scan(None).unwrap().len());
+        let result = reopened.get("partition3".to_string(), "sort7".to_string()).unwrap();
+        assert_eq!(Some(Data { operation_type: OperationType::Insert, partition_key: "partition3".to_string(), sort_key: "sort7".to_string(), value: "value7".to_string() }), result);
+        assert!(!path.join("partition0").join("sort0").exists());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let path = setup("./test_db14");
+
+        // Simulate a crash after the journal was synced but before the files were written,
+        // with a torn record at the end that was never acknowledged.
+        let record = Data { operation_type: OperationType::Insert, partition_key: "partition".to_string(), sort_key: "sort".to_string(), value: "value".to_string() };
+        let journal = format!("{}\n{{\"type\":\"Data\",\"operation", serde_json::to_string(&record).unwrap());
+        fs::write(path.join(".journal"), journal).unwrap();
+
+        let mut database = Database::new(path.clone());
+        assert_eq!(Some(record), database.get("partition".to_string(), "sort".to_string()).unwrap());
+        assert_eq!(1, database.scan(None).unwrap().len());
+
+        // A write bypassing the journal is never undone by replaying it.
+        let persistence = Persistence::new(path.clone());
+        let item = |value: &str| Data { operation_type: OperationType::Insert, partition_key: "partition".to_string(), sort_key: "sort".to_string(), value: value.to_string() };
+        persistence.write_group(&[item("journaled")]).unwrap();
+        persistence.save_data(&item("direct")).unwrap();
+        persistence.recover().unwrap();
+        assert_eq!("direct", persistence.load_data("partition".to_string(), "sort".to_string()).unwrap().value);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_tables() {
+        let path = setup("./test_db15");
+        let mut database = Database::new(path.clone());
+
+        let schema = KeySchema { partition_key: "user_id".to_string(), sort_key: "event_id".to_string() };
+        let description = database.create_table("events", schema.clone()).unwrap();
+        assert_eq!("events", description.name);
+        database.create_table("users", KeySchema::default()).unwrap();
+        assert_eq!(io::ErrorKind::AlreadyExists, database.create_table("events", KeySchema::default()).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidInput, database.create_table("../escape", KeySchema::default()).unwrap_err().kind());
+
+        assert_eq!(vec!["events", "users"], database.list_tables().unwrap());
+        assert_eq!(schema, database.describe_table("events").unwrap().key_schema);
+
+        // The same keys in different tables and in the root keyspace don't collide.
+        database.insert("partition".to_string(), "sort".to_string(), "root".to_string()).unwrap();
+        let mut events = database.table("events").unwrap();
+        events.insert("partition".to_string(), "sort".to_string(), "events".to_string()).unwrap();
+        let mut users = database.table("users").unwrap();
+        users.insert("partition".to_string(), "sort".to_string(), "users".to_string()).unwrap();
+
+        assert_eq!("root", database.get("partition".to_string(), "sort".to_string()).unwrap().unwrap().value);
+        assert_eq!("events", events.get("partition".to_string(), "sort".to_string()).unwrap().unwrap().value);
+        assert_eq!(1, database.scan(None).unwrap().len());
+        assert_eq!(1, users.scan(None).unwrap().len());
+
+        database.drop_table("events").unwrap();
+        assert_eq!(vec!["users"], database.list_tables().unwrap());
+        assert_eq!(io::ErrorKind::NotFound, database.describe_table("events").unwrap_err().kind());
+        assert_eq!(io::ErrorKind::NotFound, database.table("events").unwrap_err().kind());
+        assert_eq!(io::ErrorKind::NotFound, database.drop_table("events").unwrap_err().kind());
+
+        // A handle still open on a dropped table can't bring it back, nor write to a
+        // new table of the same name.
+        assert_eq!(io::ErrorKind::NotFound, events.insert("late".to_string(), "1".to_string(), "x".to_string()).unwrap_err().kind());
+        assert!(!path.join(".tables/events").exists());
+        database.create_table("events", KeySchema::default()).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, events.insert("late".to_string(), "1".to_string(), "x".to_string()).unwrap_err().kind());
+        let mut events = database.table("events").unwrap();
+        events.insert("new".to_string(), "1".to_string(), "x".to_string()).unwrap();
+        assert_eq!(vec!["new"], events.list_partitions().unwrap());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_table_settings() {
+        let path = setup("./test_db35");
+        let database = Database::new(path.clone());
+
+        let settings = TableSettings {
+            time_to_live: Some(TimeToLive { attribute: "expires".to_string() }),
+            indexes: vec![IndexSchema { name: "by_user".to_string(), attribute: "user".to_string() }],
+        };
+        database.create_table_with_settings("sessions", KeySchema::default(), settings.clone()).unwrap();
+        assert_eq!(settings, database.describe_table("sessions").unwrap().settings);
+        let duplicate = TableSettings { indexes: vec![settings.indexes[0].clone(), settings.indexes[0].clone()], ..settings.clone() };
+        assert_eq!(io::ErrorKind::InvalidInput, database.create_table_with_settings("t", KeySchema::default(), duplicate).unwrap_err().kind());
+
+        let mut sessions = database.table("sessions").unwrap();
+        let session = |user: &str, expires: u64| format!(r#"{{"user": "{}", "expires": {}}}"#, user, expires);
+        sessions.insert("s1".to_string(), "$".to_string(), session("ada", 4102444800)).unwrap();
+        sessions.insert("s2".to_string(), "$".to_string(), session("ada", 1)).unwrap();
+        sessions.insert("s3".to_string(), "$".to_string(), r#"{"user": {"S": "a/b"}}"#.to_string()).unwrap();
+        sessions.insert("s4".to_string(), "$".to_string(), "not an object".to_string()).unwrap();
+
+        // Expired items are skipped by reads until they are removed.
+        assert_eq!(io::ErrorKind::NotFound, sessions.get("s2".to_string(), "$".to_string()).unwrap_err().kind());
+        assert_eq!(3, sessions.scan(None).unwrap().len());
+        let keys = vec![("s1".to_string(), "$".to_string()), ("s2".to_string(), "$".to_string())];
+        assert_eq!(vec![("s2".to_string(), "$".to_string())], sessions.batch_get(keys).unwrap().missing);
+        let partition_keys = |items: Vec<Data>| items.into_iter().map(|data| data.partition_key).collect::<Vec<_>>();
+        assert_eq!(vec!["s1"], partition_keys(sessions.query_index("by_user", "ada", None).unwrap()));
+        assert_eq!(vec!["s3"], partition_keys(sessions.query_index("by_user", "a/b", None).unwrap()));
+        assert_eq!(io::ErrorKind::NotFound, sessions.query_index("by_id", "s1", None).unwrap_err().kind());
+
+        // Entries follow the items as they change.
+        sessions.insert("s1".to_string(), "$".to_string(), session("grace", 4102444800)).unwrap();
+        assert!(sessions.query_index("by_user", "ada", None).unwrap().is_empty());
+        sessions.batch(vec![Data { operation_type: OperationType::Insert, partition_key: "s5".to_string(), sort_key: "$".to_string(), value: session("grace", 4102444800) }]).unwrap();
+        assert_eq!(vec!["s1", "s5"], partition_keys(sessions.query_index("by_user", "grace", None).unwrap()));
+        assert_eq!(vec!["s1"], partition_keys(sessions.query_index("by_user", "grace", Some(1)).unwrap()));
+        sessions.delete("s1".to_string(), "$".to_string()).unwrap();
+        assert_eq!(vec!["s5"], partition_keys(database.table("sessions").unwrap().query_index("by_user", "grace", None).unwrap()));
+
+        assert_eq!(1, sessions.remove_expired().unwrap());
+        assert_eq!(3, sessions.iter().unwrap().count());
+
+        // Handles opened after the time to live is removed see every item again.
+        sessions.insert("s6".to_string(), "$".to_string(), session("ada", 1)).unwrap();
+        database.set_table_time_to_live("sessions", None).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, sessions.get("s6".to_string(), "$".to_string()).unwrap_err().kind());
+        assert_eq!(vec!["s6"], partition_keys(database.table("sessions").unwrap().query_index("by_user", "ada", None).unwrap()));
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_stats_and_compact() {
+        let path = setup("./test_db16");
+        let mut database = Database::new(path.clone());
+
+        database.insert("a".to_string(), "1".to_string(), "x".to_string()).unwrap();
+        database.insert("a".to_string(), "2".to_string(), "y".to_string()).unwrap();
+        database.insert("b".to_string(), "1".to_string(), "z".to_string()).unwrap();
+        database.create_table("events", KeySchema::default()).unwrap();
+
+        let stats = database.stats().unwrap();
+        assert_eq!(2, stats.partitions);
+        assert_eq!(3, stats.items);
+        assert!(stats.disk_bytes > 0);
+
+        database.delete("b".to_string(), "1".to_string()).unwrap();
+        assert_eq!(1, database.compact().unwrap());
+        assert_eq!(0, database.compact().unwrap());
+        assert_eq!(vec!["a"], database.list_partitions().unwrap());
+        assert_eq!(vec!["events"], database.list_tables().unwrap());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_import_jsonl() {
+        let path = setup("./test_db17");
+        let mut database = Database::new(path.clone());
+
+        let input = concat!(
+            "{\"partition_key\":\"p\",\"sort_key\":\"a\",\"value\":\"1\"}\n",
+            "\n",
+            "not json\n",
+            "{\"partition_key\":\"p\",\"sort_key\":2,\"value\":{\"nested\":true}}\n",
+            "{\"partition_key\":\"p\",\"value\":\"no sort key\"}\n",
+            "[1, 2]\n",
+            "{\"partition_key\":\"q\",\"sort_key\":\"b\",\"value\":\"3\"}\n",
+        );
+        let mut options = ImportOptions::new(ImportFormat::JsonLines);
+        options.batch_size = 2;
+        options.max_reported_errors = 2;
+        let report = import(&mut database, io::Cursor::new(input), &options).unwrap();
+
+        assert_eq!(3, report.imported);
+        assert_eq!(3, report.failed);
+        assert_eq!(vec![3, 5], report.errors.iter().map(|error| error.line).collect::<Vec<_>>());
+        assert_eq!("missing field 'sort_key'", report.errors[1].message);
+        assert_eq!("{\"nested\":true}", database.get("p".to_string(), "2".to_string()).unwrap().unwrap().value);
+        assert_eq!("3", database.get("q".to_string(), "b".to_string()).unwrap().unwrap().value);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_import_csv() {
+        let path = setup("./test_db18");
+        let mut database = Database::new(path.clone());
+
+        let input = "user,ts,event\r\nu1,1,\"clicked, \"\"twice\"\"\"\r\nu2,2,\"multi\nline\"\nu3,3\nu4,4,viewed\n";
+        let mut options = ImportOptions::new(ImportFormat::Csv);
+        options.mapping = FieldMapping { partition_key: "user".to_string(), sort_key: "ts".to_string(), value: Some("event".to_string()) };
+        let report = import(&mut database, io::Cursor::new(input), &options).unwrap();
+
+        assert_eq!(3, report.imported);
+        assert_eq!(vec![LineError { line: 5, message: "expected 3 columns, found 2".to_string() }], report.errors);
+        assert_eq!("clicked, \"twice\"", database.get("u1".to_string(), "1".to_string()).unwrap().unwrap().value);
+        assert_eq!("multi\nline", database.get("u2".to_string(), "2".to_string()).unwrap().unwrap().value);
+
+        // Without a value field the whole row is stored as a JSON object.
+        options.mapping.value = None;
+        import(&mut database, io::Cursor::new("user,ts,event\nu5,5,x\n"), &options).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&database.get("u5".to_string(), "5".to_string()).unwrap().unwrap().value).unwrap();
+        assert_eq!(serde_json::json!({"user": "u5", "ts": "5", "event": "x"}), value);
+
+        // Keys the database can't store fail their own line, not the batch.
+        let report = import(&mut database, io::Cursor::new("user,ts,event\nu6/x,6,x\n.u7,7,x\nu8,8,x\n"), &options).unwrap();
+        assert_eq!(1, report.imported);
+        assert_eq!(vec![
+            LineError { line: 2, message: "invalid key \"u6/x\"".to_string() },
+            LineError { line: 3, message: "invalid key \".u7\"".to_string() },
+        ], report.errors);
+        assert!(database.get("u8".to_string(), "8".to_string()).unwrap().is_some());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_export() {
+        let path = setup("./test_db19");
+        let mut database = Database::new(path.clone());
+        database.insert("p".to_string(), "a".to_string(), "x,\"y\"".to_string()).unwrap();
+        database.insert("p".to_string(), "b".to_string(), "2".to_string()).unwrap();
+        database.insert("p".to_string(), "c".to_string(), "multi\nline".to_string()).unwrap();
+        database.insert("q".to_string(), "a".to_string(), "4".to_string()).unwrap();
+
+        let mut out = Vec::new();
+        let selection = Selection::Range { partition_key: "p".to_string(), from: Some("b".to_string()), to: None };
+        assert_eq!(2, export(&database, &selection, ExportFormat::JsonLines, &mut out).unwrap());
+        assert_eq!(
+            "{\"partition_key\":\"p\",\"sort_key\":\"b\",\"value\":\"2\"}\n{\"partition_key\":\"p\",\"sort_key\":\"c\",\"value\":\"multi\\nline\"}\n",
+            String::from_utf8(out).unwrap()
+        );
+
+        // A CSV export imports back unchanged with the default mapping.
+        let mut out = Vec::new();
+        assert_eq!(3, export(&database, &Selection::Partition("p".to_string()), ExportFormat::Csv, &mut out).unwrap());
+        let copy_path = setup("./test_db19_copy");
+        let mut copy = Database::new(copy_path.clone());
+        let report = import(&mut copy, io::Cursor::new(out), &ImportOptions::new(ImportFormat::Csv)).unwrap();
+        assert_eq!(3, report.imported);
+        let mut original = database.query("p", None, None).unwrap();
+        let mut copied = copy.scan(None).unwrap();
+        original.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+        copied.sort_by(|a, b| a.sort_key.cmp(&b.sort_key));
+        assert_eq!(original, copied);
+
+        // Invalid options leave an existing output file alone.
+        drop(database);
+        let out_path = PathBuf::from("./test_db19_out.jsonl");
+        fs::write(&out_path, "kept").unwrap();
+        let ferret = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_ferret")).args(args).arg("--db").arg(&path).output().unwrap();
+        let out = out_path.to_str().unwrap();
+        assert_eq!(Some(2), ferret(&["export", out, "--output-format", "bogus"]).status.code());
+        assert_eq!(Some(2), ferret(&["export", out, "--from", "b"]).status.code());
+        assert_eq!(Some(2), ferret(&["export", out, "--output-format", "dump", "--partition", "p"]).status.code());
+        assert_eq!("kept", fs::read_to_string(&out_path).unwrap());
+        assert!(ferret(&["export", out, "--partition", "q"]).status.success());
+        assert_eq!("{\"partition_key\":\"q\",\"sort_key\":\"a\",\"value\":\"4\"}\n", fs::read_to_string(&out_path).unwrap());
+        fs::remove_file(out_path).unwrap();
+
+        teardown(copy_path);
+        teardown(path);
+    }
+
+    #[test]
+    fn test_dump() {
+        let path = setup("./test_db20");
+        let mut database = Database::new(path.clone());
+        database.insert("p".to_string(), "a".to_string(), "root".to_string()).unwrap();
+        let schema = KeySchema { partition_key: "user".to_string(), sort_key: "ts".to_string() };
+        database.create_table("events", schema.clone()).unwrap();
+        database.table("events").unwrap().insert("u1".to_string(), "1".to_string(), "clicked".to_string()).unwrap();
+
+        let mut dump = Vec::new();
+        assert_eq!(2, write_dump(&database, &mut dump).unwrap());
+
+        let copy_path = setup("./test_db20_copy");
+        let mut copy = Database::new(copy_path.clone());
+        assert_eq!(2, restore(&mut copy, dump.as_slice()).unwrap());
+        assert_eq!("root", copy.get("p".to_string(), "a".to_string()).unwrap().unwrap().value);
+        assert_eq!(schema, copy.describe_table("events").unwrap().key_schema);
+        assert_eq!("clicked", copy.table("events").unwrap().get("u1".to_string(), "1".to_string()).unwrap().unwrap().value);
+
+        let mut in_memory = InMemoryDatabase::new();
+        assert_eq!(1, restore_in_memory(&mut in_memory, dump.as_slice(), Some("events")).unwrap());
+        assert_eq!("clicked", in_memory.get("u1".to_string(), "1".to_string()).unwrap().value);
+        let mut in_memory_dump = Vec::new();
+        write_dump_in_memory(&in_memory, &mut in_memory_dump).unwrap();
+        let entries: Vec<DumpEntry> = DumpReader::new(in_memory_dump.as_slice()).unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(1, entries.len());
+
+        let truncated = &dump[..dump.len() - 3];
+        assert_eq!(io::ErrorKind::UnexpectedEof, restore(&mut copy, truncated).unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidData, DumpReader::new(&b"not a dump"[..]).err().unwrap().kind());
+        let mut future = dump.clone();
+        future[8..10].copy_from_slice(&(DUMP_VERSION + 1).to_le_bytes());
+        assert_eq!(io::ErrorKind::InvalidData, DumpReader::new(future.as_slice()).err().unwrap().kind());
+
+        teardown(copy_path);
+        teardown(path);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let source = setup("./test_db21");
+        let record = |partition_key: &str, sort_key: &str, value: &str| {
+            serde_json::to_string(&Data {
+                operation_type: OperationType::Insert,
+                partition_key: partition_key.to_string(),
+                sort_key: sort_key.to_string(),
+                value: value.to_string(),
+            })
+            .unwrap()
+        };
+        for (partition_key, sort_key) in [("a", "1"), ("a", "2"), ("b", "1"), ("c", "1"), (".hidden", "1"), ("a", ".dot"), ("a", "x\\y")] {
+            fs::create_dir_all(source.join(partition_key)).unwrap();
+            fs::write(source.join(partition_key).join(sort_key), record(partition_key, sort_key, "v")).unwrap();
+        }
+        fs::write(source.join("a").join("3"), "{\"type\":\"Data\",\"partition_key\":").unwrap();
+        fs::write(source.join("b").join("2"), record("b", "other", "v")).unwrap();
+
+        let destination_path = setup("./test_db21_copy");
+        let mut destination = Database::new(destination_path.clone());
+        let report = migrate(&source, &mut destination).unwrap();
+        assert_eq!(4, report.migrated);
+        let mut corrupt: Vec<PathBuf> = report.corrupt.into_iter().map(|corrupt| corrupt.path).collect();
+        corrupt.sort();
+        // Including the keys the database can't store.
+        let expected: Vec<PathBuf> = [".hidden", "a/.dot", "a/3", "a/x\\y", "b/2"].iter().map(|name| source.canonicalize().unwrap().join(name)).collect();
+        assert_eq!(expected, corrupt);
+        assert_eq!("v", destination.get("c".to_string(), "1".to_string()).unwrap().unwrap().value);
+
+        // A completed migration is not repeated.
+        assert_eq!(MigrationReport::default(), migrate(&source, &mut destination).unwrap());
+
+        // An interrupted migration resumes after the last checkpointed partition.
+        fs::write(
+            destination_path.join(".migration.json"),
+            serde_json::json!({ "source": source.canonicalize().unwrap(), "last_partition": "b", "complete": false }).to_string(),
+        )
+        .unwrap();
+        let report = migrate(&source, &mut destination).unwrap();
+        assert_eq!((1, 3), (report.migrated, report.resumed_after));
+
+        assert_eq!(io::ErrorKind::InvalidInput, migrate(&destination_path, &mut destination).unwrap_err().kind());
+
+        teardown(destination_path);
+        teardown(source);
+    }
+
+    #[test]
+    fn test_fsck() {
+        let path = setup("./test_db22");
+        let mut database = Database::new(path.clone());
+        for (partition_key, sort_key) in [("a", "1"), ("a", "2"), ("b", "1")] {
+            database.insert(partition_key.to_string(), sort_key.to_string(), "v".to_string()).unwrap();
+        }
+        database.create_table("t", KeySchema::default()).unwrap();
+        database.table("t").unwrap().insert("c".to_string(), "1".to_string(), "v".to_string()).unwrap();
+
+        let report = fsck(&database, FsckMode::Verify).unwrap();
+        assert_eq!((4, 0), (report.checked, report.corrupt.len()));
+
+        fs::write(path.join("a").join("2"), "{\"type\":\"Data\",\"partition_key\":").unwrap();
+        fs::create_dir_all(path.join("d")).unwrap();
+        fs::copy(path.join("b").join("1"), path.join("d").join("1")).unwrap();
+        assert!(Database::new(path.clone()).load_all_data().is_err());
+
+        let mut lenient = Database::new(path.clone());
+        let mut skipped: Vec<PathBuf> = lenient.load_all_data_lenient().unwrap().into_iter().map(|corrupt| corrupt.path).collect();
+        skipped.sort();
+        assert_eq!(vec![path.join("a").join("2"), path.join("d").join("1")], skipped);
+        assert_eq!("v", lenient.get("b".to_string(), "1".to_string()).unwrap().unwrap().value);
+
+        let report = fsck(&database, FsckMode::Verify).unwrap();
+        assert_eq!((5, 2, 0), (report.checked, report.corrupt.len(), report.quarantined));
+        assert!(path.join("a").join("2").exists());
+
+        let report = fsck(&database, FsckMode::Repair).unwrap();
+        assert_eq!((2, 2, 1), (report.corrupt.len(), report.quarantined, report.removed_partitions));
+        assert!(path.join(".quarantine").join("a").join("2").exists());
+        assert!(!path.join("d").exists());
+        assert_eq!(vec!["a".to_string(), "b".to_string()], {
+            let mut partitions = database.list_partitions().unwrap();
+            partitions.sort();
+            partitions
+        });
+        Database::new(path.clone()).load_all_data().unwrap();
+
+        let report = fsck(&database, FsckMode::Verify).unwrap();
+        assert_eq!((3, 0), (report.checked, report.corrupt.len()));
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_checksums() {
+        let path = setup("./test_db23");
+        let mut database = Database::new(path.clone());
+        database.insert("a".to_string(), "1".to_string(), "hello".to_string()).unwrap();
+        database.insert("a".to_string(), "2".to_string(), "world".to_string()).unwrap();
+
+        // A flipped character inside the value still parses as JSON.
+        let file = path.join("a").join("1");
+        let contents = fs::read(&file).unwrap();
+        let position = contents.windows(5).position(|window| window == b"hello").unwrap();
+        let mut flipped = contents.clone();
+        flipped[position] = b'j';
+        fs::write(&file, &flipped).unwrap();
+
+        let mut reopened = Database::new(path.clone());
+        let error = reopened.get("a".to_string(), "1".to_string()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, error.kind());
+        let corruption = CorruptionError::of(&error).unwrap();
+        assert_eq!((file.clone(), "checksum mismatch"), (corruption.path.clone(), corruption.reason.as_str()));
+        assert_eq!("checksum mismatch", fsck(&reopened, FsckMode::Verify).unwrap().corrupt[0].reason);
+
+        // Files written before checksums were added are read as they are.
+        let legacy = Data { operation_type: OperationType::Insert, partition_key: "a".to_string(), sort_key: "1".to_string(), value: "legacy".to_string() };
+        fs::write(&file, serde_json::to_string(&legacy).unwrap()).unwrap();
+        assert_eq!(Some(legacy), Database::new(path.clone()).get("a".to_string(), "1".to_string()).unwrap());
+
+        // A journal record failing its checksum ends the replay like a torn one.
+        let line = |value: &str| {
+            let record = Data { operation_type: OperationType::Insert, partition_key: "b".to_string(), sort_key: value.to_string(), value: value.to_string() };
+            serde_json::to_string(&record).unwrap()
+        };
+        let (good, bad) = (line("1"), line("2"));
+        let checksum = |line: &str| crc32fast::hash(line.as_bytes());
+        let journal = format!("{:08x} {}\n{:08x} {}\n", checksum(&good), good, checksum(&bad) ^ 1, bad);
+        fs::write(path.join(".journal"), journal).unwrap();
+        let mut recovered = Database::new(path.clone());
+        assert!(recovered.get("b".to_string(), "1".to_string()).unwrap().is_some());
+        assert!(recovered.get("b".to_string(), "2".to_string()).is_err());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_compression() {
+        let path = setup("./test_db24");
+        let mut database = Database::new(path.clone());
+        let value = "{\"name\": \"ada\", \"tags\": [\"a\", \"b\"]}".repeat(50);
+
+        database.insert("plain".to_string(), "1".to_string(), value.clone()).unwrap();
+        assert_eq!(1.0, database.stats().unwrap().compression_ratio);
+
+        database.set_compression(Compression::Lz4).unwrap();
+        database.insert("lz4".to_string(), "1".to_string(), value.clone()).unwrap();
+        database.create_table("t", KeySchema::default()).unwrap();
+        let table = database.table("t").unwrap();
+        table.set_compression(Compression::Zstd).unwrap();
+        drop(table);
+        let mut table = database.table("t").unwrap();
+        assert_eq!(Compression::Zstd, table.compression());
+        table.insert("zstd".to_string(), "1".to_string(), value.clone()).unwrap();
+
+        assert!(fs::metadata(path.join("lz4").join("1")).unwrap().len() < fs::metadata(path.join("plain").join("1")).unwrap().len());
+        let stats = table.stats().unwrap();
+        assert!(stats.compression_ratio > 2.0, "{:?}", stats);
+        assert!(stats.uncompressed_bytes > stats.disk_bytes);
+
+        // Short values that don't shrink are stored uncompressed.
+        table.insert("zstd".to_string(), "2".to_string(), "x".to_string()).unwrap();
+
+        // The setting is kept with the keyspace and reads don't depend on it.
+        let mut reopened = Database::new(path.clone());
+        assert_eq!(Compression::Lz4, reopened.compression());
+        reopened.set_compression(Compression::None).unwrap();
+        for partition_key in ["plain", "lz4"] {
+            assert_eq!(value, reopened.get(partition_key.to_string(), "1".to_string()).unwrap().unwrap().value);
+        }
+        let mut table = reopened.table("t").unwrap();
+        assert_eq!(value, table.get("zstd".to_string(), "1".to_string()).unwrap().unwrap().value);
+        assert_eq!("x", table.get("zstd".to_string(), "2".to_string()).unwrap().unwrap().value);
+        assert_eq!(0, fsck(&reopened, FsckMode::Verify).unwrap().corrupt.len());
+
+        // A flipped bit in a compressed record fails its checksum before decompression.
+        let file = path.join("lz4").join("1");
+        let mut contents = fs::read(&file).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 1;
+        fs::write(&file, contents).unwrap();
+        let error = Database::new(path.clone()).get("lz4".to_string(), "1".to_string()).unwrap_err();
+        assert_eq!("checksum mismatch", CorruptionError::of(&error).unwrap().reason);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_encryption() {
+        let path = setup("./test_db25");
+        let mut database = Database::new(path.clone());
+        database.insert("p".to_string(), "plain".to_string(), "old secret".to_string()).unwrap();
+
+        let mut keyring = Keyring::new(EncryptionKey::generate(1));
+        database.set_keyring(Some(keyring.clone())).unwrap();
+        database.set_compression(Compression::Lz4).unwrap();
+        database.insert("p".to_string(), "1".to_string(), "secret ".repeat(20)).unwrap();
+        database.create_table("t", KeySchema::default()).unwrap();
+        database.table("t").unwrap().insert("q".to_string(), "1".to_string(), "table secret".to_string()).unwrap();
+
+        let contents = fs::read(path.join("p").join("1")).unwrap();
+        assert!(!contents.windows(6).any(|window| window == b"secret"));
+        assert!(!fs::read(path.join(".tables/t/q/1")).unwrap().windows(6).any(|window| window == b"secret"));
+
+        // Without the key, encrypted records can't be read but plaintext ones still can.
+        let mut locked = Database::new(path.clone());
+        assert_eq!(io::ErrorKind::PermissionDenied, locked.get("p".to_string(), "1".to_string()).unwrap_err().kind());
+        assert_eq!("old secret", locked.get("p".to_string(), "plain".to_string()).unwrap().unwrap().value);
+
+        // Rotating keeps records under the old key readable until they are rewritten.
+        let key_file = path.join("keys.json");
+        keyring.rotate(EncryptionKey::generate(keyring.next_id()));
+        keyring.save(&key_file).unwrap();
+        let keyring = Keyring::load(&key_file).unwrap();
+        assert_eq!(2, keyring.active_id());
+        let mut rotated = Database::new(path.clone());
+        rotated.set_keyring(Some(keyring.clone())).unwrap();
+        assert_eq!("secret ".repeat(20), rotated.get("p".to_string(), "1".to_string()).unwrap().unwrap().value);
+
+        let job = rotated.start_reencryption().unwrap();
+        assert_eq!(3, job.join().unwrap());
+        assert_eq!(0, rotated.start_reencryption().unwrap().join().unwrap());
+
+        let mut retired = keyring.clone();
+        retired.remove(1).unwrap();
+        assert!(retired.remove(2).is_err());
+        let mut reopened = Database::new(path.clone());
+        reopened.set_keyring(Some(retired)).unwrap();
+        assert_eq!("old secret", reopened.get("p".to_string(), "plain".to_string()).unwrap().unwrap().value);
+        assert_eq!("table secret", reopened.table("t").unwrap().get("q".to_string(), "1".to_string()).unwrap().unwrap().value);
+        assert_eq!(0, fsck(&reopened, FsckMode::Verify).unwrap().corrupt.len());
+
+        // The wrong key with a matching id fails authentication.
+        let mut impostor = Database::new(path.clone());
+        impostor.set_keyring(Some(Keyring::new(EncryptionKey::new(2, [7; 32])))).unwrap();
+        let error = impostor.get("p".to_string(), "1".to_string()).unwrap_err();
+        assert!(CorruptionError::of(&error).unwrap().reason.contains("failed to decrypt"));
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_encrypted_group_commit() {
+        let path = setup("./test_db26");
+        let keyring = Keyring::new(EncryptionKey::generate(1));
+        let mut database = Database::with_group_commit(path.clone(), GroupCommitConfig::default());
+        database.set_keyring(Some(keyring.clone())).unwrap();
+        let record = Data { operation_type: OperationType::Insert, partition_key: "p".to_string(), sort_key: "2".to_string(), value: "secret".to_string() };
+        database.insert("p".to_string(), "2".to_string(), "secret".to_string()).unwrap();
+        drop(database);
+
+        // Simulate a crash after an encrypted group was journaled but before its file
+        // was written. Journal lines of encrypted groups hold the encoded record in hex.
+        let encoded = fs::read(path.join("p").join("2")).unwrap();
+        let hex: String = encoded.iter().map(|byte| format!("{:02x}", byte)).collect();
+        fs::write(path.join(".journal"), format!("{:08x} {}\n", crc32fast::hash(hex.as_bytes()), hex)).unwrap();
+        fs::remove_file(path.join("p").join("2")).unwrap();
+
+        // The journal waits for the keyring instead of being discarded.
+        let mut reopened = Database::with_group_commit(path.clone(), GroupCommitConfig::default());
+        assert!(reopened.get("p".to_string(), "2".to_string()).is_err());
+        assert_eq!(io::ErrorKind::PermissionDenied, reopened.insert("p".to_string(), "3".to_string(), "v".to_string()).unwrap_err().kind());
+        reopened.set_keyring(Some(keyring)).unwrap();
+        assert_eq!(Some(record), reopened.get("p".to_string(), "2".to_string()).unwrap());
+        assert_eq!(0, fs::metadata(path.join(".journal")).unwrap().len());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_bloom_filters() {
+        let path = setup("./test_db27");
+        let mut database = Database::new(path.clone());
+        for sort_key in ["a", "b", "c"] {
+            database.insert("p".to_string(), sort_key.to_string(), "1".to_string()).unwrap();
+        }
+        database.flush().unwrap();
+        assert!(path.join(".bloom/p").is_file());
+
+        // A file the filter doesn't know about is never read.
+        let data = Data { operation_type: OperationType::Insert, partition_key: "p".to_string(), sort_key: "hidden".to_string(), value: "2".to_string() };
+        fs::write(path.join("p/hidden"), serde_json::to_string(&data).unwrap()).unwrap();
+        assert_eq!(io::ErrorKind::NotFound, database.get("p".to_string(), "hidden".to_string()).unwrap_err().kind());
+        assert_eq!(vec![("p".to_string(), "hidden".to_string())], database.batch_get(vec![("p".to_string(), "hidden".to_string())]).unwrap().missing);
+
+        // Compaction rebuilds filters from the files.
+        database.compact().unwrap();
+        assert_eq!("2", database.get("p".to_string(), "hidden".to_string()).unwrap().unwrap().value);
+
+        // A write invalidates the saved filter until it is saved again.
+        database.insert("p".to_string(), "d".to_string(), "1".to_string()).unwrap();
+        assert!(!path.join(".bloom/p").exists());
+        drop(database);
+        assert!(path.join(".bloom/p").is_file());
+
+        // Filters grow with their partitions and keep every key across reopens.
+        let database = Database::new(path.clone());
+        assert!(database.set_bloom_false_positive_rate(0.0).is_err());
+        database.set_bloom_false_positive_rate(0.001).unwrap();
+        let mut database = Database::new(path.clone());
+        assert_eq!(0.001, database.bloom_false_positive_rate());
+        for i in 0..200 {
+            database.insert("q".to_string(), i.to_string(), i.to_string()).unwrap();
+        }
+        drop(database);
+        let mut database = Database::new(path.clone());
+        for i in 0..200 {
+            assert!(database.get("q".to_string(), i.to_string()).is_ok());
+        }
+        assert!(database.get("q".to_string(), "200".to_string()).is_err());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_lock() {
+        let path = setup("./test_db28");
+        let lock_file = path.join(".lock");
+        let mut database = Database::open(path.clone()).unwrap();
+        database.insert("p".to_string(), "a".to_string(), "1".to_string()).unwrap();
+        assert_eq!(std::process::id().to_string(), fs::read_to_string(&lock_file).unwrap());
+
+        // Handles within one process share the lock.
+        let same_process = Database::open(path.clone()).unwrap();
+
+        // Another process is turned away, but can open the database read-only.
+        let ferret = |args: &[&str]| std::process::Command::new(env!("CARGO_BIN_EXE_ferret")).args(args).arg("--db").arg(&path).output().unwrap();
+        let output = ferret(&["get", "p", "a"]);
+        assert_eq!(Some(1), output.status.code());
+        let message = format!("is locked by process {}", std::process::id());
+        assert!(String::from_utf8_lossy(&output.stderr).contains(&message));
+        let output = ferret(&["get", "p", "a", "--read-only", "--format", "json"]);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).contains("\"value\":\"1\""));
+        assert!(!ferret(&["put", "p", "b", "2", "--read-only"]).status.success());
+
+        // The last handle to close marks the keyspace as closed cleanly.
+        drop(same_process);
+        drop(database);
+        assert_eq!("", fs::read_to_string(&lock_file).unwrap());
+        assert!(ferret(&["put", "p", "b", "2"]).status.success());
+        // So do commands that fail after opening the database.
+        assert_eq!(Some(3), ferret(&["get", "p", "missing"]).status.code());
+        assert_eq!(Some(2), ferret(&["fsck", "--mode", "bogus"]).status.code());
+        assert_eq!("", fs::read_to_string(&lock_file).unwrap());
+        assert_eq!(None, Database::open(path.clone()).unwrap().stale_lock());
+
+        // A process id left in an unlocked file is a stale lock, taken over on open.
+        fs::write(&lock_file, "4194305").unwrap();
+        let database = Database::open(path.clone()).unwrap();
+        assert_eq!(Some(4194305), database.stale_lock());
+        drop(database);
+
+        let error = io::Error::new(io::ErrorKind::WouldBlock, LockError { path: path.clone(), pid: Some(7) });
+        assert_eq!(Some(7), LockError::of(&error).unwrap().pid);
+        assert_eq!(io::ErrorKind::NotFound, Database::open_read_only(PathBuf::from("./test_db28_missing")).unwrap_err().kind());
+        assert!(!PathBuf::from("./test_db28_missing").exists());
+
+        teardown(path);
+    }
+
+    /// Every file and directory under `path`, with its size and modification time.
+    fn snapshot(path: &std::path::Path, entries: &mut Vec<(PathBuf, u64, std::time::SystemTime)>) {
+        for entry in fs::read_dir(path).unwrap() {
+            let entry = entry.unwrap();
+            let metadata = entry.metadata().unwrap();
+            entries.push((entry.path(), metadata.len(), metadata.modified().unwrap()));
+            if metadata.is_dir() {
+                snapshot(&entry.path(), entries);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_only() {
+        let path = setup("./test_db29");
+        let mut writer = Database::open(path.clone()).unwrap();
+        writer.insert("p".to_string(), "a".to_string(), "1".to_string()).unwrap();
+        writer.create_table("t", KeySchema::default()).unwrap();
+        writer.table("t").unwrap().insert("p".to_string(), "a".to_string(), "2".to_string()).unwrap();
+        writer.flush().unwrap();
+        let mut before = Vec::new();
+        snapshot(&path, &mut before);
+
+        // Opens alongside the writer, and reads everything.
+        let mut reader = Database::open_read_only(path.clone()).unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!("1", reader.get("p".to_string(), "a".to_string()).unwrap().unwrap().value);
+        assert!(reader.get("p".to_string(), "z".to_string()).is_err());
+        assert_eq!(1, reader.scan(None).unwrap().len());
+        assert_eq!(1, reader.stats().unwrap().items);
+        assert_eq!(0, fsck(&reader, FsckMode::Verify).unwrap().corrupt.len());
+        assert_eq!(2, write_dump(&reader, Vec::new()).unwrap());
+        let mut table = reader.table("t").unwrap();
+        assert!(table.is_read_only());
+        assert_eq!("2", table.get("p".to_string(), "a".to_string()).unwrap().unwrap().value);
+
+        // Every kind of write is rejected with a typed error.
+        let item = Data { operation_type: OperationType::Insert, partition_key: "p".to_string(), sort_key: "b".to_string(), value: "3".to_string() };
+        reader.set_keyring(Some(Keyring::new(EncryptionKey::generate(1)))).unwrap();
+        // Even a migration with nothing to copy doesn't get to write its checkpoint.
+        let legacy = setup("./test_db29_legacy");
+        let errors = vec![
+            migrate(&legacy, &mut reader).unwrap_err(),
+            reader.insert("p".to_string(), "b".to_string(), "3".to_string()).unwrap_err(),
+            reader.delete("p".to_string(), "a".to_string()).unwrap_err(),
+            reader.batch(vec![item]).unwrap_err(),
+            table.insert("p".to_string(), "b".to_string(), "3".to_string()).unwrap_err(),
+            reader.create_table("u", KeySchema::default()).unwrap_err(),
+            reader.drop_table("t").unwrap_err(),
+            reader.compact().unwrap_err(),
+            reader.set_compression(Compression::Zstd).unwrap_err(),
+            reader.set_bloom_false_positive_rate(0.1).unwrap_err(),
+            fsck(&reader, FsckMode::Repair).unwrap_err(),
+            reader.start_reencryption().unwrap_err(),
+        ];
+        for error in errors {
+            assert_eq!(io::ErrorKind::PermissionDenied, error.kind());
+            assert!(ReadOnlyError::of(&error).is_some(), "{}", error);
+        }
+        assert_eq!("1", reader.get("p".to_string(), "a".to_string()).unwrap().unwrap().value);
+        drop(table);
+        drop(reader);
+        teardown(legacy);
+
+        let mut after = Vec::new();
+        snapshot(&path, &mut after);
+        assert_eq!(before, after);
+
+        // Writes made by the writer meanwhile are seen by items the reader hasn't cached.
+        let mut reader = Database::open_read_only(path.clone()).unwrap();
+        writer.insert("q".to_string(), "a".to_string(), "4".to_string()).unwrap();
+        assert_eq!("4", reader.get("q".to_string(), "a".to_string()).unwrap().unwrap().value);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let path = setup("./test_db30");
+        let mut database = Database::open(path.clone()).unwrap();
+        let outside = fs::canonicalize(".").unwrap().join("test_db30_outside");
+        let keys = [
+            ("p", ""), ("", "s"), ("p", "."), ("..", "s"), ("p", ".."), ("p", "a/b"), ("p", "a\\b"), ("p", "a\0b"),
+            ("p", ".journal"), (".tables", "s"), (".bloom", "s"), ("p", outside.to_str().unwrap()),
+        ];
+        for (partition_key, sort_key) in keys {
+            let (partition_key, sort_key) = (partition_key.to_string(), sort_key.to_string());
+            let item = Data { operation_type: OperationType::Insert, partition_key: partition_key.clone(), sort_key: sort_key.clone(), value: "v".to_string() };
+            let errors = vec![
+                database.insert(partition_key.clone(), sort_key.clone(), "v".to_string()).unwrap_err(),
+                database.batch(vec![item]).unwrap_err(),
+                database.get(partition_key.clone(), sort_key.clone()).unwrap_err(),
+                database.delete(partition_key.clone(), sort_key.clone()).unwrap_err(),
+            ];
+            for error in errors {
+                assert_eq!(io::ErrorKind::InvalidInput, error.kind(), "{:?} {:?}", partition_key, sort_key);
+            }
+        }
+        assert!(!outside.exists());
+        assert_eq!(0, database.stats().unwrap().items);
+
+        // Keys that only contain dots or reserved names further in are fine.
+        database.insert("p.q".to_string(), "a..b".to_string(), "v".to_string()).unwrap();
+        assert_eq!(1, database.stats().unwrap().items);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_read_only_with_writer() {
+        let path = setup("./test_db31");
+        let mut writer = Database::open(path.clone()).unwrap();
+        writer.insert("p".to_string(), "a".to_string(), "0".repeat(200_000)).unwrap();
+        let reader = Database::open_read_only(path.clone()).unwrap();
+
+        // Items are replaced whole, so a reader never sees one partly written.
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let writing = {
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                for i in 1..200 {
+                    let digit = char::from(b'0' + (i % 10) as u8);
+                    writer.insert("p".to_string(), "a".to_string(), digit.to_string().repeat(200_000)).unwrap();
+                }
+                done.store(true, std::sync::atomic::Ordering::SeqCst);
+            })
+        };
+        let mut reads = 0;
+        while !done.load(std::sync::atomic::Ordering::SeqCst) || reads == 0 {
+            let items = reader.query("p", None, None).unwrap();
+            assert_eq!(1, items.len());
+            assert_eq!(200_000, items[0].value.len());
+            assert!(fsck(&reader, FsckMode::Verify).unwrap().corrupt.is_empty());
+            reads += 1;
+        }
+        writing.join().unwrap();
+        assert_eq!(1, reader.stats().unwrap().items);
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_group_commit_lock() {
+        let path = setup("./test_db32");
+        let config = GroupCommitConfig::default();
+        let database = Database::open_with_group_commit(path.clone(), config).unwrap();
+        database.create_table("t", KeySchema::default()).unwrap();
+
+        // Another process holds the table open.
+        let table_path = path.join(".tables").join("t");
+        let mut repl = std::process::Command::new(env!("CARGO_BIN_EXE_ferret"))
+            .args(["repl", "--db"])
+            .arg(&table_path)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        while fs::read_to_string(table_path.join(".lock")).unwrap_or_default() != repl.id().to_string() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let error = database.table("t").unwrap_err();
+        assert_eq!(Some(repl.id()), LockError::of(&error).unwrap().pid);
+        let error = Database::open_with_group_commit(table_path.clone(), config).unwrap_err();
+        assert_eq!(Some(repl.id()), LockError::of(&error).unwrap().pid);
+
+        drop(repl.stdin.take());
+        repl.wait().unwrap();
+        database.table("t").unwrap().insert("p".to_string(), "a".to_string(), "1".to_string()).unwrap();
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_reencryption_with_writes() {
+        let path = setup("./test_db33");
+        let mut keyring = Keyring::new(EncryptionKey::generate(1));
+        let mut database = Database::open(path.clone()).unwrap();
+        database.set_keyring(Some(keyring.clone())).unwrap();
+        database.create_table("t", KeySchema::default()).unwrap();
+        for i in 0..300 {
+            database.insert("p".to_string(), i.to_string(), "old".to_string()).unwrap();
+        }
+
+        // Writes and deletes made while the job runs are never undone by it.
+        keyring.rotate(EncryptionKey::generate(2));
+        database.set_keyring(Some(keyring.clone())).unwrap();
+        let job = database.start_reencryption().unwrap();
+        for i in 0..300 {
+            match i % 3 {
+                0 => database.delete("p".to_string(), i.to_string()).unwrap(),
+                _ => database.insert("p".to_string(), i.to_string(), "new".to_string()).unwrap(),
+            }
+        }
+        job.join().unwrap();
+        let mut reopened = Database::open(path.clone()).unwrap();
+        reopened.set_keyring(Some(keyring.clone())).unwrap();
+        for i in 0..300 {
+            match reopened.get("p".to_string(), i.to_string()) {
+                Ok(data) => assert_eq!("new", data.unwrap().value, "{}", i),
+                Err(e) => assert!(i % 3 == 0 && e.kind() == io::ErrorKind::NotFound, "{}: {}", i, e),
+            }
+        }
+
+        // A keyspace that can't be opened fails the job instead of ending it silently.
+        let table_path = path.join(".tables").join("t");
+        let mut repl = std::process::Command::new(env!("CARGO_BIN_EXE_ferret"))
+            .args(["repl", "--db"])
+            .arg(&table_path)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        while fs::read_to_string(table_path.join(".lock")).unwrap_or_default() != repl.id().to_string() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let error = database.start_reencryption().unwrap().join().unwrap_err();
+        assert_eq!(Some(repl.id()), LockError::of(&error).unwrap().pid);
+        drop(repl.stdin.take());
+        repl.wait().unwrap();
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_rotate_key_command() {
+        let path = setup("./test_db34");
+        let key_file = path.join("keys.json");
+        let rotate_key = || {
+            std::process::Command::new(env!("CARGO_BIN_EXE_ferret"))
+                .args(["rotate-key", "--format", "json", "--key-file"])
+                .arg(&key_file)
+                .output()
+                .unwrap()
+        };
+
+        // No database is needed to create or rotate a key file.
+        let output = rotate_key();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(String::from_utf8_lossy(&output.stdout).contains("\"active_key\":1"));
+        assert_eq!(1, Keyring::load(&key_file).unwrap().active_id());
+        assert!(rotate_key().status.success());
+        assert_eq!(2, Keyring::load(&key_file).unwrap().active_id());
+
+        // Other commands still insist on --db.
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_ferret")).args(["stats"]).output().unwrap();
+        assert_eq!(Some(2), output.status.code());
+
+        teardown(path);
+    }
 }
@@ -1,4 +1,4 @@
-use data_ferret::db::{InMemoryDatabase, Data, OperationType};
+use data_ferret::db::{InMemoryDatabase, Data, OperationType, Attribute, Filter};
 
 #[cfg(test)]
 mod tests {
@@ -76,4 +76,58 @@ mod tests {
         let result = database.get_all(partition_key.clone());
         println!("{:?}", result);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_scan_in_memory() {
+        let mut database = InMemoryDatabase::new();
+
+        for i in 0..20 {
+            database.insert(format!("partition{}", i % 4), format!("sort{}", i), format!("value{}", i));
+        }
+
+        let result = database.scan(None);
+        assert_eq!(20, result.len());
+
+        let filter = Filter::Eq(Attribute::PartitionKey, "partition1".to_string())
+            .and(Filter::BeginsWith(Attribute::Value, "value1".to_string()));
+        let mut result: Vec<String> = database.scan(Some(&filter)).into_iter().map(|data| data.sort_key).collect();
+        result.sort();
+        assert_eq!(vec!["sort1".to_string(), "sort13".to_string(), "sort17".to_string()], result);
+    }
+
+    #[test]
+    fn test_parallel_scan_in_memory() {
+        let mut database = InMemoryDatabase::new();
+
+        for i in 0..50 {
+            database.insert(format!("partition{}", i), "sort".to_string(), format!("value{}", i));
+        }
+
+        let segmented: usize = (0..4).map(|segment| database.scan_segment(None, segment, 4).len()).sum();
+        assert_eq!(50, segmented);
+
+        let filter = Filter::Contains(Attribute::Value, "5".to_string()).negate();
+        let mut result = database.parallel_scan(Some(&filter), 4);
+        let mut expected = database.scan(Some(&filter));
+        result.sort_by(|a, b| a.partition_key.cmp(&b.partition_key));
+        expected.sort_by(|a, b| a.partition_key.cmp(&b.partition_key));
+        assert_eq!(expected, result);
+        assert_eq!(45, result.len());
+    }
+
+    #[test]
+    fn test_iter_partition_in_memory() {
+        let mut database = InMemoryDatabase::new();
+
+        database.insert("partition".to_string(), "sort1".to_string(), "value1".to_string());
+        database.insert("partition".to_string(), "sort2".to_string(), "value2".to_string());
+        database.insert("other".to_string(), "sort".to_string(), "value".to_string());
+
+        let mut result: Vec<&str> = database.iter_partition("partition").map(|data| data.value.as_str()).collect();
+        result.sort();
+        assert_eq!(vec!["value1", "value2"], result);
+        assert_eq!(0, database.iter_partition("missing").count());
+        assert_eq!(3, database.iter().count());
+    }
+}
+
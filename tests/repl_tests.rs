@@ -0,0 +1,97 @@
+use data_ferret::db::{Attribute, Database, Filter, KeySchema};
+use data_ferret::repl::{parse, Session, Statement};
+use std::path::PathBuf;
+use std::fs;
+use std::io;
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn setup(database_path: &str) -> PathBuf {
+        let path = PathBuf::from(database_path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn teardown(path: PathBuf) {
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Statement::Get { table: "users".to_string(), partition_key: "42".to_string(), sort_key: None },
+            parse("GET users 42").unwrap()
+        );
+        assert_eq!(
+            Statement::Put {
+                table: ".".to_string(),
+                partition_key: "u1".to_string(),
+                sort_key: "a b".to_string(),
+                value: "{\"x\": 1}".to_string(),
+            },
+            parse(r#"put . u1 "a b" '{"x": 1}'"#).unwrap()
+        );
+        assert_eq!(
+            Statement::Query {
+                table: "events".to_string(),
+                partition_key: "u1".to_string(),
+                filter: Some(Filter::Between(Attribute::SortKey, "a".to_string(), "b".to_string())),
+                limit: Some(10),
+            },
+            parse("QUERY events PK=u1 SK BETWEEN a AND b LIMIT 10").unwrap()
+        );
+        assert_eq!(
+            Statement::Query {
+                table: "events".to_string(),
+                partition_key: "u1".to_string(),
+                filter: Some(Filter::Ge(Attribute::SortKey, "2024".to_string())),
+                limit: None,
+            },
+            parse("query events pk = u1 sk>=2024").unwrap()
+        );
+        assert_eq!(Statement::Scan { table: ".".to_string(), limit: Some(5) }, parse("SCAN . LIMIT 5").unwrap());
+        assert_eq!(Statement::Exit, parse("quit").unwrap());
+
+        for invalid in ["", "FETCH . a", "GET .", "QUERY events u1", "QUERY e PK=u1 SK LIKE a", "SCAN . LIMIT x", "GET . a b c", "PUT . a b 'open"] {
+            assert_eq!(io::ErrorKind::InvalidInput, parse(invalid).unwrap_err().kind(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_session() {
+        let path = setup("./test_repl_db1");
+        let database = Database::new(path.clone());
+        database.create_table("events", KeySchema::default()).unwrap();
+        let mut session = Session::new(database);
+
+        session.execute(parse("PUT events user1 2024-01 clicked").unwrap()).unwrap();
+        session.execute(parse("PUT events user1 2024-02 '{\"page\": 2}'").unwrap()).unwrap();
+        session.execute(parse("PUT events user2 2024-01 viewed").unwrap()).unwrap();
+        session.execute(parse("PUT . root r v").unwrap()).unwrap();
+
+        assert_eq!("clicked\n", session.execute(parse("GET events user1 2024-01").unwrap()).unwrap());
+        assert_eq!("{\n  \"page\": 2\n}\n", session.execute(parse("GET events user1 2024-02").unwrap()).unwrap());
+        assert_eq!("(not found)\n", session.execute(parse("GET events user1 2023").unwrap()).unwrap());
+        let output = session.execute(parse("QUERY events PK=user1 SK > 2024-01").unwrap()).unwrap();
+        assert!(output.contains("2024-02") && !output.contains("clicked") && output.ends_with("(1 item)\n"));
+        assert!(session.execute(parse("SCAN events").unwrap()).unwrap().ends_with("(3 items)\n"));
+        assert_eq!(".\nevents\n", session.execute(parse("TABLES").unwrap()).unwrap());
+        assert_eq!(io::ErrorKind::NotFound, session.execute(parse("GET missing a b").unwrap()).unwrap_err().kind());
+
+        assert_eq!((0, vec!["QUERY".to_string(), "QUIT".to_string()]), session.complete("q", 1));
+        assert_eq!((4, vec!["events".to_string()]), session.complete("GET e", 5));
+        assert_eq!((11, vec!["user1".to_string(), "user2".to_string()]), session.complete("GET events u", 12));
+        assert_eq!((16, vec!["user1".to_string(), "user2".to_string()]), session.complete("QUERY events PK=u", 17));
+        assert_eq!((6, vec!["root".to_string()]), session.complete("PUT . ", 6));
+        assert_eq!((8, Vec::<String>::new()), session.complete("QUERY . éé", "QUERY . éé".len()));
+        assert_eq!((13, Vec::<String>::new()), session.complete("QUERY events é", "QUERY events é".len()));
+
+        session.execute(parse("DELETE events user2 2024-01").unwrap()).unwrap();
+        assert!(session.execute(parse("GET events user2").unwrap()).unwrap().ends_with("(0 items)\n"));
+
+        teardown(path);
+    }
+}
@@ -0,0 +1,515 @@
+use data_ferret::db::{Database, Data, GroupCommitConfig, OperationType};
+use data_ferret::server::{Server, RestServer, DynamoServer, RespServer, Response, TableHandles};
+use serde_json::json;
+use std::path::PathBuf;
+use std::fs;
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpStream;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    fn setup(database_path: &str) -> PathBuf {
+        let path = PathBuf::from(database_path);
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn teardown(path: PathBuf) {
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    fn read_response(reader: &mut BufReader<TcpStream>) -> Response {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    fn http(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, method: &str, path: &str, body: &str) -> (u16, String) {
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        read_http_response(reader)
+    }
+
+    fn read_http_response(reader: &mut BufReader<TcpStream>) -> (u16, String) {
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).unwrap();
+        let status = status_line.split_whitespace().nth(1).unwrap().parse().unwrap();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some(length) = header.strip_prefix("Content-Length: ") {
+                content_length = length.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        (status, String::from_utf8(body).unwrap())
+    }
+
+    #[test]
+    fn test_tcp_pipelining_and_shutdown() {
+        let path = setup("./test_server_db1");
+        let database = Arc::new(Mutex::new(Database::new(path.clone())));
+        let server = Server::bind("127.0.0.1:0", database).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.run().unwrap());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        // All requests are written before any response is read.
+        let requests = concat!(
+            "{\"op\":\"insert\",\"partition_key\":\"p\",\"sort_key\":\"b\",\"value\":\"2\"}\n",
+            "{\"op\":\"batch\",\"items\":[{\"type\":\"Data\",\"operation_type\":\"Insert\",\"partition_key\":\"p\",\"sort_key\":\"a\",\"value\":\"1\"}]}\n",
+            "{\"op\":\"get\",\"partition_key\":\"p\",\"sort_key\":\"a\"}\n",
+            "{\"op\":\"get\",\"partition_key\":\"p\",\"sort_key\":\"missing\"}\n",
+            "not json\n",
+            "{\"op\":\"query\",\"partition_key\":\"p\",\"filter\":{\"Ge\":[\"SortKey\",\"a\"]},\"limit\":5}\n",
+            "{\"op\":\"delete\",\"partition_key\":\"p\",\"sort_key\":\"b\"}\n",
+            "{\"op\":\"insert\",\"partition_key\":\"..\",\"sort_key\":\"escaped\",\"value\":\"2\"}\n",
+        );
+        stream.write_all(requests.as_bytes()).unwrap();
+
+        let item = |sort_key: &str, value: &str| Data {
+            operation_type: OperationType::Insert,
+            partition_key: "p".to_string(),
+            sort_key: sort_key.to_string(),
+            value: value.to_string(),
+        };
+        assert_eq!(Response::Ok, read_response(&mut reader));
+        assert_eq!(Response::Ok, read_response(&mut reader));
+        assert_eq!(Response::Item { data: Some(item("a", "1")) }, read_response(&mut reader));
+        assert_eq!(Response::Item { data: None }, read_response(&mut reader));
+        assert!(matches!(read_response(&mut reader), Response::Error { kind, .. } if kind == "InvalidInput"));
+        assert_eq!(Response::Items { items: vec![item("a", "1"), item("b", "2")] }, read_response(&mut reader));
+        assert_eq!(Response::Ok, read_response(&mut reader));
+        assert!(matches!(read_response(&mut reader), Response::Error { kind, .. } if kind == "InvalidInput"));
+        assert!(!PathBuf::from("escaped").exists());
+
+        // An overlong line is rejected without being buffered, and the next one is served.
+        let overlong = vec![b'x'; 64 * 1024 * 1024 + 1];
+        let writer = {
+            let mut stream = stream.try_clone().unwrap();
+            thread::spawn(move || {
+                stream.write_all(&overlong).unwrap();
+                stream.write_all(b"\n{\"op\":\"get\",\"partition_key\":\"p\",\"sort_key\":\"a\"}\n").unwrap();
+            })
+        };
+        assert!(matches!(read_response(&mut reader), Response::Error { kind, .. } if kind == "InvalidData"));
+        assert_eq!(Response::Item { data: Some(item("a", "1")) }, read_response(&mut reader));
+        writer.join().unwrap();
+
+        // Shutting down closes idle connections and stops the accept loop.
+        shutdown.shutdown();
+        handle.join().unwrap();
+        let mut line = String::new();
+        assert_eq!(0, reader.read_line(&mut line).unwrap());
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_rest_api() {
+        let path = setup("./test_server_db2");
+        let database = Arc::new(Mutex::new(Database::new(path.clone())));
+        let server = RestServer::bind("127.0.0.1:0", database, TableHandles::new()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.run().unwrap());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let (status, body) = http(&mut stream, &mut reader, "PUT", "/users/user%2042", "hello world");
+        assert_eq!(200, status);
+        let stored: Data = serde_json::from_str(&body).unwrap();
+        assert_eq!("user 42", stored.sort_key);
+
+        let (status, body) = http(&mut stream, &mut reader, "GET", "/users/user%2042", "");
+        assert_eq!(200, status);
+        assert_eq!(stored, serde_json::from_str(&body).unwrap());
+        assert_eq!(404, http(&mut stream, &mut reader, "GET", "/users/nobody", "").0);
+
+        let batch = r#"[{"type":"Data","operation_type":"Insert","partition_key":"users","sort_key":"user 1","value":"a"}]"#;
+        assert_eq!(204, http(&mut stream, &mut reader, "POST", "/_batch", batch).0);
+
+        let (status, body) = http(&mut stream, &mut reader, "GET", "/users?begins_with=user+4&limit=10", "");
+        assert_eq!(200, status);
+        assert_eq!(vec![stored.clone()], serde_json::from_str::<Vec<Data>>(&body).unwrap());
+
+        let (status, body) = http(&mut stream, &mut reader, "POST", "/_query", r#"{"partition_key":"users","limit":1}"#);
+        assert_eq!(200, status);
+        assert_eq!("user 1", serde_json::from_str::<Vec<Data>>(&body).unwrap()[0].sort_key);
+
+        assert_eq!(204, http(&mut stream, &mut reader, "DELETE", "/users/user%2042", "").0);
+        assert_eq!(404, http(&mut stream, &mut reader, "DELETE", "/users/user%2042", "").0);
+        assert_eq!(405, http(&mut stream, &mut reader, "PATCH", "/users/user%201", "").0);
+
+        // Keys can't name files outside the database directory.
+        let outside = fs::canonicalize(".").unwrap().join("test_server_outside");
+        let escaped = outside.to_str().unwrap().replace('/', "%2F");
+        for target in [format!("/x/{}", escaped), "/%2E%2E/test_server_outside".to_string(), "/x/.hidden".to_string()] {
+            assert_eq!(400, http(&mut stream, &mut reader, "PUT", &target, "owned").0, "{}", target);
+        }
+        assert!(!outside.exists());
+
+        // Tables are resources too, and their items are isolated from the root keyspace.
+        assert_eq!(201, http(&mut stream, &mut reader, "PUT", "/_tables/events", "").0);
+        assert_eq!(409, http(&mut stream, &mut reader, "PUT", "/_tables/events", "").0);
+        assert_eq!(200, http(&mut stream, &mut reader, "PUT", "/_tables/events/users/user%201", "click").0);
+        let (_, body) = http(&mut stream, &mut reader, "GET", "/_tables/events/users/user%201", "");
+        assert_eq!("click", serde_json::from_str::<Data>(&body).unwrap().value);
+        let (_, body) = http(&mut stream, &mut reader, "GET", "/_tables", "");
+        assert_eq!(vec!["events"], serde_json::from_str::<Vec<String>>(&body).unwrap());
+        assert_eq!(204, http(&mut stream, &mut reader, "DELETE", "/_tables/events", "").0);
+        assert_eq!(404, http(&mut stream, &mut reader, "GET", "/_tables/events/users/user%201", "").0);
+
+        let schema = r#"{"partition_key": "id", "sort_key": "$", "indexes": [{"name": "by_user", "attribute": "user"}]}"#;
+        assert_eq!(201, http(&mut stream, &mut reader, "PUT", "/_tables/sessions", schema).0);
+        assert_eq!(200, http(&mut stream, &mut reader, "PUT", "/_tables/sessions/s1/$", r#"{"user": "ada"}"#).0);
+        assert_eq!(200, http(&mut stream, &mut reader, "PUT", "/_tables/sessions/s2/$", r#"{"user": "alan"}"#).0);
+        let (status, body) = http(&mut stream, &mut reader, "GET", "/_tables/sessions/_indexes/by_user/ada", "");
+        assert_eq!(200, status);
+        let items = serde_json::from_str::<Vec<Data>>(&body).unwrap();
+        assert_eq!(vec!["s1"], items.iter().map(|data| data.partition_key.as_str()).collect::<Vec<_>>());
+        assert_eq!(404, http(&mut stream, &mut reader, "GET", "/_tables/sessions/_indexes/by_id/s1", "").0);
+
+        // Requests are read within fixed limits: an overlong line, too many headers
+        // or a body shorter than announced is answered with 400.
+        for request in [
+            format!("GET /{}", "x".repeat(16 * 1024 - 5)),
+            format!("GET /users HTTP/1.1\r\n{}", "X-Header: 1\r\n".repeat(101)),
+            "PUT /users/a HTTP/1.1\r\nContent-Length: 60000000\r\n\r\nshort".to_string(),
+        ] {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(request.as_bytes()).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            assert_eq!(400, read_http_response(&mut reader).0);
+        }
+
+        shutdown.shutdown();
+        handle.join().unwrap();
+
+        teardown(path);
+    }
+
+    fn dynamo(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, operation: &str, body: serde_json::Value) -> (u16, serde_json::Value) {
+        let body = body.to_string();
+        write!(stream, "POST / HTTP/1.1\r\nX-Amz-Target: DynamoDB_20120810.{}\r\nContent-Length: {}\r\n\r\n{}", operation, body.len(), body).unwrap();
+        let (status, body) = read_http_response(reader);
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[test]
+    fn test_dynamo_api() {
+        let path = setup("./test_server_db3");
+        let database = Arc::new(Mutex::new(Database::new(path.clone())));
+        let server = DynamoServer::bind("127.0.0.1:0", database, TableHandles::new()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.run().unwrap());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let (status, _) = dynamo(&mut stream, &mut reader, "CreateTable", json!({
+            "TableName": "Orders",
+            "KeySchema": [{"AttributeName": "customer", "KeyType": "HASH"}, {"AttributeName": "order", "KeyType": "RANGE"}],
+            "AttributeDefinitions": [{"AttributeName": "customer", "AttributeType": "S"}, {"AttributeName": "order", "AttributeType": "S"}],
+        }));
+        assert_eq!(200, status);
+
+        let item = json!({"customer": {"S": "c1"}, "order": {"S": "2023-01"}, "total": {"N": "12"}, "tags": {"SS": ["a", "b"]}});
+        assert_eq!(200, dynamo(&mut stream, &mut reader, "PutItem", json!({"TableName": "Orders", "Item": item})).0);
+
+        let (_, body) = dynamo(&mut stream, &mut reader, "GetItem", json!({"TableName": "Orders", "Key": {"customer": {"S": "c1"}, "order": {"S": "2023-01"}}}));
+        assert_eq!(item, body["Item"]);
+        let (_, body) = dynamo(&mut stream, &mut reader, "GetItem", json!({"TableName": "Orders", "Key": {"customer": {"S": "c1"}, "order": {"S": "nope"}}}));
+        assert_eq!(json!({}), body);
+
+        let (status, _) = dynamo(&mut stream, &mut reader, "BatchWriteItem", json!({"RequestItems": {"Orders": [
+            {"PutRequest": {"Item": {"customer": {"S": "c1"}, "order": {"S": "2023-02"}}}},
+            {"PutRequest": {"Item": {"customer": {"S": "c1"}, "order": {"S": "2024-01"}}}},
+            {"PutRequest": {"Item": {"customer": {"S": "c2"}, "order": {"S": "2023-01"}}}},
+            {"DeleteRequest": {"Key": {"customer": {"S": "c9"}, "order": {"S": "missing"}}}},
+        ]}}));
+        assert_eq!(200, status);
+
+        let (_, body) = dynamo(&mut stream, &mut reader, "Query", json!({
+            "TableName": "Orders",
+            "KeyConditionExpression": "#c = :c AND begins_with(#o, :year)",
+            "ExpressionAttributeNames": {"#c": "customer", "#o": "order"},
+            "ExpressionAttributeValues": {":c": {"S": "c1"}, ":year": {"S": "2023"}},
+            "ScanIndexForward": false,
+        }));
+        assert_eq!(2, body["Count"]);
+        assert_eq!(json!({"S": "2023-02"}), body["Items"][0]["order"]);
+
+        let (_, body) = dynamo(&mut stream, &mut reader, "Query", json!({
+            "TableName": "Orders",
+            "KeyConditionExpression": "customer = :c AND order BETWEEN :from AND :to",
+            "ExpressionAttributeValues": {":c": {"S": "c1"}, ":from": {"S": "2023-02"}, ":to": {"S": "2024-12"}},
+            "Limit": 1,
+        }));
+        assert_eq!(json!({"S": "2023-02"}), body["Items"][0]["order"]);
+        assert_eq!(1, body["Count"]);
+
+        let (_, body) = dynamo(&mut stream, &mut reader, "BatchGetItem", json!({"RequestItems": {"Orders": {"Keys": [
+            {"customer": {"S": "c2"}, "order": {"S": "2023-01"}},
+            {"customer": {"S": "c3"}, "order": {"S": "2023-01"}},
+        ]}}}));
+        assert_eq!(1, body["Responses"]["Orders"].as_array().unwrap().len());
+
+        let segmented: u64 = (0..2).map(|segment| {
+            let (_, body) = dynamo(&mut stream, &mut reader, "Scan", json!({"TableName": "Orders", "Segment": segment, "TotalSegments": 2}));
+            body["Count"].as_u64().unwrap()
+        }).sum();
+        assert_eq!(4, segmented);
+
+        assert_eq!(200, dynamo(&mut stream, &mut reader, "DeleteItem", json!({"TableName": "Orders", "Key": {"customer": {"S": "c2"}, "order": {"S": "2023-01"}}})).0);
+        let (_, body) = dynamo(&mut stream, &mut reader, "Scan", json!({"TableName": "Orders"}));
+        assert_eq!(3, body["Count"]);
+
+        let (status, body) = dynamo(&mut stream, &mut reader, "GetItem", json!({"TableName": "Missing", "Key": {}}));
+        assert_eq!(400, status);
+        assert!(body["__type"].as_str().unwrap().ends_with("ResourceNotFoundException"));
+        let (status, body) = dynamo(&mut stream, &mut reader, "PutItem", json!({"TableName": "Orders", "Item": item, "ConditionExpression": "attribute_not_exists(customer)"}));
+        assert_eq!(400, status);
+        assert!(body["__type"].as_str().unwrap().ends_with("ValidationException"));
+
+        // Key attributes can't name files outside the table.
+        let outside = fs::canonicalize(".").unwrap().join("test_server_outside_dynamo");
+        for key in ["..", outside.to_str().unwrap()] {
+            let item = json!({"customer": {"S": key}, "order": {"S": key}});
+            let (status, body) = dynamo(&mut stream, &mut reader, "PutItem", json!({"TableName": "Orders", "Item": item}));
+            assert_eq!(400, status);
+            assert!(body["__type"].as_str().unwrap().ends_with("ValidationException"));
+        }
+        assert!(!outside.exists());
+
+        // Global secondary indexes and the time to live map to the table's settings.
+        let (status, body) = dynamo(&mut stream, &mut reader, "CreateTable", json!({
+            "TableName": "Sessions",
+            "KeySchema": [{"AttributeName": "id", "KeyType": "HASH"}],
+            "GlobalSecondaryIndexes": [{"IndexName": "by_user", "KeySchema": [{"AttributeName": "user", "KeyType": "HASH"}], "Projection": {"ProjectionType": "ALL"}}],
+        }));
+        assert_eq!(200, status);
+        assert_eq!("by_user", body["TableDescription"]["GlobalSecondaryIndexes"][0]["IndexName"]);
+        for (id, user, expires) in [("s1", "ada", "4102444800"), ("s2", "ada", "1"), ("s3", "alan", "4102444800")] {
+            let item = json!({"id": {"S": id}, "user": {"S": user}, "expires": {"N": expires}});
+            assert_eq!(200, dynamo(&mut stream, &mut reader, "PutItem", json!({"TableName": "Sessions", "Item": item})).0);
+        }
+        let by_user = json!({
+            "TableName": "Sessions",
+            "IndexName": "by_user",
+            "KeyConditionExpression": "#u = :u",
+            "ExpressionAttributeNames": {"#u": "user"},
+            "ExpressionAttributeValues": {":u": {"S": "ada"}},
+        });
+        let (_, body) = dynamo(&mut stream, &mut reader, "Query", by_user.clone());
+        assert_eq!(2, body["Count"]);
+
+        let (_, body) = dynamo(&mut stream, &mut reader, "DescribeTimeToLive", json!({"TableName": "Sessions"}));
+        assert_eq!("DISABLED", body["TimeToLiveDescription"]["TimeToLiveStatus"]);
+        let specification = json!({"AttributeName": "expires", "Enabled": true});
+        let (status, _) = dynamo(&mut stream, &mut reader, "UpdateTimeToLive", json!({"TableName": "Sessions", "TimeToLiveSpecification": specification}));
+        assert_eq!(200, status);
+        let (_, body) = dynamo(&mut stream, &mut reader, "DescribeTimeToLive", json!({"TableName": "Sessions"}));
+        assert_eq!(json!({"TimeToLiveStatus": "ENABLED", "AttributeName": "expires"}), body["TimeToLiveDescription"]);
+        let (_, body) = dynamo(&mut stream, &mut reader, "Query", by_user);
+        assert_eq!(1, body["Count"]);
+        assert_eq!(json!({"S": "s1"}), body["Items"][0]["id"]);
+        let (_, body) = dynamo(&mut stream, &mut reader, "Scan", json!({"TableName": "Sessions"}));
+        assert_eq!(2, body["Count"]);
+
+        let (status, _) = dynamo(&mut stream, &mut reader, "Query", json!({
+            "TableName": "Sessions",
+            "IndexName": "by_id",
+            "KeyConditionExpression": "id = :id",
+            "ExpressionAttributeValues": {":id": {"S": "s1"}},
+        }));
+        assert_eq!(400, status);
+
+        shutdown.shutdown();
+        handle.join().unwrap();
+
+        teardown(path);
+    }
+
+    fn resp(stream: &mut TcpStream, reader: &mut BufReader<TcpStream>, command: &[&str]) -> String {
+        let mut request = format!("*{}\r\n", command.len());
+        for argument in command {
+            request.push_str(&format!("${}\r\n{}\r\n", argument.len(), argument));
+        }
+        stream.write_all(request.as_bytes()).unwrap();
+        read_resp_reply(reader)
+    }
+
+    /// Reads one reply and renders it on a single line for easy comparison.
+    fn read_resp_reply(reader: &mut BufReader<TcpStream>) -> String {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end().to_string();
+        match line.as_bytes()[0] {
+            b'$' if line == "$-1" => "(nil)".to_string(),
+            b'$' => {
+                let mut value = vec![0; line[1..].parse::<usize>().unwrap() + 2];
+                reader.read_exact(&mut value).unwrap();
+                String::from_utf8(value).unwrap().trim_end().to_string()
+            },
+            b'*' => {
+                let items: Vec<String> = (0..line[1..].parse::<usize>().unwrap()).map(|_| read_resp_reply(reader)).collect();
+                format!("[{}]", items.join(" "))
+            },
+            _ => line,
+        }
+    }
+
+    #[test]
+    fn test_resp_api() {
+        let path = setup("./test_server_db4");
+        let database = Arc::new(Mutex::new(Database::new(path.clone())));
+        let server = RespServer::bind("127.0.0.1:0", database).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let handle = thread::spawn(move || server.run().unwrap());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        assert_eq!("+PONG", resp(&mut stream, &mut reader, &["PING"]));
+        assert_eq!("+OK", resp(&mut stream, &mut reader, &["SET", "greeting", "hello world"]));
+        assert_eq!("hello world", resp(&mut stream, &mut reader, &["GET", "greeting"]));
+        assert_eq!("(nil)", resp(&mut stream, &mut reader, &["GET", "missing"]));
+
+        assert_eq!(":2", resp(&mut stream, &mut reader, &["HSET", "user:1", "name", "ada", "lang", "rust"]));
+        assert_eq!(":0", resp(&mut stream, &mut reader, &["HSET", "user:1", "name", "grace"]));
+        assert_eq!("grace", resp(&mut stream, &mut reader, &["HGET", "user:1", "name"]));
+        assert_eq!("[lang rust name grace]", resp(&mut stream, &mut reader, &["HGETALL", "user:1"]));
+        assert_eq!(":1", resp(&mut stream, &mut reader, &["HDEL", "user:1", "lang", "nope"]));
+        assert_eq!(":1", resp(&mut stream, &mut reader, &["HSET", "user:2", "name", "alan"]));
+
+        assert_eq!(":2", resp(&mut stream, &mut reader, &["EXISTS", "greeting", "user:1", "missing"]));
+        assert_eq!("[0 [user:1 user:2]]", resp(&mut stream, &mut reader, &["SCAN", "0", "MATCH", "user:*"]));
+        assert_eq!("[2 [greeting user:1]]", resp(&mut stream, &mut reader, &["SCAN", "0", "COUNT", "2"]));
+        assert_eq!(":2", resp(&mut stream, &mut reader, &["DEL", "greeting", "user:1", "missing"]));
+        assert_eq!(":0", resp(&mut stream, &mut reader, &["EXISTS", "greeting"]));
+
+        // Patterns match in linear time, even ones that backtrack a lot.
+        let long_key = "a".repeat(40);
+        assert_eq!("+OK", resp(&mut stream, &mut reader, &["SET", &long_key, "x"]));
+        assert_eq!("[0 []]", resp(&mut stream, &mut reader, &["SCAN", "0", "MATCH", &format!("{}b", "*a".repeat(15))]));
+        assert_eq!(format!("[0 [{}]]", long_key), resp(&mut stream, &mut reader, &["SCAN", "0", "MATCH", "*a*\\a?"]));
+        assert_eq!("[0 [user:1 user:2]]", resp(&mut stream, &mut reader, &["SCAN", "0", "MATCH", "us?r\\:*"]));
+        assert_eq!(":1", resp(&mut stream, &mut reader, &["DEL", &long_key]));
+
+        assert!(resp(&mut stream, &mut reader, &["HGET", "user:2"]).starts_with("-ERR wrong number of arguments"));
+        assert!(resp(&mut stream, &mut reader, &["FLUSHALL"]).starts_with("-ERR unknown command"));
+
+        // Inline commands, pipelined.
+        stream.write_all(b"PING\r\nGET user:2\r\nHGET user:2 name\r\n").unwrap();
+        assert_eq!("+PONG", read_resp_reply(&mut reader));
+        assert_eq!("(nil)", read_resp_reply(&mut reader));
+        assert_eq!("alan", read_resp_reply(&mut reader));
+
+        assert_eq!("+OK", resp(&mut stream, &mut reader, &["QUIT"]));
+
+        // Oversized headers are refused without allocating for them.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(b"*10000000000\r\n").unwrap();
+        assert!(read_resp_reply(&mut reader).starts_with("-ERR Protocol error"));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        stream.write_all(&vec![b'x'; 64 * 1024]).unwrap();
+        assert!(read_resp_reply(&mut reader).starts_with("-ERR Protocol error"));
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"*1\r\n$536870912\r\nshort").unwrap();
+        drop(stream);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        assert_eq!("alan", resp(&mut stream, &mut reader, &["HGET", "user:2", "name"]));
+
+        shutdown.shutdown();
+        handle.join().unwrap();
+
+        teardown(path);
+    }
+
+    #[test]
+    fn test_shared_table_handles() {
+        let path = setup("./test_server_db5");
+        let database = Arc::new(Mutex::new(Database::with_group_commit(path.clone(), GroupCommitConfig::default())));
+        let tables = TableHandles::new();
+        let rest = RestServer::bind("127.0.0.1:0", Arc::clone(&database), Arc::clone(&tables)).unwrap();
+        let dynamo_server = DynamoServer::bind("127.0.0.1:0", database, Arc::clone(&tables)).unwrap();
+        let (rest_addr, dynamo_addr) = (rest.local_addr().unwrap(), dynamo_server.local_addr().unwrap());
+        let shutdowns = [rest.shutdown_handle(), dynamo_server.shutdown_handle()];
+        let handles = [thread::spawn(move || rest.run().unwrap()), thread::spawn(move || dynamo_server.run().unwrap())];
+
+        let connect = |addr| {
+            let stream = TcpStream::connect(addr).unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            (stream, reader)
+        };
+        let (mut stream, mut reader) = connect(rest_addr);
+        assert_eq!(201, http(&mut stream, &mut reader, "PUT", "/_tables/events", "").0);
+
+        // Both front-ends write to the table at once, through the same handle.
+        let writers: Vec<_> = (0..8).map(|writer| thread::spawn(move || {
+            let (mut stream, mut reader) = connect(if writer % 2 == 0 { rest_addr } else { dynamo_addr });
+            for i in 0..25 {
+                let sort_key = format!("{}-{}", writer, i);
+                let status = match writer % 2 {
+                    0 => http(&mut stream, &mut reader, "PUT", &format!("/_tables/events/p/{}", sort_key), "v").0,
+                    _ => {
+                        let item = json!({"partition_key": {"S": "p"}, "sort_key": {"S": sort_key}});
+                        dynamo(&mut stream, &mut reader, "PutItem", json!({"TableName": "events", "Item": item})).0
+                    },
+                };
+                assert_eq!(200, status);
+            }
+        })).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let (mut dynamo_stream, mut dynamo_reader) = connect(dynamo_addr);
+        let (_, body) = dynamo(&mut dynamo_stream, &mut dynamo_reader, "Scan", json!({"TableName": "events"}));
+        assert_eq!(200, body["Count"]);
+
+        // Dropping the table closes its handle, and a new table of the same name starts empty.
+        assert_eq!(200, dynamo(&mut dynamo_stream, &mut dynamo_reader, "DeleteTable", json!({"TableName": "events"})).0);
+        assert_eq!(404, http(&mut stream, &mut reader, "GET", "/_tables/events/p/0-0", "").0);
+        assert_eq!(201, http(&mut stream, &mut reader, "PUT", "/_tables/events", "").0);
+        assert_eq!(404, http(&mut stream, &mut reader, "GET", "/_tables/events/p/0-0", "").0);
+        assert_eq!(200, http(&mut stream, &mut reader, "PUT", "/_tables/events/p/new", "v").0);
+        let (_, body) = dynamo(&mut dynamo_stream, &mut dynamo_reader, "Scan", json!({"TableName": "events"}));
+        assert_eq!(1, body["Count"]);
+
+        for shutdown in shutdowns {
+            shutdown.shutdown();
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        teardown(path);
+    }
+}